@@ -0,0 +1,124 @@
+//! Compares `GridMap` with the `HashMap` keyed by layer and cell it replaced, on a 500×500 map.
+
+use bevy::{prelude::*, utils::HashMap};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use spectrum::{
+    grid::{GridBounds, GridLayer, GridMap},
+    GridPosition,
+};
+
+const SIZE: i32 = 500;
+
+/// The grid as it was stored before chunks: one entry per occupied cell.
+#[derive(Default)]
+struct HashGrid {
+    map: HashMap<(GridLayer, GridPosition), Entity>,
+}
+
+impl HashGrid {
+    fn get(&self, layer: GridLayer, position: GridPosition) -> Option<&Entity> {
+        self.map.get(&(layer, position))
+    }
+
+    fn set(&mut self, layer: GridLayer, position: GridPosition, value: Entity) -> Result<(), ()> {
+        if self.map.contains_key(&(layer, position)) {
+            return Err(());
+        }
+        self.map.insert((layer, position), value);
+        Ok(())
+    }
+
+    /// Without chunks, every entry has to be looked at.
+    fn iter_region(
+        &self,
+        layer: GridLayer,
+        region: GridBounds,
+    ) -> impl Iterator<Item = (GridPosition, Entity)> + '_ {
+        self.map
+            .iter()
+            .filter(move |((cell_layer, position), _)| {
+                *cell_layer == layer && region.contains(*position)
+            })
+            .map(|((_, position), entity)| (*position, *entity))
+    }
+}
+
+fn cells() -> impl Iterator<Item = GridPosition> {
+    // the cells of `GridBounds::centred(SIZE, SIZE)`
+    let range = -SIZE / 2..SIZE - SIZE / 2;
+    range
+        .clone()
+        .flat_map(move |y| range.clone().map(move |x| GridPosition::new(x, y)))
+}
+
+fn filled_grid_map() -> GridMap {
+    let mut grid = GridMap::new(GridBounds::centred(SIZE, SIZE));
+    for (index, cell) in cells().enumerate() {
+        grid.set(GridLayer::Build, cell, Entity::from_raw(index as u32))
+            .unwrap();
+    }
+    grid
+}
+
+fn filled_hash_grid() -> HashGrid {
+    let mut grid = HashGrid::default();
+    for (index, cell) in cells().enumerate() {
+        grid.set(GridLayer::Build, cell, Entity::from_raw(index as u32))
+            .unwrap();
+    }
+    grid
+}
+
+fn fill(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fill 500x500");
+    group.sample_size(20);
+    group.bench_function("GridMap", |b| b.iter(filled_grid_map));
+    group.bench_function("HashMap", |b| b.iter(filled_hash_grid));
+    group.finish();
+}
+
+fn lookup(c: &mut Criterion) {
+    let grid_map = filled_grid_map();
+    let hash_grid = filled_hash_grid();
+    let mut group = c.benchmark_group("get every cell of 500x500");
+    group.bench_function("GridMap", |b| {
+        b.iter(|| {
+            cells()
+                .filter(|&cell| grid_map.get(GridLayer::Build, black_box(cell)).is_some())
+                .count()
+        })
+    });
+    group.bench_function("HashMap", |b| {
+        b.iter(|| {
+            cells()
+                .filter(|&cell| hash_grid.get(GridLayer::Build, black_box(cell)).is_some())
+                .count()
+        })
+    });
+    group.finish();
+}
+
+fn region(c: &mut Criterion) {
+    let grid_map = filled_grid_map();
+    let hash_grid = filled_hash_grid();
+    let region = GridBounds::new(GridPosition::new(-20, -20), GridPosition::new(43, 43));
+    let mut group = c.benchmark_group("64x64 region of 500x500");
+    group.bench_function("GridMap", |b| {
+        b.iter(|| {
+            grid_map
+                .iter_region(GridLayer::Build, black_box(region))
+                .count()
+        })
+    });
+    group.bench_function("HashMap", |b| {
+        b.iter(|| {
+            hash_grid
+                .iter_region(GridLayer::Build, black_box(region))
+                .count()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, fill, lookup, region);
+criterion_main!(benches);
@@ -0,0 +1,472 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    building::{find_conflicts, BuildChecker, BuildRules, BuildingRegistry, Placeable},
+    controls::{Action, Actions},
+    grid::{GridMap, GridSettings, GridShape},
+    history::{BuildAction, BuildCommand, PlacedBuilding},
+    hotbar::{Hotbar, StatusMessage},
+    laser::mirrored_orientation,
+    outline_cells, ColorWell, Game, GridPosition, MouseGridPosition, Orientation, Selected,
+    GHOST_INVALID, GHOST_VALID,
+};
+
+/// Where `Export` writes the copied blueprint and `Import` reads one from, either as RON or as a
+/// base64 string.
+#[derive(Resource, Debug)]
+pub struct BlueprintSettings {
+    pub export: PathBuf,
+    pub import: PathBuf,
+}
+
+impl Default for BlueprintSettings {
+    fn default() -> Self {
+        Self {
+            export: PathBuf::from("blueprints/export.ron"),
+            import: PathBuf::from("blueprints/import.txt"),
+        }
+    }
+}
+
+/// A layout of buildings relative to its bottom-left pivot.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct Blueprint {
+    pub buildings: Vec<BlueprintBuilding>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct BlueprintBuilding {
+    pub placeable: Placeable,
+    pub offset: IVec2,
+    #[serde(default)]
+    pub orientation: Orientation,
+}
+
+#[derive(Error, Debug)]
+pub enum BlueprintError {
+    #[error("could not access blueprint file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse blueprint: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("could not write blueprint: {0}")]
+    Serialize(#[from] ron::Error),
+}
+
+impl Blueprint {
+    /// Captures `buildings`, keeping their positions relative to each other.
+    pub fn capture(buildings: impl IntoIterator<Item = PlacedBuilding>) -> Self {
+        let buildings: Vec<PlacedBuilding> = buildings.into_iter().collect();
+        let min_x = buildings.iter().map(|building| building.pivot.x).min();
+        let min_y = buildings.iter().map(|building| building.pivot.y).min();
+        let (Some(min_x), Some(min_y)) = (min_x, min_y) else {
+            return Self::default();
+        };
+
+        let mut buildings: Vec<BlueprintBuilding> = buildings
+            .into_iter()
+            .map(|building| BlueprintBuilding {
+                placeable: building.placeable,
+                offset: IVec2::new(building.pivot.x - min_x, building.pivot.y - min_y),
+                orientation: building.orientation,
+            })
+            .collect();
+        buildings.sort_by_key(|building| (building.offset.y, building.offset.x));
+        Self { buildings }
+    }
+
+    /// The buildings with the blueprint's origin at `anchor`, mirrored first if asked to and then
+    /// turned by `rotation`.
+    pub fn place_at(
+        &self,
+        anchor: GridPosition,
+        rotation: Orientation,
+        mirrored: bool,
+        shape: GridShape,
+        registry: &BuildingRegistry,
+    ) -> Vec<PlacedBuilding> {
+        self.buildings
+            .iter()
+            .map(|building| {
+                let (offset, orientation) = if mirrored {
+                    let kind = registry
+                        .get(&building.placeable)
+                        .and_then(|definition| definition.intersector);
+                    (
+                        shape.mirror(building.offset),
+                        mirrored_orientation(kind, building.orientation, shape),
+                    )
+                } else {
+                    (building.offset, building.orientation)
+                };
+                PlacedBuilding {
+                    placeable: building.placeable.clone(),
+                    pivot: anchor.offset(rotation.rotate(offset, shape)),
+                    orientation: orientation.turned(rotation.steps() as i32, shape),
+                }
+            })
+            .collect()
+    }
+
+    pub fn to_ron(&self) -> Result<String, BlueprintError> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    /// Single-line form for sharing in chat: compact RON, base64 encoded.
+    pub fn to_base64(&self) -> Result<String, BlueprintError> {
+        Ok(STANDARD.encode(ron::ser::to_string(self)?))
+    }
+
+    /// Reads either form written by `to_ron` and `to_base64`.
+    pub fn parse(text: &str) -> Result<Self, BlueprintError> {
+        let text = text.trim();
+        let decoded = STANDARD
+            .decode(text)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok());
+        Ok(ron::from_str(decoded.as_deref().unwrap_or(text))?)
+    }
+}
+
+/// The copied blueprint, and how it is being pasted if it is.
+#[derive(Resource, Default)]
+pub struct Clipboard {
+    blueprint: Option<Blueprint>,
+    pasting: bool,
+    mirrored: bool,
+}
+
+impl Clipboard {
+    pub fn pasting(&self) -> bool {
+        self.pasting
+    }
+
+    fn start_pasting(&mut self, blueprint: Blueprint) {
+        self.blueprint = Some(blueprint);
+        self.pasting = true;
+        self.mirrored = false;
+    }
+
+    /// Where the blueprint would land with its origin at `anchor`, turned by `rotation`.
+    fn preview(
+        &self,
+        anchor: GridPosition,
+        rotation: Orientation,
+        shape: GridShape,
+        registry: &BuildingRegistry,
+    ) -> Option<Vec<PlacedBuilding>> {
+        let blueprint = self.blueprint.as_ref().filter(|_| self.pasting)?;
+        Some(blueprint.place_at(anchor, rotation, self.mirrored, shape, registry))
+    }
+}
+
+/// `Copy` copies the selection, `Paste` starts pasting it. `Export` and `Import` do the same
+/// through the files in `BlueprintSettings`. While pasting, the placement rotation turns the
+/// blueprint, `Mirror` mirrors it and `Cancel` stops.
+pub fn blueprint_input(
+    actions: Res<Actions>,
+    settings: Res<BlueprintSettings>,
+    mut status: EventWriter<StatusMessage>,
+    mut clipboard: ResMut<Clipboard>,
+    mut game: ResMut<Game>,
+    selected: Query<(&Placeable, &GridPosition, &Orientation), With<Selected>>,
+) {
+    let export_too = actions.just_pressed(Action::Export);
+    if actions.just_pressed(Action::Copy) || export_too {
+        let blueprint =
+            Blueprint::capture(selected.iter().map(|(placeable, pivot, orientation)| {
+                PlacedBuilding {
+                    placeable: placeable.clone(),
+                    pivot: *pivot,
+                    orientation: *orientation,
+                }
+            }));
+        if blueprint.buildings.is_empty() {
+            return;
+        }
+        let message = if export_too {
+            match export(&blueprint, &settings.export) {
+                Ok(code) => {
+                    info!("Blueprint code: {}", code);
+                    format!(
+                        "Copied {} buildings and exported them to {}, code: {}",
+                        blueprint.buildings.len(),
+                        settings.export.display(),
+                        code
+                    )
+                }
+                Err(error) => format!("Can't export blueprint: {}", error),
+            }
+        } else {
+            format!("Copied {} buildings", blueprint.buildings.len())
+        };
+        status.send(StatusMessage(message));
+        clipboard.blueprint = Some(blueprint);
+    }
+
+    let import = actions.just_pressed(Action::Import);
+    if actions.just_pressed(Action::Paste) || import {
+        let blueprint = if import {
+            match fs::read_to_string(&settings.import)
+                .map_err(BlueprintError::from)
+                .and_then(|text| Blueprint::parse(&text))
+            {
+                Ok(blueprint) => Some(blueprint),
+                Err(error) => {
+                    status.send(StatusMessage(format!(
+                        "Can't import blueprint from {}: {}",
+                        settings.import.display(),
+                        error
+                    )));
+                    None
+                }
+            }
+        } else {
+            clipboard.blueprint.clone()
+        };
+        if let Some(blueprint) = blueprint {
+            if import {
+                status.send(StatusMessage(format!(
+                    "Imported {} buildings from {}",
+                    blueprint.buildings.len(),
+                    settings.import.display()
+                )));
+            }
+            clipboard.start_pasting(blueprint);
+            game.current_placeable = None;
+        }
+    }
+
+    if !clipboard.pasting {
+        return;
+    }
+    if actions.just_pressed(Action::Mirror) {
+        clipboard.mirrored = !clipboard.mirrored;
+    }
+    if actions.just_pressed(Action::Cancel) || game.current_placeable.is_some() {
+        clipboard.pasting = false;
+    }
+}
+
+/// Writes the RON file and returns the base64 form.
+fn export(blueprint: &Blueprint, path: &Path) -> Result<String, BlueprintError> {
+    if let Some(folder) = path.parent() {
+        fs::create_dir_all(folder)?;
+    }
+    fs::write(path, blueprint.to_ron()?)?;
+    blueprint.to_base64()
+}
+
+/// `Place` stamps the blueprint at the cursor, but only if every building fits.
+pub fn paste_blueprint(
+    checker: BuildChecker,
+    mut build_commands: EventWriter<BuildCommand>,
+    mut status: EventWriter<StatusMessage>,
+    clipboard: Res<Clipboard>,
+    game: Res<Game>,
+    settings: Res<GridSettings>,
+    registry: Res<BuildingRegistry>,
+    mouse_grid_pos: Res<MouseGridPosition>,
+    actions: Res<Actions>,
+    hotbar: Res<Hotbar>,
+) {
+    if !actions.just_pressed(Action::Place) || hotbar.hovered() {
+        return;
+    }
+    let Some(buildings) = clipboard.preview(
+        mouse_grid_pos.0,
+        game.orientation,
+        settings.shape,
+        &registry,
+    ) else {
+        return;
+    };
+
+    let conflicts = checker.conflicts(&buildings);
+    if !conflicts.is_empty() {
+        for (building, error) in &conflicts {
+            info!(
+                "Can't paste {:?} at {:?}: {}",
+                building.placeable, building.pivot, error
+            );
+        }
+        let (_, first) = &conflicts[0];
+        status.send(StatusMessage(format!(
+            "Can't paste here, {} buildings don't fit: {}",
+            conflicts.len(),
+            first
+        )));
+        return;
+    }
+    build_commands.send(BuildCommand::Do(BuildAction::Place(buildings)));
+}
+
+/// Outlines every building of the blueprint being pasted, red where it would not fit.
+pub fn draw_paste_preview(
+    mut gizmos: Gizmos,
+    clipboard: Res<Clipboard>,
+    game: Res<Game>,
+    grid_map: Res<GridMap>,
+    settings: Res<GridSettings>,
+    registry: Res<BuildingRegistry>,
+    mouse_grid_pos: Res<MouseGridPosition>,
+    color_wells: Query<(), With<ColorWell>>,
+) {
+    let Some(buildings) = clipboard.preview(
+        mouse_grid_pos.0,
+        game.orientation,
+        settings.shape,
+        &registry,
+    ) else {
+        return;
+    };
+
+    let conflicts = find_conflicts(&grid_map, &registry, settings.shape, &buildings, |ground| {
+        color_wells.contains(ground)
+    });
+    for building in &buildings {
+        let Some(definition) = registry.get(&building.placeable) else {
+            continue;
+        };
+        let cells: Vec<GridPosition> = definition
+            .footprint
+            .cells(building.pivot, building.orientation, settings.shape)
+            .collect();
+        let valid = !conflicts
+            .iter()
+            .any(|(conflict, _)| std::ptr::eq(*conflict, building));
+        outline_cells(
+            &mut gizmos,
+            &settings,
+            cells.iter().copied(),
+            |cell| cells.contains(&cell),
+            if valid { GHOST_VALID } else { GHOST_INVALID }.with_a(0.9),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn building(placeable: &str, x: i32, y: i32, orientation: u8) -> BlueprintBuilding {
+        BlueprintBuilding {
+            placeable: Placeable(placeable.to_string()),
+            offset: IVec2::new(x, y),
+            orientation: Orientation(orientation),
+        }
+    }
+
+    fn layout() -> Blueprint {
+        Blueprint {
+            buildings: vec![
+                building("collector", 0, 0, 1),
+                building("mirror", 0, 3, 0),
+                building("wall", 2, 3, 1),
+                building("storage", 4, 1, 2),
+            ],
+        }
+    }
+
+    /// `blueprint` placed at the origin, read back as a blueprint.
+    fn placed(
+        blueprint: &Blueprint,
+        rotation: u8,
+        mirrored: bool,
+        shape: GridShape,
+        registry: &BuildingRegistry,
+    ) -> Blueprint {
+        let origin = GridPosition::default();
+        Blueprint {
+            buildings: blueprint
+                .place_at(origin, Orientation(rotation), mirrored, shape, registry)
+                .into_iter()
+                .map(|building| BlueprintBuilding {
+                    placeable: building.placeable,
+                    offset: IVec2::new(building.pivot.x, building.pivot.y),
+                    orientation: building.orientation,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn both_forms_parse_back() {
+        let blueprint = layout();
+        let ron = blueprint.to_ron().unwrap();
+        assert_eq!(Blueprint::parse(&ron).unwrap(), blueprint);
+        let code = blueprint.to_base64().unwrap();
+        assert!(!code.contains('\n'));
+        assert_eq!(
+            Blueprint::parse(&format!("  {}\n", code)).unwrap(),
+            blueprint
+        );
+    }
+
+    #[test]
+    fn garbage_is_refused() {
+        for text in ["not a blueprint", "aGVsbG8=", ""] {
+            assert!(
+                matches!(Blueprint::parse(text), Err(BlueprintError::Ron(_))),
+                "{:?} parsed",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn full_turns_and_double_mirrors_change_nothing() {
+        let registry = BuildingRegistry::from_assets();
+        for shape in [GridShape::Square, GridShape::Hexagonal] {
+            let original = layout();
+            let mut turned = original.clone();
+            for turn in 0..shape.direction_count() {
+                turned = placed(&turned, 1, false, shape, &registry);
+                assert_eq!(turned == original, turn + 1 == shape.direction_count());
+            }
+
+            let mirrored = placed(&original, 0, true, shape, &registry);
+            assert_ne!(mirrored, original);
+            assert_eq!(placed(&mirrored, 0, true, shape, &registry), original);
+        }
+    }
+
+    #[test]
+    fn hex_blueprints_turn_and_mirror_along_hex_axes() {
+        let registry = BuildingRegistry::from_assets();
+        let shape = GridShape::Hexagonal;
+        let blueprint = Blueprint {
+            buildings: vec![building("wall", 1, 0, 1), building("mirror", 0, 1, 0)],
+        };
+
+        let turned = placed(&blueprint, 1, false, shape, &registry);
+        assert_eq!(
+            turned.buildings,
+            [building("wall", 0, 1, 2), building("mirror", -1, 1, 1)]
+        );
+
+        // the mirror axis runs along direction 1, so (0, 1) stays put and (1, 0) flips over it
+        let mirrored = placed(&blueprint, 0, true, shape, &registry);
+        assert_eq!(
+            mirrored.buildings,
+            [building("wall", -1, 1, 5), building("mirror", 0, 1, 1)]
+        );
+
+        // mirrored first, then turned
+        let both = placed(&blueprint, 1, true, shape, &registry);
+        assert_eq!(
+            both.buildings,
+            [building("wall", -1, 0, 0), building("mirror", -1, 1, 2)]
+        );
+    }
+}
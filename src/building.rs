@@ -0,0 +1,1484 @@
+use std::collections::BTreeMap;
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext, LoadedFolder},
+    ecs::system::{EntityCommands, SystemParam},
+    prelude::*,
+    utils::{BoxedFuture, HashMap},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    construction::{
+        with_site_pulse, ConstructionQueue, ConstructionSettings, ConstructionSite, SITE_DEPTH,
+    },
+    economy::{Collector, Resources},
+    grid::{GridLayer, GridMap, GridShape},
+    history::{BuildAction, PlacedBuilding},
+    laser::{ChangedIntersector, Efficiency, IntersectorType, LaserUpdateEvent, UpdateType},
+    level::{Fixed, Inventory},
+    tween::{Animation, AnimationAction, Tween},
+    Active, Building, ColorWell, DeletionPending, Footprint, GridPosition, GridSettings,
+    LightColor, Orientation, Selected,
+};
+
+pub struct BuildingPlugin;
+
+impl Plugin for BuildingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<BuildingDefinition>()
+            .register_asset_loader(BuildingDefinitionLoader)
+            .init_resource::<BuildingRegistry>();
+
+        app.add_systems(Startup, load_building_definitions);
+        app.add_systems(PreUpdate, update_building_registry);
+    }
+}
+
+/// Identifies a kind of building; the `id` of its `BuildingDefinition`.
+#[derive(Component, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Placeable(pub String);
+
+/// A building type, loaded from a `*.building.ron` file in `assets/buildings`.
+#[derive(Asset, TypePath, Deserialize, Clone, Debug)]
+pub struct BuildingDefinition {
+    pub id: Placeable,
+    pub name: String,
+    pub model: BuildingModel,
+    /// Resting height of the model's origin above the floor.
+    #[serde(default)]
+    pub height: f32,
+    #[serde(default)]
+    pub footprint: Footprint,
+    /// How the building takes part in the beam network, if at all.
+    #[serde(default)]
+    pub intersector: Option<IntersectorType>,
+    #[serde(default)]
+    pub placement: PlacementRule,
+    #[serde(default)]
+    pub cost: BTreeMap<LightColor, u32>,
+    /// Light per second harvested from the well underneath while the beam reaches a receiver.
+    #[serde(default)]
+    pub production: f32,
+    /// Share of the light a beam keeps when it reflects off this building.
+    #[serde(default = "no_loss")]
+    pub efficiency: f32,
+    /// Seconds of builder work it takes to construct, when construction is enabled.
+    #[serde(default = "default_build_time")]
+    pub build_time: f32,
+    /// The next tier, which this building can be upgraded to in place.
+    #[serde(default)]
+    pub upgrade: Option<Placeable>,
+    /// Position in the hotbar, lowest first. Types without one go last, ordered by id.
+    #[serde(default)]
+    pub hotbar_slot: Option<u32>,
+}
+
+fn no_loss() -> f32 {
+    1.0
+}
+
+fn default_build_time() -> f32 {
+    2.0
+}
+
+impl BuildingDefinition {
+    /// What turning this building into `to` costs, and what it refunds, colour by colour. Both
+    /// tiers together cost what `to` does on its own, so demolishing refunds the right amount.
+    pub fn upgrade_cost(
+        &self,
+        to: &BuildingDefinition,
+    ) -> (BTreeMap<LightColor, u32>, BTreeMap<LightColor, u32>) {
+        let mut spend = BTreeMap::new();
+        let mut refund = BTreeMap::new();
+        for color in self.cost.keys().chain(to.cost.keys()) {
+            let from = self.cost.get(color).copied().unwrap_or_default();
+            let to = to.cost.get(color).copied().unwrap_or_default();
+            if to > from {
+                spend.insert(*color, to - from);
+            } else if from > to {
+                refund.insert(*color, from - to);
+            }
+        }
+        (spend, refund)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum BuildingModel {
+    /// Path of a glTF scene, e.g. `"models/collector.glb#Scene0"`.
+    Scene(String),
+    Primitive {
+        shape: PrimitiveShape,
+        #[serde(default)]
+        material: MaterialDefinition,
+    },
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub enum PrimitiveShape {
+    Cuboid(Vec3),
+    Cylinder { radius: f32, height: f32 },
+}
+
+impl From<PrimitiveShape> for Mesh {
+    fn from(shape: PrimitiveShape) -> Self {
+        match shape {
+            PrimitiveShape::Cuboid(size) => Cuboid::from_size(size).into(),
+            PrimitiveShape::Cylinder { radius, height } => Cylinder::new(radius, height).into(),
+        }
+    }
+}
+
+/// The `StandardMaterial` fields designers get to tune.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct MaterialDefinition {
+    pub base_color: Color,
+    pub emissive: Color,
+    pub reflectance: f32,
+    pub perceptual_roughness: f32,
+    pub diffuse_transmission: f32,
+    pub specular_transmission: f32,
+    pub thickness: f32,
+    pub ior: f32,
+}
+
+impl Default for MaterialDefinition {
+    fn default() -> Self {
+        let material = StandardMaterial::default();
+        Self {
+            base_color: material.base_color,
+            emissive: material.emissive,
+            reflectance: material.reflectance,
+            perceptual_roughness: material.perceptual_roughness,
+            diffuse_transmission: material.diffuse_transmission,
+            specular_transmission: material.specular_transmission,
+            thickness: material.thickness,
+            ior: material.ior,
+        }
+    }
+}
+
+impl From<&MaterialDefinition> for StandardMaterial {
+    fn from(material: &MaterialDefinition) -> Self {
+        StandardMaterial {
+            base_color: material.base_color,
+            emissive: material.emissive,
+            reflectance: material.reflectance,
+            perceptual_roughness: material.perceptual_roughness,
+            diffuse_transmission: material.diffuse_transmission,
+            specular_transmission: material.specular_transmission,
+            thickness: material.thickness,
+            ior: material.ior,
+            ..default()
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, Eq, PartialEq, Debug)]
+pub enum PlacementRule {
+    /// Every cell of the footprint has to be bare ground.
+    #[default]
+    EmptyGround,
+    /// The pivot has to sit on a colour well, the rest of the footprint on bare ground.
+    OnColorWell,
+}
+
+#[derive(Error, Clone, Copy, Eq, PartialEq, Debug)]
+pub enum PlacementError {
+    #[error("the footprint leaves the map or overlaps another building")]
+    Blocked,
+    #[error("the ground under the footprint is not clear")]
+    GroundOccupied,
+    #[error("has to be placed on a colour well")]
+    NeedsColorWell,
+    #[error("no building definition with this id is loaded")]
+    UnknownBuilding,
+    #[error("not enough light in stock")]
+    CannotAfford,
+    #[error("still under construction")]
+    UnderConstruction,
+    #[error("none left to place in this level")]
+    NotInInventory,
+    #[error("part of the level")]
+    Fixed,
+    #[error("no building stands there")]
+    NothingToUpgrade,
+}
+
+/// Whether `definition` fits at `pivot`. `is_well` tells colour wells apart from anything else
+/// on the ground layer.
+pub fn check_placement(
+    definition: &BuildingDefinition,
+    grid: &GridMap,
+    shape: GridShape,
+    pivot: GridPosition,
+    orientation: Orientation,
+    is_well: impl Fn(Entity) -> bool,
+) -> Result<(), PlacementError> {
+    check_replacement(definition, grid, shape, pivot, orientation, None, is_well)
+}
+
+/// Like `check_placement`, but the cells `replacing` covers count as free, as they will be once
+/// it makes way for `definition`.
+fn check_replacement(
+    definition: &BuildingDefinition,
+    grid: &GridMap,
+    shape: GridShape,
+    pivot: GridPosition,
+    orientation: Orientation,
+    replacing: Option<Entity>,
+    is_well: impl Fn(Entity) -> bool,
+) -> Result<(), PlacementError> {
+    let cells: Vec<GridPosition> = definition
+        .footprint
+        .cells(pivot, orientation, shape)
+        .collect();
+    let free = cells.iter().all(|cell| {
+        grid.bounds().contains(*cell)
+            && grid
+                .get(GridLayer::Build, *cell)
+                .is_none_or(|entity| Some(*entity) == replacing)
+    });
+    if !free {
+        return Err(PlacementError::Blocked);
+    }
+
+    let on_well = definition.placement == PlacementRule::OnColorWell;
+    if on_well
+        && !grid
+            .get(GridLayer::Ground, pivot)
+            .is_some_and(|ground| is_well(*ground))
+    {
+        return Err(PlacementError::NeedsColorWell);
+    }
+    let bare_ground = cells
+        .iter()
+        .copied()
+        .filter(|cell| !(on_well && *cell == pivot));
+    if !grid.is_free(GridLayer::Ground, bare_ground) {
+        return Err(PlacementError::GroundOccupied);
+    }
+
+    Ok(())
+}
+
+/// Checks `buildings` as if they were placed one after the other, so they may not overlap each
+/// other either. Returns the ones that would not fit, with the reason.
+pub fn find_conflicts<'a>(
+    grid: &GridMap,
+    registry: &BuildingRegistry,
+    shape: GridShape,
+    buildings: &'a [PlacedBuilding],
+    is_well: impl Fn(Entity) -> bool,
+) -> Vec<(&'a PlacedBuilding, PlacementError)> {
+    let mut grid = grid.clone();
+    let mut conflicts = Vec::new();
+    for building in buildings {
+        let Some(definition) = registry.get(&building.placeable) else {
+            conflicts.push((building, PlacementError::UnknownBuilding));
+            continue;
+        };
+        let cells = definition
+            .footprint
+            .cells(building.pivot, building.orientation, shape);
+        match check_placement(
+            definition,
+            &grid,
+            shape,
+            building.pivot,
+            building.orientation,
+            &is_well,
+        ) {
+            Ok(()) => grid
+                .set_footprint(GridLayer::Build, cells, Entity::PLACEHOLDER)
+                .unwrap(),
+            Err(error) => conflicts.push((building, error)),
+        }
+    }
+    conflicts
+}
+
+/// Marks the child entity holding a building's model.
+#[derive(Component)]
+pub struct BuildingModelRoot;
+
+/// Spawns a building rising out of the floor at `grid_pos`, or a construction site sunk into it
+/// if `site` is given. The caller records it in the `GridMap` and announces it to the beam
+/// network.
+fn spawn_building(
+    commands: &mut Commands,
+    definition: &BuildingDefinition,
+    settings: &GridSettings,
+    grid_pos: GridPosition,
+    orientation: Orientation,
+    site: Option<ConstructionSite>,
+    asset_server: &AssetServer,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) -> Entity {
+    let position = settings.grid_to_world(grid_pos);
+    let mut building = commands.spawn((
+        SpatialBundle::from_transform(
+            Transform::from_translation(Vec3::new(position.x, SITE_DEPTH, position.y))
+                .with_rotation(orientation.to_quat(settings.shape)),
+        ),
+        grid_pos,
+        orientation,
+        Building,
+    ));
+    insert_definition(&mut building, definition);
+    match site {
+        Some(site) => {
+            building.insert((site, with_site_pulse(Animation::default())));
+        }
+        None => {
+            // rise a little past the rest height, then settle onto it
+            let rest = Vec3::new(position.x, definition.height, position.y);
+            building.insert(
+                Animation::from(
+                    Tween::new(1.2)
+                        .translation(rest + Vec3::Y * 0.15)
+                        .scale(Vec3::ONE),
+                )
+                .then(Tween::new(0.3).translation(rest).easing("ease_in_out")),
+            );
+            insert_behaviour(&mut building, definition);
+        }
+    }
+    building.with_children(|parent| {
+        spawn_model(
+            parent,
+            definition,
+            settings.shape,
+            asset_server,
+            meshes,
+            materials,
+        );
+    });
+    building.id()
+}
+
+/// Gives a building the components that identify its definition, replacing those of the
+/// definition it had before.
+fn insert_definition(building: &mut EntityCommands, definition: &BuildingDefinition) {
+    building.insert((
+        definition.footprint.clone(),
+        definition.id.clone(),
+        Name::new(definition.name.clone()),
+    ));
+}
+
+/// Gives a finished building the components that make it take part in the beam network and the
+/// economy, replacing those of the definition it had before.
+pub fn insert_behaviour(building: &mut EntityCommands, definition: &BuildingDefinition) {
+    match definition.intersector {
+        Some(intersector) => building.insert((intersector, Efficiency(definition.efficiency))),
+        None => building.remove::<(IntersectorType, Efficiency)>(),
+    };
+    if definition.production > 0.0 {
+        building.insert(Collector {
+            rate: definition.production,
+        });
+    } else {
+        building.remove::<Collector>();
+    }
+}
+
+/// Spawns the model of `definition` below `parent`, e.g. a building or the cursor's ghost.
+pub fn spawn_model(
+    parent: &mut ChildBuilder,
+    definition: &BuildingDefinition,
+    shape: GridShape,
+    asset_server: &AssetServer,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) -> Entity {
+    // reflector models lie along +z; turn them half a step off the grid axes onto the line the
+    // beam tracer reflects on
+    let rotation = match definition.intersector {
+        Some(IntersectorType::Reflector) => {
+            Quat::from_rotation_y(FRAC_PI_2 - shape.step_angle() / 2.0)
+        }
+        _ => Quat::IDENTITY,
+    };
+    let transform = Transform::from_rotation(rotation);
+
+    match &definition.model {
+        BuildingModel::Scene(path) => parent
+            .spawn((
+                SceneBundle {
+                    scene: asset_server.load(path.clone()),
+                    transform,
+                    ..default()
+                },
+                BuildingModelRoot,
+            ))
+            .id(),
+        BuildingModel::Primitive { shape, material } => parent
+            .spawn((
+                PbrBundle {
+                    mesh: meshes.add(*shape),
+                    material: materials.add(StandardMaterial::from(material)),
+                    transform,
+                    ..default()
+                },
+                BuildingModelRoot,
+            ))
+            .id(),
+    }
+}
+
+/// Places, removes, rotates and upgrades buildings, keeping the `GridMap` and the beam network in
+/// step. Every change to the buildings on the grid goes through `apply`, which is what makes the
+/// actions undoable.
+#[derive(SystemParam)]
+pub struct Builder<'w, 's> {
+    commands: Commands<'w, 's>,
+    grid_map: ResMut<'w, GridMap>,
+    settings: Res<'w, GridSettings>,
+    registry: Res<'w, BuildingRegistry>,
+    asset_server: Res<'w, AssetServer>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+    ev_laser_update: EventWriter<'w, LaserUpdateEvent>,
+    resources: ResMut<'w, Resources>,
+    inventory: ResMut<'w, Inventory>,
+    construction: Res<'w, ConstructionSettings>,
+    construction_queue: ResMut<'w, ConstructionQueue>,
+    lookup: BuildingLookup<'w, 's>,
+    animations: Query<'w, 's, &'static mut Animation>,
+    children: Query<'w, 's, &'static Children>,
+    model_roots: Query<'w, 's, (), With<BuildingModelRoot>>,
+    buildings: Query<
+        'w,
+        's,
+        (
+            &'static Placeable,
+            &'static GridPosition,
+            &'static Orientation,
+            &'static Footprint,
+            Option<&'static IntersectorType>,
+            &'static mut Transform,
+        ),
+        (With<Building>, Without<DeletionPending>),
+    >,
+}
+
+/// What the build rules read: the grid, definitions and stock, and the entities to tell apart.
+/// `Builder` and `BuildChecker` each lend theirs out through `BuildRules::view`.
+pub struct BuildView<'a> {
+    grid: &'a GridMap,
+    shape: GridShape,
+    registry: &'a BuildingRegistry,
+    resources: &'a Resources,
+    inventory: &'a Inventory,
+    lookup: &'a BuildingLookup<'a, 'a>,
+}
+
+/// The entities the build rules look up, shared by `Builder` and `BuildChecker`.
+#[derive(SystemParam)]
+pub struct BuildingLookup<'w, 's> {
+    color_wells: Query<'w, 's, (), With<ColorWell>>,
+    sites: Query<'w, 's, (), With<ConstructionSite>>,
+    fixed: Query<'w, 's, (), With<Fixed>>,
+    buildings: Query<
+        'w,
+        's,
+        (
+            &'static Placeable,
+            &'static GridPosition,
+            &'static Orientation,
+        ),
+        (With<Building>, Without<DeletionPending>),
+    >,
+}
+
+/// The checks placing, upgrading and pasting go through, shared by `Builder` and the read-only
+/// `BuildChecker`. Implementors only say where the grid, definitions and stock are.
+pub trait BuildRules {
+    fn view(&self) -> BuildView<'_>;
+
+    fn grid(&self) -> &GridMap {
+        self.view().grid
+    }
+
+    fn shape(&self) -> GridShape {
+        self.view().shape
+    }
+
+    fn registry(&self) -> &BuildingRegistry {
+        self.view().registry
+    }
+
+    fn resources(&self) -> &Resources {
+        self.view().resources
+    }
+
+    fn inventory(&self) -> &Inventory {
+        self.view().inventory
+    }
+
+    fn is_well(&self, ground: Entity) -> bool {
+        self.view().lookup.color_wells.contains(ground)
+    }
+
+    fn is_fixed(&self, entity: Entity) -> bool {
+        self.view().lookup.fixed.contains(entity)
+    }
+
+    fn is_site(&self, entity: Entity) -> bool {
+        self.view().lookup.sites.contains(entity)
+    }
+
+    /// The building `entity` is, as the history would record it.
+    fn describe(&self, entity: Entity) -> Option<PlacedBuilding> {
+        let (placeable, pivot, orientation) = self.view().lookup.buildings.get(entity).ok()?;
+        Some(PlacedBuilding {
+            placeable: placeable.clone(),
+            pivot: *pivot,
+            orientation: *orientation,
+        })
+    }
+
+    /// The building covering `position`, as the history would record it.
+    fn building_at(&self, position: GridPosition) -> Option<PlacedBuilding> {
+        let entity = self.grid().get(GridLayer::Build, position)?;
+        self.describe(*entity)
+    }
+
+    /// Whether `building` could be placed right now, including whether it is affordable and
+    /// there is one left in the inventory.
+    fn check(&self, building: &PlacedBuilding) -> Result<(), PlacementError> {
+        self.check_site(building)?;
+        let definition = self
+            .registry()
+            .get(&building.placeable)
+            .ok_or(PlacementError::UnknownBuilding)?;
+        if !self.inventory().has(&building.placeable) {
+            return Err(PlacementError::NotInInventory);
+        }
+        if !self.resources().can_afford(&definition.cost) {
+            return Err(PlacementError::CannotAfford);
+        }
+        Ok(())
+    }
+
+    /// Whether `building` fits where it is to go, leaving cost and inventory aside.
+    fn check_site(&self, building: &PlacedBuilding) -> Result<(), PlacementError> {
+        let definition = self
+            .registry()
+            .get(&building.placeable)
+            .ok_or(PlacementError::UnknownBuilding)?;
+        check_placement(
+            definition,
+            self.grid(),
+            self.shape(),
+            building.pivot,
+            building.orientation,
+            |ground| self.is_well(ground),
+        )
+    }
+
+    /// Whether `building` could be turned into `to` right now. The new tier has to fit where
+    /// the old one stands, and the difference in cost has to be affordable.
+    fn check_upgrade(
+        &self,
+        building: &PlacedBuilding,
+        to: &Placeable,
+    ) -> Result<(), PlacementError> {
+        let from = self
+            .registry()
+            .get(&building.placeable)
+            .ok_or(PlacementError::UnknownBuilding)?;
+        let to = self
+            .registry()
+            .get(to)
+            .ok_or(PlacementError::UnknownBuilding)?;
+        let entity = *self
+            .grid()
+            .get(GridLayer::Build, building.pivot)
+            .ok_or(PlacementError::NothingToUpgrade)?;
+        if self.is_fixed(entity) {
+            return Err(PlacementError::Fixed);
+        }
+        if self.is_site(entity) {
+            return Err(PlacementError::UnderConstruction);
+        }
+
+        check_replacement(
+            to,
+            self.grid(),
+            self.shape(),
+            building.pivot,
+            building.orientation,
+            Some(entity),
+            |ground| self.is_well(ground),
+        )?;
+        if !self.inventory().has(&to.id) {
+            return Err(PlacementError::NotInInventory);
+        }
+        if !self.resources().can_afford(&from.upgrade_cost(to).0) {
+            return Err(PlacementError::CannotAfford);
+        }
+        Ok(())
+    }
+
+    /// Like `find_conflicts`, but also flags the buildings the inventory or the stock runs out
+    /// before.
+    fn conflicts<'a>(
+        &self,
+        buildings: &'a [PlacedBuilding],
+    ) -> Vec<(&'a PlacedBuilding, PlacementError)> {
+        let mut conflicts = find_conflicts(
+            self.grid(),
+            self.registry(),
+            self.shape(),
+            buildings,
+            |ground| self.is_well(ground),
+        );
+
+        let mut stock = self.resources().clone();
+        let mut inventory = self.inventory().clone();
+        for building in buildings {
+            if conflicts
+                .iter()
+                .any(|(conflict, _)| std::ptr::eq(*conflict, building))
+            {
+                continue;
+            }
+            if !inventory.has(&building.placeable) {
+                conflicts.push((building, PlacementError::NotInInventory));
+                continue;
+            }
+            inventory.take(&building.placeable);
+            let definition = self.registry().get(&building.placeable).unwrap();
+            if !stock.spend(&definition.cost) {
+                conflicts.push((building, PlacementError::CannotAfford));
+            }
+        }
+        conflicts
+    }
+}
+
+/// Looks at the buildings without changing them, for systems that only check what the player
+/// is about to do before sending a `BuildCommand`. Unlike `Builder` it runs alongside other
+/// readers.
+#[derive(SystemParam)]
+pub struct BuildChecker<'w, 's> {
+    grid_map: Res<'w, GridMap>,
+    settings: Res<'w, GridSettings>,
+    registry: Res<'w, BuildingRegistry>,
+    resources: Res<'w, Resources>,
+    inventory: Res<'w, Inventory>,
+    lookup: BuildingLookup<'w, 's>,
+}
+
+impl BuildRules for BuildChecker<'_, '_> {
+    fn view(&self) -> BuildView<'_> {
+        BuildView {
+            grid: &self.grid_map,
+            shape: self.settings.shape,
+            registry: &self.registry,
+            resources: &self.resources,
+            inventory: &self.inventory,
+            lookup: &self.lookup,
+        }
+    }
+}
+
+impl BuildRules for Builder<'_, '_> {
+    fn view(&self) -> BuildView<'_> {
+        BuildView {
+            grid: &self.grid_map,
+            shape: self.settings.shape,
+            registry: &self.registry,
+            resources: &self.resources,
+            inventory: &self.inventory,
+            lookup: &self.lookup,
+        }
+    }
+}
+
+impl Builder<'_, '_> {
+    /// Carries out as much of `action` as possible, announcing every intersector it touched in
+    /// one `LaserUpdateEvent`. Returns the part that took effect, if any.
+    pub fn apply(&mut self, action: &BuildAction) -> Option<BuildAction> {
+        let mut changed = Vec::new();
+        let (applied, update_type) = match action {
+            BuildAction::Place(buildings) => {
+                let placed: Vec<PlacedBuilding> = buildings
+                    .iter()
+                    .filter(|building| self.place(building, &mut changed).is_some())
+                    .cloned()
+                    .collect();
+                (
+                    (!placed.is_empty()).then_some(BuildAction::Place(placed)),
+                    UpdateType::Place,
+                )
+            }
+            BuildAction::Remove(buildings) => {
+                let removed: Vec<PlacedBuilding> = buildings
+                    .iter()
+                    .filter(|building| self.remove(building, &mut changed).is_some())
+                    .cloned()
+                    .collect();
+                (
+                    (!removed.is_empty()).then_some(BuildAction::Remove(removed)),
+                    UpdateType::Remove,
+                )
+            }
+            BuildAction::Rotate { pivot, from, to } => (
+                self.rotate(*pivot, *from, *to, &mut changed)
+                    .map(|_| action.clone()),
+                UpdateType::Rotate,
+            ),
+            BuildAction::Upgrade { pivot, from, to } => (
+                self.upgrade(*pivot, from, to, &mut changed)
+                    .map(|_| action.clone()),
+                UpdateType::Upgrade,
+            ),
+        };
+
+        if !changed.is_empty() {
+            self.ev_laser_update.send(LaserUpdateEvent {
+                update_type,
+                intersectors: changed,
+            });
+        }
+        applied
+    }
+
+    /// Puts buildings back as a saved game or level has them, without charging for them or
+    /// taking them from the inventory. `progress` is how far along a construction site was,
+    /// `None` for finished buildings. Returns the entity of each building that could be put
+    /// back; the beams are retraced once for all of them.
+    pub fn restore(&mut self, buildings: &[(PlacedBuilding, Option<f32>)]) -> Vec<Option<Entity>> {
+        let mut changed = Vec::new();
+        let entities = buildings
+            .iter()
+            .map(|(building, progress)| {
+                if let Err(error) = self.check_site(building) {
+                    warn!("Could not restore {:?}: {}", building, error);
+                    return None;
+                }
+                let definition = self.registry.get(&building.placeable)?;
+                let site = progress.map(|progress| ConstructionSite {
+                    progress,
+                    ..ConstructionSite::new(definition.build_time)
+                });
+                self.put(building, site, &mut changed)
+            })
+            .collect();
+        // sent even when empty, so beams of the buildings that were replaced go away
+        self.ev_laser_update.send(LaserUpdateEvent {
+            update_type: UpdateType::Place,
+            intersectors: changed,
+        });
+        entities
+    }
+
+    /// Takes buildings away without refunding them or handing them back to the inventory, the
+    /// counterpart of `restore` for laying out a level. Returns how many were taken away; the
+    /// beams are retraced once for all of them.
+    pub fn discard(&mut self, buildings: &[PlacedBuilding]) -> usize {
+        let mut changed = Vec::new();
+        let discarded = buildings
+            .iter()
+            .filter(|building| self.take_down(building, &mut changed).is_some())
+            .count();
+        if !changed.is_empty() {
+            self.ev_laser_update.send(LaserUpdateEvent {
+                update_type: UpdateType::Remove,
+                intersectors: changed,
+            });
+        }
+        discarded
+    }
+
+    fn place(
+        &mut self,
+        building: &PlacedBuilding,
+        changed: &mut Vec<ChangedIntersector>,
+    ) -> Option<Entity> {
+        self.check(building).ok()?;
+        let definition = self.registry.get(&building.placeable)?;
+        self.resources.spend(&definition.cost);
+        self.inventory.take(&building.placeable);
+
+        let site = self
+            .construction
+            .enabled
+            .then(|| ConstructionSite::new(definition.build_time));
+        self.put(building, site, changed)
+    }
+
+    /// Spawns a building the checks already passed for and claims its cells.
+    fn put(
+        &mut self,
+        building: &PlacedBuilding,
+        site: Option<ConstructionSite>,
+        changed: &mut Vec<ChangedIntersector>,
+    ) -> Option<Entity> {
+        let definition = self.registry.get(&building.placeable)?;
+        if definition.placement == PlacementRule::OnColorWell {
+            let well = self.grid_map.get(GridLayer::Ground, building.pivot)?;
+            self.commands.entity(*well).insert(Active);
+        }
+
+        let constructing = site.is_some();
+        let entity = spawn_building(
+            &mut self.commands,
+            definition,
+            &self.settings,
+            building.pivot,
+            building.orientation,
+            site,
+            &self.asset_server,
+            &mut self.meshes,
+            &mut self.materials,
+        );
+        self.grid_map
+            .set_footprint(
+                GridLayer::Build,
+                definition.footprint.cells(
+                    building.pivot,
+                    building.orientation,
+                    self.settings.shape,
+                ),
+                entity,
+            )
+            .unwrap();
+        if constructing {
+            // joins the beam network once built
+            self.construction_queue.push(entity);
+        } else if let Some(intersector) = definition.intersector {
+            changed.push(ChangedIntersector {
+                entity,
+                intersector,
+                grid_position: building.pivot,
+            });
+        }
+        Some(entity)
+    }
+
+    /// Takes the building down, see `take_down`. The full cost is refunded, whether the building
+    /// was finished or still a site: undo removes what it takes back through here, so anything
+    /// less would make every undone placement cost the player light.
+    fn remove(
+        &mut self,
+        building: &PlacedBuilding,
+        changed: &mut Vec<ChangedIntersector>,
+    ) -> Option<Entity> {
+        let entity = self.take_down(building, changed)?;
+        if let Some(definition) = self.registry.get(&building.placeable) {
+            self.resources.refund(&definition.cost);
+        }
+        self.inventory.give(&building.placeable);
+        Some(entity)
+    }
+
+    /// Sinks the building into the floor. Its cells are freed straight away, the entity is
+    /// despawned once the animation ends.
+    fn take_down(
+        &mut self,
+        building: &PlacedBuilding,
+        changed: &mut Vec<ChangedIntersector>,
+    ) -> Option<Entity> {
+        let entity = *self.grid_map.get(GridLayer::Build, building.pivot)?;
+        if self.describe(entity).as_ref() != Some(building) || self.is_fixed(entity) {
+            return None;
+        }
+        let (_, _, _, footprint, intersector, _) = self.buildings.get(entity).ok()?;
+
+        self.grid_map
+            .remove_footprint(
+                GridLayer::Build,
+                footprint.cells(building.pivot, building.orientation, self.settings.shape),
+            )
+            .unwrap();
+        // only buildings placed on a well may cover one with their pivot
+        if let Some(well) = self.grid_map.get(GridLayer::Ground, building.pivot) {
+            if self.is_well(*well) {
+                self.commands.entity(*well).remove::<Active>();
+            }
+        }
+        if let Some(intersector) = intersector {
+            changed.push(ChangedIntersector {
+                entity,
+                intersector: *intersector,
+                grid_position: building.pivot,
+            });
+        }
+
+        let position = self.settings.grid_to_world(building.pivot);
+        self.commands
+            .entity(entity)
+            .insert((
+                // replaces any animation still playing, along with what it would have done
+                Animation::from(
+                    Tween::new(0.5)
+                        .translation(Vec3::new(position.x, -0.5, position.y))
+                        .scale(Vec3::ZERO)
+                        .easing("ease_in"),
+                )
+                .on_complete(AnimationAction::Despawn),
+                DeletionPending,
+            ))
+            .remove::<Selected>();
+        Some(entity)
+    }
+
+    /// Turns the building at `pivot` from `from` to `to`, if its footprint still fits.
+    fn rotate(
+        &mut self,
+        pivot: GridPosition,
+        from: Orientation,
+        to: Orientation,
+        changed: &mut Vec<ChangedIntersector>,
+    ) -> Option<Entity> {
+        let building = self.building_at(pivot)?;
+        if building.pivot != pivot || building.orientation != from {
+            return None;
+        }
+        let entity = *self.grid_map.get(GridLayer::Build, pivot)?;
+        if self.is_fixed(entity) {
+            return None;
+        }
+        let shape = self.settings.shape;
+        let footprint = self.buildings.get(entity).ok()?.3.clone();
+
+        // lift the building off the grid so it does not block its own new footprint
+        self.grid_map
+            .remove_footprint(GridLayer::Build, footprint.cells(pivot, from, shape))
+            .unwrap();
+        let fits = self
+            .check_site(&PlacedBuilding {
+                orientation: to,
+                ..building
+            })
+            .is_ok();
+        let orientation = if fits { to } else { from };
+        self.grid_map
+            .set_footprint(
+                GridLayer::Build,
+                footprint.cells(pivot, orientation, shape),
+                entity,
+            )
+            .unwrap();
+        if !fits {
+            return None;
+        }
+
+        let (placeable, _, _, _, intersector, _) = self.buildings.get(entity).ok()?;
+        if let Some(intersector) = intersector {
+            changed.push(ChangedIntersector {
+                entity,
+                intersector: *intersector,
+                grid_position: pivot,
+            });
+        }
+        // the beams turn at once, the model follows. Queued sites are raised by their
+        // builders and keep pulsing, other buildings are also brought to rest
+        let tween = Tween::new(0.25).rotation(to.to_quat(shape));
+        let animation = if self.construction_queue.contains(entity) {
+            with_site_pulse(Animation::from(tween))
+        } else {
+            let height = self
+                .registry
+                .get(placeable)
+                .map_or(0.0, |definition| definition.height);
+            let position = self.settings.grid_to_world(pivot);
+            Animation::from(
+                tween
+                    .translation(Vec3::new(position.x, height, position.y))
+                    .scale(Vec3::ONE),
+            )
+        };
+        self.commands.entity(entity).insert(to);
+        self.animate(entity, animation);
+        Some(entity)
+    }
+
+    /// Plays `animation` on `entity` in place of its current one. Whatever the current one was
+    /// going to do on completion still happens, once the new one ends.
+    fn animate(&mut self, entity: Entity, mut animation: Animation) {
+        if let Ok(mut current) = self.animations.get_mut(entity) {
+            for action in current.take_actions() {
+                animation = animation.on_complete(action);
+            }
+        }
+        self.commands.entity(entity).insert(animation);
+    }
+
+    /// Swaps the building at `pivot` from one tier to another. The entity stays, so the beams
+    /// linked to it do too; its model, components and footprint are replaced.
+    fn upgrade(
+        &mut self,
+        pivot: GridPosition,
+        from: &Placeable,
+        to: &Placeable,
+        changed: &mut Vec<ChangedIntersector>,
+    ) -> Option<Entity> {
+        let building = self.building_at(pivot)?;
+        if building.pivot != pivot || building.placeable != *from {
+            return None;
+        }
+        self.check_upgrade(&building, to).ok()?;
+        let entity = *self.grid_map.get(GridLayer::Build, pivot)?;
+        let old = self.registry.get(from)?;
+        let new = self.registry.get(to)?;
+        let shape = self.settings.shape;
+
+        let (_, _, _, footprint, ..) = self.buildings.get(entity).ok()?;
+        if let Err(error) = swap_footprint(
+            &mut self.grid_map,
+            entity,
+            footprint.cells(pivot, building.orientation, shape),
+            new.footprint.cells(pivot, building.orientation, shape),
+        ) {
+            warn!("Can't upgrade {:?} at {:?}: {}", from, pivot, error);
+            return None;
+        }
+
+        let (spend, refund) = old.upgrade_cost(new);
+        self.resources.spend(&spend);
+        self.resources.refund(&refund);
+        self.inventory.take(to);
+        self.inventory.give(from);
+
+        if old.placement != new.placement {
+            if let Some(well) = self.grid_map.get(GridLayer::Ground, pivot) {
+                if new.placement == PlacementRule::OnColorWell {
+                    self.commands.entity(*well).insert(Active);
+                } else {
+                    self.commands.entity(*well).remove::<Active>();
+                }
+            }
+        }
+        if let Some(intersector) = new.intersector.or(old.intersector) {
+            changed.push(ChangedIntersector {
+                entity,
+                intersector,
+                grid_position: pivot,
+            });
+        }
+
+        if let Ok(children) = self.children.get(entity) {
+            for child in children.iter() {
+                if self.model_roots.contains(*child) {
+                    self.commands.entity(*child).despawn_recursive();
+                }
+            }
+        }
+        let mut commands = self.commands.entity(entity);
+        insert_definition(&mut commands, new);
+        insert_behaviour(&mut commands, new);
+        commands.with_children(|parent| {
+            spawn_model(
+                parent,
+                new,
+                shape,
+                &self.asset_server,
+                &mut self.meshes,
+                &mut self.materials,
+            );
+        });
+
+        // pop the new tier into place, overshooting a little
+        let (.., mut transform) = self.buildings.get_mut(entity).ok()?;
+        transform.scale = Vec3::splat(0.6);
+        let position = self.settings.grid_to_world(pivot);
+        let height = new.height;
+        self.animate(
+            entity,
+            Animation::from(
+                Tween::new(0.45)
+                    .translation(Vec3::new(position.x, height, position.y))
+                    .scale(Vec3::splat(1.15))
+                    .easing("snappy"),
+            )
+            .then(Tween::new(0.2).scale(Vec3::ONE).easing("ease_in_out")),
+        );
+        Some(entity)
+    }
+}
+
+/// Every loaded building definition, keyed by id.
+#[derive(Resource, Default)]
+pub struct BuildingRegistry {
+    folder: Handle<LoadedFolder>,
+    ids: HashMap<AssetId<BuildingDefinition>, Placeable>,
+    definitions: HashMap<Placeable, BuildingDefinition>,
+}
+
+impl BuildingRegistry {
+    pub fn get(&self, placeable: &Placeable) -> Option<&BuildingDefinition> {
+        self.definitions.get(placeable)
+    }
+
+    /// All definitions, ordered by id.
+    pub fn iter(&self) -> impl Iterator<Item = &BuildingDefinition> {
+        let mut definitions: Vec<&BuildingDefinition> = self.definitions.values().collect();
+        definitions.sort_by(|a, b| a.id.cmp(&b.id));
+        definitions.into_iter()
+    }
+
+    /// The definitions in `assets/buildings`, read straight from the files rather than through
+    /// the asset server.
+    #[cfg(test)]
+    pub fn from_assets() -> Self {
+        let folder = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/buildings");
+        let mut registry = Self::default();
+        for entry in std::fs::read_dir(folder).unwrap() {
+            let text = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+            let definition: BuildingDefinition = ron::from_str(&text).unwrap();
+            registry
+                .definitions
+                .insert(definition.id.clone(), definition);
+        }
+        registry
+    }
+}
+
+/// Moves `entity` on the build layer from the `old` cells to the `new` ones, or leaves the grid
+/// alone if it does not cover all of `old` or something else covers part of `new`.
+fn swap_footprint(
+    grid: &mut GridMap,
+    entity: Entity,
+    old: impl IntoIterator<Item = GridPosition>,
+    new: impl IntoIterator<Item = GridPosition>,
+) -> Result<(), PlacementError> {
+    let old: Vec<GridPosition> = old.into_iter().collect();
+    let new: Vec<GridPosition> = new.into_iter().collect();
+    if !old
+        .iter()
+        .all(|cell| grid.get(GridLayer::Build, *cell) == Some(&entity))
+    {
+        return Err(PlacementError::NothingToUpgrade);
+    }
+    let free = new.iter().all(|cell| {
+        grid.bounds().contains(*cell)
+            && grid
+                .get(GridLayer::Build, *cell)
+                .is_none_or(|other| *other == entity)
+    });
+    if !free {
+        return Err(PlacementError::Blocked);
+    }
+
+    grid.remove_footprint(GridLayer::Build, old)
+        .and_then(|()| grid.set_footprint(GridLayer::Build, new, entity))
+        .map_err(|()| PlacementError::Blocked)
+}
+
+fn load_building_definitions(
+    mut registry: ResMut<BuildingRegistry>,
+    asset_server: Res<AssetServer>,
+) {
+    registry.folder = asset_server.load_folder("buildings");
+}
+
+fn update_building_registry(
+    mut registry: ResMut<BuildingRegistry>,
+    mut events: EventReader<AssetEvent<BuildingDefinition>>,
+    definitions: Res<Assets<BuildingDefinition>>,
+) {
+    for event in events.read() {
+        match event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } => {
+                let Some(definition) = definitions.get(*id) else {
+                    continue;
+                };
+                info!("Loaded building definition {:?}", definition.id);
+                if let Some(previous) = registry.ids.insert(*id, definition.id.clone()) {
+                    registry.definitions.remove(&previous);
+                }
+                registry
+                    .definitions
+                    .insert(definition.id.clone(), definition.clone());
+            }
+            AssetEvent::Removed { id } => {
+                if let Some(placeable) = registry.ids.remove(id) {
+                    registry.definitions.remove(&placeable);
+                }
+            }
+            AssetEvent::Unused { .. } | AssetEvent::LoadedWithDependencies { .. } => {}
+        }
+    }
+}
+
+#[derive(Default)]
+struct BuildingDefinitionLoader;
+
+#[derive(Error, Debug)]
+enum BuildingDefinitionError {
+    #[error("could not read building definition: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse building definition: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+}
+
+impl AssetLoader for BuildingDefinitionLoader {
+    type Asset = BuildingDefinition;
+    type Settings = ();
+    type Error = BuildingDefinitionError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["building.ron"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::{grid::GridBounds, history::BuildCommand, testing::build_app};
+
+    fn footprint(definition: &str) -> Footprint {
+        ron::from_str(definition).unwrap()
+    }
+
+    fn cells(footprint: &Footprint, orientation: u8, shape: GridShape) -> Vec<(i32, i32)> {
+        let mut cells: Vec<(i32, i32)> = footprint
+            .cells(GridPosition { x: 5, y: 5 }, Orientation(orientation), shape)
+            .map(|cell| (cell.x, cell.y))
+            .collect();
+        cells.sort();
+        cells
+    }
+
+    #[test]
+    fn repeated_cells_are_claimed_once() {
+        let repeated = footprint("Cells([(1, 0), (0, 1), (1, 0), (0, 0)])");
+        assert_eq!(
+            cells(&repeated, 0, GridShape::Square),
+            [(5, 5), (5, 6), (6, 5)]
+        );
+
+        let mut grid = GridMap::new(GridBounds::default());
+        let cells = repeated.cells(GridPosition::new(0, 0), Orientation(0), GridShape::Square);
+        assert!(grid
+            .set_footprint(GridLayer::Build, cells, Entity::from_raw(0))
+            .is_ok());
+    }
+
+    #[test]
+    fn multi_cell_footprints_turn_about_their_pivot_on_square_grids() {
+        let ell = footprint("Cells([(1, 0), (2, 0), (0, 1)])");
+        let shape = GridShape::Square;
+        assert_eq!(cells(&ell, 0, shape), [(5, 5), (5, 6), (6, 5), (7, 5)]);
+        assert_eq!(cells(&ell, 1, shape), [(4, 5), (5, 5), (5, 6), (5, 7)]);
+        assert_eq!(cells(&ell, 2, shape), [(3, 5), (4, 5), (5, 4), (5, 5)]);
+        assert_eq!(cells(&ell, 3, shape), [(5, 3), (5, 4), (5, 5), (6, 5)]);
+
+        let wall = footprint("Rect(width: 3, height: 1, pivot: (1, 0))");
+        assert_eq!(cells(&wall, 1, shape), [(5, 4), (5, 5), (5, 6)]);
+    }
+
+    #[test]
+    fn multi_cell_footprints_turn_about_their_pivot_on_hex_grids() {
+        let ell = footprint("Cells([(1, 0), (2, 0), (0, 1)])");
+        let shape = GridShape::Hexagonal;
+        assert_eq!(cells(&ell, 0, shape), [(5, 5), (5, 6), (6, 5), (7, 5)]);
+        assert_eq!(cells(&ell, 1, shape), [(4, 6), (5, 5), (5, 6), (5, 7)]);
+        assert_eq!(cells(&ell, 3, shape), [(3, 5), (4, 5), (5, 4), (5, 5)]);
+        for orientation in 0..6 {
+            assert_eq!(cells(&ell, orientation, shape).len(), 4);
+        }
+    }
+
+    fn mirror(placeable: &str, x: i32) -> PlacedBuilding {
+        PlacedBuilding {
+            placeable: Placeable(placeable.to_string()),
+            pivot: GridPosition { x, y: 0 },
+            orientation: Orientation::default(),
+        }
+    }
+
+    fn send(app: &mut App, command: BuildCommand) {
+        app.world.send_event(command);
+        app.update();
+    }
+
+    fn upgrade(from: &str, to: &str) -> BuildCommand {
+        BuildCommand::Do(BuildAction::Upgrade {
+            pivot: GridPosition { x: 0, y: 0 },
+            from: Placeable(from.to_string()),
+            to: Placeable(to.to_string()),
+        })
+    }
+
+    /// A mirror at the origin, with 20 orange and two mirrors and one low-loss mirror to
+    /// build. The registry also knows a cheap `wide_mirror` three cells across.
+    fn upgrade_app() -> (App, Entity) {
+        let mut app = build_app(GridShape::Square);
+        let mut registry = BuildingRegistry::from_assets();
+        let mut wide = registry
+            .get(&Placeable("mirror".to_string()))
+            .unwrap()
+            .clone();
+        wide.id = Placeable("wide_mirror".to_string());
+        wide.footprint = footprint("Rect(width: 3, height: 1, pivot: (1, 0))");
+        wide.cost = BTreeMap::from([(LightColor::Orange, 1)]);
+        registry.definitions.insert(wide.id.clone(), wide);
+        app.insert_resource(registry)
+            .insert_resource(Resources::new([(LightColor::Orange, 20)]))
+            .insert_resource(Inventory::limited(BTreeMap::from([
+                (Placeable("mirror".to_string()), 2),
+                (Placeable("low_loss_mirror".to_string()), 1),
+                (Placeable("wide_mirror".to_string()), 1),
+            ])));
+        send(
+            &mut app,
+            BuildCommand::Do(BuildAction::Place(vec![mirror("mirror", 0)])),
+        );
+        let entity = *app
+            .world
+            .resource::<GridMap>()
+            .get(GridLayer::Build, GridPosition { x: 0, y: 0 })
+            .unwrap();
+        (app, entity)
+    }
+
+    /// What stands at the origin, the orange in stock and the mirrors and low-loss mirrors left.
+    fn state(app: &App) -> (Option<Entity>, Placeable, u32, Option<u32>, Option<u32>) {
+        let entity = app
+            .world
+            .resource::<GridMap>()
+            .get(GridLayer::Build, GridPosition { x: 0, y: 0 })
+            .copied();
+        let placeable = entity
+            .and_then(|entity| app.world.get::<Placeable>(entity))
+            .cloned()
+            .unwrap_or(Placeable(String::new()));
+        let inventory = app.world.resource::<Inventory>();
+        (
+            entity,
+            placeable,
+            app.world.resource::<Resources>().get(LightColor::Orange),
+            inventory.count(&Placeable("mirror".to_string())),
+            inventory.count(&Placeable("low_loss_mirror".to_string())),
+        )
+    }
+
+    #[test]
+    fn upgrades_swap_the_tier_in_place() {
+        let (mut app, entity) = upgrade_app();
+        assert_eq!(
+            state(&app),
+            (
+                Some(entity),
+                mirror("mirror", 0).placeable,
+                18,
+                Some(1),
+                Some(1)
+            )
+        );
+
+        // the difference of 6 is spent, the mirror goes back to the inventory
+        send(&mut app, upgrade("mirror", "low_loss_mirror"));
+        assert_eq!(
+            state(&app),
+            (
+                Some(entity),
+                mirror("low_loss_mirror", 0).placeable,
+                12,
+                Some(2),
+                Some(0)
+            )
+        );
+        assert_eq!(
+            app.world.get::<GridPosition>(entity),
+            Some(&GridPosition { x: 0, y: 0 })
+        );
+
+        // a cheaper tier refunds the difference
+        send(&mut app, upgrade("low_loss_mirror", "wide_mirror"));
+        assert_eq!(state(&app).2, 19);
+        assert_eq!(state(&app).4, Some(1));
+
+        // undoing turns it back, step by step
+        send(&mut app, BuildCommand::Undo);
+        assert_eq!(
+            state(&app),
+            (
+                Some(entity),
+                mirror("low_loss_mirror", 0).placeable,
+                12,
+                Some(2),
+                Some(0)
+            )
+        );
+        send(&mut app, BuildCommand::Undo);
+        assert_eq!(
+            state(&app),
+            (
+                Some(entity),
+                mirror("mirror", 0).placeable,
+                18,
+                Some(1),
+                Some(1)
+            )
+        );
+    }
+
+    #[test]
+    fn bigger_tiers_have_to_fit() {
+        let (mut app, entity) = upgrade_app();
+        send(
+            &mut app,
+            BuildCommand::Do(BuildAction::Place(vec![mirror("mirror", 1)])),
+        );
+        let before = state(&app);
+        let refused = app.world.run_system_once(|checker: BuildChecker| {
+            checker.check_upgrade(&mirror("mirror", 0), &Placeable("wide_mirror".to_string()))
+        });
+        assert!(matches!(refused, Err(PlacementError::Blocked)));
+        send(&mut app, upgrade("mirror", "wide_mirror"));
+        assert_eq!(state(&app), before);
+        let grid = app.world.resource::<GridMap>();
+        assert!(!grid.contains(GridLayer::Build, GridPosition { x: -1, y: 0 }));
+
+        // with the neighbour gone it covers all three cells, its own old one included
+        send(
+            &mut app,
+            BuildCommand::Do(BuildAction::Remove(vec![mirror("mirror", 1)])),
+        );
+        send(&mut app, upgrade("mirror", "wide_mirror"));
+        let grid = app.world.resource::<GridMap>();
+        for x in -1..=1 {
+            assert_eq!(
+                grid.get(GridLayer::Build, GridPosition { x, y: 0 }),
+                Some(&entity)
+            );
+        }
+
+        send(&mut app, BuildCommand::Undo);
+        let grid = app.world.resource::<GridMap>();
+        assert_eq!(
+            grid.get(GridLayer::Build, GridPosition { x: 0, y: 0 }),
+            Some(&entity)
+        );
+        assert!(!grid.contains(GridLayer::Build, GridPosition { x: -1, y: 0 }));
+        assert!(!grid.contains(GridLayer::Build, GridPosition { x: 1, y: 0 }));
+    }
+}
@@ -1,108 +1,522 @@
+use std::{
+    f32::consts::{FRAC_PI_2, TAU},
+    time::Duration,
+};
+
 use bevy::{
     core_pipeline::{bloom::BloomSettings, tonemapping::Tonemapping},
-    input::mouse::{MouseMotion, MouseWheel},
+    input::mouse::MouseMotion,
     prelude::*,
     render::{
         camera::{Exposure, ScalingMode},
         view::ColorGrading,
     },
+    utils::HashMap,
     window::PrimaryWindow,
 };
 
+use crate::{
+    controls::{Action, Actions},
+    grid::{GridLayer, GridMap, GridSettings},
+    hotbar::Hotbar,
+    laser::{Intersection, IntersectorType},
+    Building, DeletionPending, GridPosition, MouseGridPosition,
+};
+
 pub struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
+        app.init_resource::<CameraSettings>()
+            .init_resource::<Viewpoints>()
+            .init_resource::<LastFocused>();
+
         app.add_systems(Startup, spawn_camera);
-        app.add_systems(Update, (move_camera_system, zoom_camera));
+        app.add_systems(
+            Update,
+            (
+                (focus_on_double_click, focus_unlit_receiver, viewpoints),
+                drag_camera,
+                pan_camera,
+                zoom_camera,
+                orbit_camera,
+                glide_camera,
+                clamp_camera,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// How the camera responds to input.
+#[derive(Resource, Clone, Debug)]
+pub struct CameraSettings {
+    /// Use a perspective projection instead of the orthographic one.
+    pub perspective: bool,
+    /// Screen heights per second panned by the keys and the screen edges.
+    pub pan_speed: f32,
+    /// Pan when the cursor touches the edge of the window.
+    pub edge_scroll: bool,
+    /// Width in pixels of the band along the window edge that scrolls.
+    pub edge_margin: f32,
+    /// Fraction the view shrinks or grows by per wheel step.
+    pub zoom_step: f32,
+    /// Height of the view at the focus point, in world units, when zoomed all the way in.
+    pub closest: f32,
+    /// Height of the view at the focus point when zoomed all the way out.
+    pub farthest: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            perspective: false,
+            pan_speed: 0.8,
+            edge_scroll: false,
+            edge_margin: 8.0,
+            zoom_step: 0.1,
+            closest: 3.5,
+            farthest: 10.0,
+        }
     }
 }
 
+/// A view to come back to: what was in the middle, from which side, how close.
+#[derive(Clone, Copy, Debug)]
+pub struct Viewpoint {
+    pub focus: Vec3,
+    pub yaw: f32,
+    pub height: f32,
+}
+
+/// Views stored with `StoreViewpoint`, by number.
+#[derive(Resource, Default, Debug)]
+pub struct Viewpoints(HashMap<u8, Viewpoint>);
+
+/// Receiver `FocusUnlit` went to last, so pressing it again moves on to the next one.
+#[derive(Resource, Default)]
+struct LastFocused(Option<Entity>);
+
 #[derive(Component)]
 pub struct MainCamera;
 
-fn move_camera_system(
+/// How far the camera has been turned around the vertical axis, and how far it should be.
+/// Turning happens around the ground point in the middle of the screen, so whatever the player
+/// is looking at stays put.
+#[derive(Component, Default)]
+pub struct Orbit {
+    pub yaw: f32,
+    pub target_yaw: f32,
+}
+
+/// Ground point and view height the camera is gliding towards, e.g. to focus on a building or
+/// return to a viewpoint. Panning by hand cancels the glide.
+#[derive(Component, Default)]
+pub struct Glide {
+    pub focus: Option<Vec3>,
+    pub height: Option<f32>,
+}
+
+impl Glide {
+    pub fn to(&mut self, focus: Vec3) {
+        self.focus = Some(focus);
+    }
+}
+
+/// Fraction of the remaining turn covered per second, roughly. Higher is snappier.
+const ORBIT_SPEED: f32 = 10.0;
+/// Same for gliding.
+const GLIDE_SPEED: f32 = 6.0;
+/// Longest gap between the clicks of a double-click.
+const DOUBLE_CLICK: Duration = Duration::from_millis(350);
+
+/// Where the view axis through `transform` meets the ground.
+fn ground_focus(transform: &Transform) -> Vec3 {
+    let forward = transform.forward();
+    let t = -transform.translation.y / forward.y;
+    transform.translation + forward * t
+}
+
+/// Height in world units of what the camera shows at its focus point.
+fn view_height(transform: &Transform, projection: &Projection) -> f32 {
+    match projection {
+        Projection::Orthographic(projection) => projection.area.height(),
+        Projection::Perspective(projection) => {
+            let distance = transform.translation.distance(ground_focus(transform));
+            2.0 * distance * (projection.fov / 2.0).tan()
+        }
+    }
+}
+
+/// Where the cursor points on the ground, if it is over the window.
+fn cursor_on_ground(
+    window: &Window,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+) -> Option<Vec3> {
+    let ray = camera.viewport_to_world(camera_transform, window.cursor_position()?)?;
+    let t = ray.intersect_plane(Vec3::ZERO, Plane3d::new(Vec3::Y))?;
+    Some(ray.get_point(t))
+}
+
+/// Drags the view along with the mouse, one view height per window height.
+fn drag_camera(
     primary_windows: Query<&Window, With<PrimaryWindow>>,
     mut ev_motion: EventReader<MouseMotion>,
-    input_mouse: Res<ButtonInput<MouseButton>>,
-    mut query: Query<(&mut Transform, &Projection), With<Camera>>,
+    actions: Res<Actions>,
+    mut query: Query<(&mut Transform, &Projection), With<MainCamera>>,
 ) {
-    let primary_window = primary_windows.single();
-    let window = Vec2::new(primary_window.width(), primary_window.height());
-
-    let mut pan = Vec2::ZERO;
-
-    for ev in ev_motion.read() {
-        pan = ev.delta;
-    }
-
-    if input_mouse.pressed(MouseButton::Right) {
-        for (mut transform, projection) in &mut query {
-            println!("Delta: {:?}", pan);
-            println!("Window: {:?}", window);
-            if let Projection::Orthographic(projection) = projection {
-                // println!("Projection area: {:?}", projection.area);
-                // println!(
-                //     "Width/Height: {:?} / {:?}",
-                //     projection.area.width(),
-                //     projection.area.height()
-                // );
-                // println!(
-                //     "Magic: {:?}",
-                //     Vec2::new(projection.area.width(), projection.area.height()) / window
-                // );
-                pan *= Vec2::new(projection.area.width(), projection.area.height()) / window;
+    let window = primary_windows.single();
+    let motion: Vec2 = ev_motion.read().map(|event| event.delta).sum();
+    if !actions.pressed(Action::Pan) || motion == Vec2::ZERO {
+        return;
+    }
+
+    for (mut transform, projection) in &mut query {
+        let pan = motion * view_height(&transform, projection) / window.height();
+        let translation = transform.right() * -pan.x + transform.up() * pan.y;
+        transform.translation += translation;
+    }
+}
+
+/// Pans with the keys, and with the window edges if enabled. Moves along the ground, so the
+/// camera keeps its height whichever way it faces.
+fn pan_camera(
+    time: Res<Time>,
+    settings: Res<CameraSettings>,
+    actions: Res<Actions>,
+    primary_windows: Query<&Window, With<PrimaryWindow>>,
+    mut query: Query<(&mut Transform, &Projection), With<MainCamera>>,
+) {
+    let mut direction = Vec2::ZERO;
+    for (action, step) in [
+        (Action::PanUp, Vec2::Y),
+        (Action::PanDown, Vec2::NEG_Y),
+        (Action::PanLeft, Vec2::NEG_X),
+        (Action::PanRight, Vec2::X),
+    ] {
+        if actions.pressed(action) {
+            direction += step;
+        }
+    }
+    if settings.edge_scroll {
+        let window = primary_windows.single();
+        if let Some(cursor) = window.cursor_position() {
+            let margin = settings.edge_margin;
+            if cursor.x < margin {
+                direction.x -= 1.0;
+            }
+            if cursor.x > window.width() - margin {
+                direction.x += 1.0;
+            }
+            // window coordinates grow downwards
+            if cursor.y < margin {
+                direction.y += 1.0;
+            }
+            if cursor.y > window.height() - margin {
+                direction.y -= 1.0;
             }
+        }
+    }
+    if direction == Vec2::ZERO {
+        return;
+    }
+    let direction = direction.clamp_length_max(1.0);
+
+    for (mut transform, projection) in &mut query {
+        let right = (*transform.right() * Vec3::new(1.0, 0.0, 1.0)).normalize_or_zero();
+        let forward = (*transform.forward() * Vec3::new(1.0, 0.0, 1.0)).normalize_or_zero();
+        let distance =
+            settings.pan_speed * view_height(&transform, projection) * time.delta_seconds();
+        transform.translation += (right * direction.x + forward * direction.y) * distance;
+    }
+}
+
+/// Zooms one step per wheel notch, keeping the ground under the cursor where it is.
+fn zoom_camera(
+    settings: Res<CameraSettings>,
+    actions: Res<Actions>,
+    hotbar: Res<Hotbar>,
+    primary_windows: Query<&Window, With<PrimaryWindow>>,
+    mut query: Query<
+        (&Camera, &GlobalTransform, &mut Transform, &mut Projection),
+        With<MainCamera>,
+    >,
+) {
+    let mut zoom_delta = 0.0;
+    if actions.just_pressed(Action::ZoomIn) {
+        zoom_delta += 1.0;
+    }
+    if actions.just_pressed(Action::ZoomOut) {
+        zoom_delta -= 1.0;
+    }
+    // the wheel scrolls the hotbar while hovering it
+    if hotbar.hovered() {
+        return;
+    }
 
-            if let Projection::Perspective(projection) = projection {
-                pan *= Vec2::new(projection.fov * projection.aspect_ratio, projection.fov) / window;
+    if zoom_delta == 0. {
+        return;
+    }
+
+    let window = primary_windows.single();
+    for (camera, global_transform, mut transform, mut projection) in &mut query {
+        let height = view_height(&transform, &projection);
+        let target = (height * (1.0 - zoom_delta * settings.zoom_step))
+            .clamp(settings.closest, settings.farthest);
+        let factor = target / height;
+        let focus = ground_focus(&transform);
+        let anchor = cursor_on_ground(window, camera, global_transform).unwrap_or(focus);
+
+        match &mut *projection {
+            Projection::Orthographic(projection) => {
+                projection.scale *= factor;
+                // offsets from the centre shrink with the view, move the centre to make up
+                transform.translation += (anchor - focus) * (1.0 - factor);
+            }
+            Projection::Perspective(_) => {
+                // moving along the ray through the anchor keeps it under the cursor
+                transform.translation = anchor + (transform.translation - anchor) * factor;
             }
+        }
+    }
+}
 
-            let right = transform.rotation * Vec3::X * -pan.x;
-            let up = transform.rotation * Vec3::Y * pan.y;
+/// Turns the camera in quarter steps, easing towards the target so a quick double press just
+/// turns further.
+fn orbit_camera(
+    time: Res<Time>,
+    actions: Res<Actions>,
+    mut query: Query<(&mut Transform, &mut Orbit), With<MainCamera>>,
+) {
+    for (mut transform, mut orbit) in &mut query {
+        if actions.just_pressed(Action::OrbitLeft) {
+            orbit.target_yaw -= FRAC_PI_2;
+        }
+        if actions.just_pressed(Action::OrbitRight) {
+            orbit.target_yaw += FRAC_PI_2;
+        }
 
-            println!("Rotation: {:?}", transform.rotation);
-            println!("Right: {:?}", right);
-            println!("Up: {:?}", up);
+        let remaining = orbit.target_yaw - orbit.yaw;
+        if remaining == 0.0 {
+            continue;
+        }
+        // framerate independent exponential approach, snapping once it is close enough
+        let step = if remaining.abs() < 0.001 {
+            remaining
+        } else {
+            remaining * (1.0 - (-ORBIT_SPEED * time.delta_seconds()).exp())
+        };
+        orbit.yaw += step;
 
-            let translation = right + up;
-            // println!("Translation: {:?}", translation);
+        let focus = ground_focus(&transform);
+        transform.rotate_around(focus, Quat::from_rotation_y(step));
+    }
+}
+
+/// Brings the ground under the screen centre back inside the map, so the view cannot be lost.
+fn clamp_camera(
+    grid_settings: Res<GridSettings>,
+    grid_map: Res<GridMap>,
+    mut query: Query<&mut Transform, With<MainCamera>>,
+) {
+    let bounds = grid_map.bounds();
+    // the corners of a hex map are not its extremes, so take them all
+    let corners = [
+        bounds.min,
+        bounds.max,
+        GridPosition {
+            x: bounds.min.x,
+            y: bounds.max.y,
+        },
+        GridPosition {
+            x: bounds.max.x,
+            y: bounds.min.y,
+        },
+    ]
+    .map(|cell| grid_settings.grid_to_world(cell));
+    let min = corners.iter().copied().reduce(Vec2::min).unwrap();
+    let max = corners.iter().copied().reduce(Vec2::max).unwrap();
 
-            transform.translation += translation;
+    for mut transform in &mut query {
+        let focus = ground_focus(&transform);
+        let clamped = Vec2::new(focus.x, focus.z).clamp(min, max);
+        transform.translation.x += clamped.x - focus.x;
+        transform.translation.z += clamped.y - focus.z;
+    }
+}
+
+/// Eases the camera towards its glide target, ending the glide once it has arrived. Any manual
+/// pan takes over from it.
+fn glide_camera(
+    time: Res<Time>,
+    actions: Res<Actions>,
+    mut query: Query<(&mut Transform, &mut Projection, &mut Glide), With<MainCamera>>,
+) {
+    let panning = [
+        Action::Pan,
+        Action::PanUp,
+        Action::PanDown,
+        Action::PanLeft,
+        Action::PanRight,
+    ]
+    .into_iter()
+    .any(|action| actions.pressed(action));
+    let approach = 1.0 - (-GLIDE_SPEED * time.delta_seconds()).exp();
+
+    for (mut transform, mut projection, mut glide) in &mut query {
+        if panning {
+            glide.focus = None;
+        }
+        if let Some(target) = glide.focus {
+            let focus = ground_focus(&transform);
+            let remaining = target - focus;
+            let step = if remaining.length() < 0.01 {
+                glide.focus = None;
+                remaining
+            } else {
+                remaining * approach
+            };
+            transform.translation += step;
+        }
+        if let Some(target) = glide.height {
+            let height = view_height(&transform, &projection);
+            let factor = if (target - height).abs() < 0.01 {
+                glide.height = None;
+                target / height
+            } else {
+                (target / height).powf(approach)
+            };
+            let focus = ground_focus(&transform);
+            match &mut *projection {
+                Projection::Orthographic(projection) => projection.scale *= factor,
+                Projection::Perspective(_) => {
+                    transform.translation = focus + (transform.translation - focus) * factor;
+                }
+            }
         }
     }
 }
 
-fn zoom_camera(
-    mut ev_scroll: EventReader<MouseWheel>,
-    mut query: Query<&mut Projection, With<Camera>>,
+/// Double-clicking a building centres the view on it.
+fn focus_on_double_click(
+    time: Res<Time>,
+    actions: Res<Actions>,
+    grid_settings: Res<GridSettings>,
+    grid_map: Res<GridMap>,
+    mouse_grid_pos: Res<MouseGridPosition>,
+    buildings: Query<&GridPosition, (With<Building>, Without<DeletionPending>)>,
+    mut last_click: Local<Option<(Duration, GridPosition)>>,
+    mut query: Query<&mut Glide, With<MainCamera>>,
 ) {
-    let max_scale = 3.5;
-    let min_scale = 10.0;
-    let zoom_speed = 0.1;
+    if !actions.just_pressed(Action::Place) {
+        return;
+    }
+    let now = time.elapsed();
+    let cell = mouse_grid_pos.0;
+    let double = last_click.is_some_and(|(at, last)| last == cell && now - at <= DOUBLE_CLICK);
+    // a third click starts a new double-click rather than completing another one
+    *last_click = (!double).then_some((now, cell));
+    if !double {
+        return;
+    }
 
-    let mut zoom_delta = 0.0;
-    for event in ev_scroll.read() {
-        zoom_delta += event.y;
+    let Some(pivot) = grid_map
+        .get(GridLayer::Build, cell)
+        .and_then(|entity| buildings.get(*entity).ok())
+    else {
+        return;
+    };
+    let position = grid_settings.grid_to_world(*pivot);
+    for mut glide in &mut query {
+        glide.to(Vec3::new(position.x, 0.0, position.y));
     }
+}
 
-    if zoom_delta == 0. {
+/// Glides to the receivers no beam reaches, one after the other, wrapping around at the end.
+fn focus_unlit_receiver(
+    actions: Res<Actions>,
+    grid_settings: Res<GridSettings>,
+    mut last_focused: ResMut<LastFocused>,
+    intersections: Query<&Intersection>,
+    receivers: Query<(Entity, &IntersectorType, &GridPosition), Without<DeletionPending>>,
+    mut query: Query<&mut Glide, With<MainCamera>>,
+) {
+    if !actions.just_pressed(Action::FocusUnlit) {
         return;
     }
+    let lit: Vec<Entity> = intersections
+        .iter()
+        .filter_map(Intersection::delivers_to)
+        .collect();
+    let mut unlit: Vec<(Entity, GridPosition)> = receivers
+        .iter()
+        .filter(|(entity, kind, _)| **kind == IntersectorType::Receiver && !lit.contains(entity))
+        .map(|(entity, _, pivot)| (entity, *pivot))
+        .collect();
+    if unlit.is_empty() {
+        info!("Every receiver is lit");
+        return;
+    }
+    unlit.sort_by_key(|(entity, _)| *entity);
 
-    for mut projection in &mut query {
-        if let Projection::Orthographic(projection) = &mut *projection {
-            projection.scale -= zoom_delta * projection.scale * 0.2 * zoom_speed;
+    // the first one after the last focused, wrapping around
+    let (entity, pivot) = last_focused
+        .0
+        .and_then(|last| unlit.iter().find(|(entity, _)| *entity > last))
+        .unwrap_or(&unlit[0]);
+    last_focused.0 = Some(*entity);
+    let position = grid_settings.grid_to_world(*pivot);
+    for mut glide in &mut query {
+        glide.to(Vec3::new(position.x, 0.0, position.y));
+    }
+}
 
-            // clamp scale
-            projection.scale = f32::max(projection.scale, max_scale);
-            projection.scale = f32::min(projection.scale, min_scale);
+/// Stores and recalls numbered views.
+fn viewpoints(
+    actions: Res<Actions>,
+    mut viewpoints: ResMut<Viewpoints>,
+    mut query: Query<(&Transform, &Projection, &mut Orbit, &mut Glide), With<MainCamera>>,
+) {
+    let Ok((transform, projection, mut orbit, mut glide)) = query.get_single_mut() else {
+        return;
+    };
+    for slot in 1..=4 {
+        if actions.just_pressed(Action::StoreViewpoint(slot)) {
+            viewpoints.0.insert(
+                slot,
+                Viewpoint {
+                    focus: ground_focus(transform),
+                    yaw: orbit.target_yaw,
+                    height: view_height(transform, projection),
+                },
+            );
+            info!("Stored viewpoint {}", slot);
+        }
+        if actions.just_pressed(Action::RecallViewpoint(slot)) {
+            let Some(viewpoint) = viewpoints.0.get(&slot) else {
+                continue;
+            };
+            glide.focus = Some(viewpoint.focus);
+            glide.height = Some(viewpoint.height);
+            // turn the short way round, the yaw keeps counting past full turns
+            let turns = ((orbit.target_yaw - viewpoint.yaw) / TAU).round();
+            orbit.target_yaw = viewpoint.yaw + turns * TAU;
         }
     }
 }
 
-fn spawn_camera(mut commands: Commands) {
+fn spawn_camera(mut commands: Commands, settings: Res<CameraSettings>) {
     let translation = Vec3::new(5.0, 5.0, 5.0);
+    let projection = if settings.perspective {
+        Projection::Perspective(PerspectiveProjection::default())
+    } else {
+        Projection::Orthographic(OrthographicProjection {
+            scaling_mode: ScalingMode::FixedVertical(1.0),
+            scale: 4.0,
+            ..default()
+        })
+    };
 
     commands.spawn((
         Camera3dBundle {
@@ -111,11 +525,7 @@ fn spawn_camera(mut commands: Commands) {
                 ..default()
             },
             transform: Transform::from_translation(translation).looking_at(Vec3::ZERO, Vec3::Y),
-            projection: Projection::Orthographic(OrthographicProjection {
-                scaling_mode: ScalingMode::FixedVertical(1.0),
-                scale: 4.0,
-                ..default()
-            }),
+            projection,
             color_grading: ColorGrading {
                 post_saturation: 1.2,
                 ..default()
@@ -128,5 +538,7 @@ fn spawn_camera(mut commands: Commands) {
             ..Default::default()
         },
         MainCamera,
+        Orbit::default(),
+        Glide::default(),
     ));
 }
@@ -0,0 +1,308 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::{
+    building::{insert_behaviour, BuildingRegistry, Placeable},
+    history::apply_build_commands,
+    laser::{ChangedIntersector, LaserUpdateEvent, UpdateType},
+    tween::{Animation, AnimationAction, Tween},
+    DeletionPending, GridPosition, GridSettings,
+};
+
+pub struct ConstructionPlugin;
+
+impl Plugin for ConstructionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ConstructionQueue>();
+
+        // after the frame's building, so replays build at the same pace
+        app.add_systems(Update, advance_construction.after(apply_build_commands));
+    }
+}
+
+/// Whether placed buildings have to be built first, and how fast that goes.
+#[derive(Resource, Clone, Debug)]
+pub struct ConstructionSettings {
+    pub enabled: bool,
+    /// Sites worked on at the same time. The rest wait in the queue.
+    pub builders: usize,
+    /// Seconds of `build_time` each builder gets through per second.
+    pub rate: f32,
+}
+
+impl Default for ConstructionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            builders: 2,
+            rate: 1.0,
+        }
+    }
+}
+
+/// A building that has been paid for and blocks its cells, but does nothing until built. It is
+/// not an intersector yet, so beams pass through it.
+#[derive(Component, Debug)]
+pub struct ConstructionSite {
+    pub progress: f32,
+    pub build_time: f32,
+}
+
+impl ConstructionSite {
+    pub fn new(build_time: f32) -> Self {
+        Self {
+            progress: 0.0,
+            build_time,
+        }
+    }
+
+    pub fn fraction(&self) -> f32 {
+        if self.build_time <= 0.0 {
+            1.0
+        } else {
+            (self.progress / self.build_time).min(1.0)
+        }
+    }
+}
+
+/// Construction sites in the order they were placed. Builders take the front ones.
+#[derive(Resource, Default, Debug)]
+pub struct ConstructionQueue(VecDeque<Entity>);
+
+impl ConstructionQueue {
+    pub fn push(&mut self, site: Entity) {
+        self.0.push_back(site);
+    }
+
+    pub fn contains(&self, site: Entity) -> bool {
+        self.0.contains(&site)
+    }
+}
+
+/// Height a site rises from while it is being built.
+pub const SITE_DEPTH: f32 = -0.4;
+
+/// Appends the gentle throb sites show while they wait for or are worked on by a builder, and
+/// makes `animation` loop. Only the scale is touched, the builders drive the height.
+pub fn with_site_pulse(animation: Animation) -> Animation {
+    animation
+        .then(
+            Tween::new(0.6)
+                .scale(Vec3::splat(0.92))
+                .easing("ease_in_out"),
+        )
+        .then(Tween::new(0.6).scale(Vec3::ONE).easing("ease_in_out"))
+        .looping()
+}
+
+/// Builders work on the oldest sites, raising them out of the floor. Finished buildings leave the
+/// queue and settle into place, then join the beam network; until then they still count as sites.
+/// Demolished sites drop out of the queue, their cost was refunded on removal.
+fn advance_construction(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<ConstructionSettings>,
+    grid_settings: Res<GridSettings>,
+    registry: Res<BuildingRegistry>,
+    mut queue: ResMut<ConstructionQueue>,
+    mut sites: Query<
+        (
+            &mut ConstructionSite,
+            &Placeable,
+            &GridPosition,
+            &mut Transform,
+        ),
+        Without<DeletionPending>,
+    >,
+) {
+    queue.0.retain(|entity| sites.contains(*entity));
+
+    let mut finished = Vec::new();
+    for entity in queue.0.iter().take(settings.builders) {
+        let Ok((mut site, placeable, pivot, mut transform)) = sites.get_mut(*entity) else {
+            continue;
+        };
+        let Some(definition) = registry.get(placeable) else {
+            continue;
+        };
+        site.progress += settings.rate * time.delta_seconds();
+        transform.translation.y = SITE_DEPTH + (definition.height - SITE_DEPTH) * site.fraction();
+
+        if site.fraction() >= 1.0 {
+            finished.push(*entity);
+            let position = grid_settings.grid_to_world(*pivot);
+            let definition = definition.clone();
+            let intersector = definition.intersector;
+            let pivot = *pivot;
+            let mut animation = Animation::from(
+                Tween::new(0.3)
+                    .translation(Vec3::new(position.x, definition.height, position.y))
+                    .scale(Vec3::ONE),
+            )
+            .on_complete(AnimationAction::modify(move |building| {
+                building.remove::<ConstructionSite>();
+                insert_behaviour(building, &definition);
+            }));
+            if let Some(intersector) = intersector {
+                animation =
+                    animation.on_complete(AnimationAction::send(move |entity| LaserUpdateEvent {
+                        update_type: UpdateType::Place,
+                        intersectors: vec![ChangedIntersector {
+                            entity,
+                            intersector,
+                            grid_position: pivot,
+                        }],
+                    }));
+            }
+            commands.entity(*entity).insert(animation);
+        }
+    }
+    queue.0.retain(|entity| !finished.contains(entity));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+    use crate::{
+        economy::Resources,
+        grid::{GridLayer, GridMap, GridShape},
+        history::{BuildAction, BuildCommand, PlacedBuilding},
+        laser::{Intersection, LaserPlugin},
+        testing::build_app,
+        tween::TweenPlugin,
+        ColorWell, LightColor, Orientation,
+    };
+
+    fn placed(placeable: &str, x: i32, y: i32) -> PlacedBuilding {
+        PlacedBuilding {
+            placeable: Placeable(placeable.to_string()),
+            pivot: GridPosition { x, y },
+            orientation: Orientation::default(),
+        }
+    }
+
+    /// Construction on with `builders` builders at twice the normal pace, a tenth of a second
+    /// per frame.
+    fn app(builders: usize) -> App {
+        let mut app = build_app(GridShape::Square);
+        app.add_plugins((LaserPlugin, TweenPlugin))
+            .insert_resource(ConstructionSettings {
+                enabled: true,
+                builders,
+                rate: 2.0,
+            })
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+                100,
+            )))
+            .add_systems(Update, advance_construction.after(apply_build_commands));
+        app
+    }
+
+    fn send(app: &mut App, action: BuildAction) {
+        app.world.send_event(BuildCommand::Do(action));
+        app.update();
+    }
+
+    fn at(app: &App, x: i32, y: i32) -> Option<Entity> {
+        app.world
+            .resource::<GridMap>()
+            .get(GridLayer::Build, GridPosition { x, y })
+            .copied()
+    }
+
+    fn progress(app: &App, site: Entity) -> Option<f32> {
+        app.world
+            .get::<ConstructionSite>(site)
+            .map(|site| site.progress)
+    }
+
+    #[test]
+    fn only_as_many_sites_as_builders_progress() {
+        let mut app = app(2);
+        send(
+            &mut app,
+            BuildAction::Place(vec![
+                placed("storage", 0, 0),
+                placed("storage", 2, 0),
+                placed("storage", 4, 0),
+            ]),
+        );
+        let sites = [at(&app, 0, 0), at(&app, 2, 0), at(&app, 4, 0)].map(Option::unwrap);
+        for _ in 0..10 {
+            app.update();
+        }
+        // a second's work at twice the pace for the first two, nothing for the third
+        let progress = sites.map(|site| progress(&app, site).unwrap());
+        assert!((progress[0] - 2.0).abs() < 1e-3, "{:?}", progress);
+        assert!((progress[1] - 2.0).abs() < 1e-3, "{:?}", progress);
+        assert_eq!(progress[2], 0.0);
+    }
+
+    #[test]
+    fn sites_block_their_cells_but_not_beams_until_built() {
+        let mut app = app(1);
+        let well = app
+            .world
+            .spawn(ColorWell {
+                color: LightColor::Orange,
+            })
+            .id();
+        app.world
+            .resource_mut::<GridMap>()
+            .set(GridLayer::Ground, GridPosition { x: 0, y: 0 }, well)
+            .unwrap();
+        app.world.resource_mut::<ConstructionSettings>().enabled = false;
+        send(
+            &mut app,
+            BuildAction::Place(vec![placed("collector", 0, 0), placed("storage", 0, 4)]),
+        );
+        let (collector, storage) = (at(&app, 0, 0).unwrap(), at(&app, 0, 4).unwrap());
+        let delivers_to = |app: &App| {
+            app.world
+                .get::<Intersection>(collector)
+                .and_then(Intersection::delivers_to)
+        };
+        assert_eq!(delivers_to(&app), Some(storage));
+
+        app.world.resource_mut::<ConstructionSettings>().enabled = true;
+        send(&mut app, BuildAction::Place(vec![placed("wall", 0, 2)]));
+        let wall = at(&app, 0, 2).unwrap();
+        assert!(progress(&app, wall).is_some());
+        send(&mut app, BuildAction::Place(vec![placed("mirror", 0, 2)]));
+        assert_eq!(at(&app, 0, 2), Some(wall));
+        assert_eq!(delivers_to(&app), Some(storage));
+
+        // three seconds of work at twice the pace, then the wall settles and blocks the beam
+        for _ in 0..20 {
+            app.update();
+        }
+        assert!(progress(&app, wall).is_none());
+        assert_eq!(delivers_to(&app), None);
+    }
+
+    #[test]
+    fn removing_a_queued_site_refunds_it() {
+        let mut app = app(1);
+        app.insert_resource(Resources::new([(LightColor::Orange, 20)]));
+        send(
+            &mut app,
+            BuildAction::Place(vec![placed("storage", 0, 0), placed("storage", 2, 0)]),
+        );
+        let queued = at(&app, 2, 0).unwrap();
+        assert!(app.world.resource::<ConstructionQueue>().contains(queued));
+        assert_eq!(app.world.resource::<Resources>().get(LightColor::Orange), 0);
+
+        send(&mut app, BuildAction::Remove(vec![placed("storage", 2, 0)]));
+        assert_eq!(
+            app.world.resource::<Resources>().get(LightColor::Orange),
+            10
+        );
+        assert!(!app.world.resource::<ConstructionQueue>().contains(queued));
+        assert_eq!(at(&app, 2, 0), None);
+    }
+}
@@ -0,0 +1,640 @@
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+use bevy::{
+    input::{mouse::MouseWheel, InputSystem},
+    prelude::*,
+    utils::HashSet,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_bindings(Path::new(BINDINGS_PATH)))
+            .init_resource::<Actions>();
+
+        app.add_systems(PreUpdate, update_actions.after(InputSystem));
+    }
+}
+
+/// Where the bindings are read from. Written with the defaults if it does not exist yet.
+const BINDINGS_PATH: &str = "config/controls.ron";
+
+/// Something the player can do, independent of the button it is bound to.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub enum Action {
+    /// Places the held building, stamps a blueprint, or box-selects when holding nothing.
+    Place,
+    /// Demolishes every building in the dragged box.
+    Demolish,
+    DeleteSelected,
+    /// Drags the view along with the mouse.
+    Pan,
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+    ZoomIn,
+    ZoomOut,
+    /// Turns the view by a quarter around the point in the middle of the screen.
+    OrbitLeft,
+    OrbitRight,
+    /// Glides the view to the next receiver no beam reaches.
+    FocusUnlit,
+    /// Remembers the current view under a number, counting from 1.
+    StoreViewpoint(u8),
+    /// Glides back to a remembered view.
+    RecallViewpoint(u8),
+    /// Turns the building about to be placed.
+    RotateLeft,
+    RotateRight,
+    /// Turns the building under the cursor.
+    RotateBuilding,
+    /// Upgrades the building under the cursor to its next tier.
+    Upgrade,
+    /// Picks the building in a hotbar slot, counting from 1.
+    SelectSlot(u8),
+    Cancel,
+    Undo,
+    Redo,
+    Copy,
+    Paste,
+    Export,
+    Import,
+    Mirror,
+    QuickSave,
+    QuickLoad,
+    /// Leaves the level, free play or the editor for the level select.
+    LevelSelect,
+    /// Switches the level editor between editing and playing the level.
+    Playtest,
+    /// Writes the level being edited to its file.
+    SaveLevel,
+    /// Switches what the editor paints with nothing picked in the hotbar: terrain or wells.
+    NextBrush,
+    /// Cycles the colour of the wells and receivers the editor puts down.
+    NextColor,
+    /// Adds or takes away one of the picked building in the level's inventory.
+    MoreInInventory,
+    FewerInInventory,
+    /// Grows or shrinks the edited map by a column or a row.
+    MapWider,
+    MapNarrower,
+    MapTaller,
+    MapShorter,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Modifier {
+    Ctrl,
+    Shift,
+    Alt,
+}
+
+impl Modifier {
+    fn keys(self) -> [KeyCode; 2] {
+        match self {
+            Modifier::Ctrl => [KeyCode::ControlLeft, KeyCode::ControlRight],
+            Modifier::Shift => [KeyCode::ShiftLeft, KeyCode::ShiftRight],
+            Modifier::Alt => [KeyCode::AltLeft, KeyCode::AltRight],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum InputButton {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    /// The same button on any connected gamepad.
+    Gamepad(GamepadButtonType),
+    /// Counts as pressed for the frame the wheel turns.
+    WheelUp,
+    WheelDown,
+}
+
+/// A button, with the modifier keys that have to be held along with it.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Binding {
+    pub button: InputButton,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modifiers: Vec<Modifier>,
+}
+
+impl Binding {
+    pub fn new(button: InputButton) -> Self {
+        Self {
+            button,
+            modifiers: Vec::new(),
+        }
+    }
+
+    pub fn with(mut self, modifier: Modifier) -> Self {
+        self.modifiers.push(modifier);
+        self
+    }
+
+    /// Whether both bindings react to exactly the same input.
+    fn overlaps(&self, other: &Binding) -> bool {
+        self.button == other.button
+            && self.modifiers.len() == other.modifiers.len()
+            && self
+                .modifiers
+                .iter()
+                .all(|modifier| other.modifiers.contains(modifier))
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for modifier in &self.modifiers {
+            write!(f, "{:?}+", modifier)?;
+        }
+        match self.button {
+            InputButton::Key(key) => {
+                let name = format!("{:?}", key);
+                let name = name
+                    .strip_prefix("Digit")
+                    .or_else(|| name.strip_prefix("Key"))
+                    .unwrap_or(&name);
+                write!(f, "{}", name)
+            }
+            InputButton::Mouse(button) => write!(f, "Mouse {:?}", button),
+            InputButton::Gamepad(button) => write!(f, "Pad {:?}", button),
+            InputButton::WheelUp => write!(f, "Wheel Up"),
+            InputButton::WheelDown => write!(f, "Wheel Down"),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum BindingsError {
+    #[error("could not access bindings file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse bindings: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("could not write bindings: {0}")]
+    Serialize(#[from] ron::Error),
+    #[error("{binding} is bound to both {first:?} and {second:?}")]
+    Conflict {
+        binding: Binding,
+        first: Action,
+        second: Action,
+    },
+}
+
+/// Which inputs trigger which actions. An action can have several bindings.
+#[derive(Resource, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(transparent)]
+pub struct Bindings(BTreeMap<Action, Vec<Binding>>);
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use InputButton::*;
+        use Modifier::*;
+
+        let key = |key| Binding::new(Key(key));
+        let mut bindings = BTreeMap::from([
+            (
+                Action::Place,
+                vec![
+                    Binding::new(Mouse(MouseButton::Left)),
+                    Binding::new(Gamepad(GamepadButtonType::South)),
+                ],
+            ),
+            (
+                Action::Demolish,
+                vec![
+                    Binding::new(Mouse(MouseButton::Right)),
+                    Binding::new(Gamepad(GamepadButtonType::West)),
+                ],
+            ),
+            (Action::DeleteSelected, vec![key(KeyCode::Delete)]),
+            (Action::Pan, vec![Binding::new(Mouse(MouseButton::Middle))]),
+            (
+                Action::PanUp,
+                vec![key(KeyCode::KeyW), key(KeyCode::ArrowUp)],
+            ),
+            (
+                Action::PanDown,
+                vec![key(KeyCode::KeyS), key(KeyCode::ArrowDown)],
+            ),
+            (
+                Action::PanLeft,
+                vec![key(KeyCode::KeyA), key(KeyCode::ArrowLeft)],
+            ),
+            (
+                Action::PanRight,
+                vec![key(KeyCode::KeyD), key(KeyCode::ArrowRight)],
+            ),
+            (Action::ZoomIn, vec![Binding::new(WheelUp)]),
+            (Action::ZoomOut, vec![Binding::new(WheelDown)]),
+            (
+                Action::OrbitLeft,
+                vec![
+                    key(KeyCode::KeyQ).with(Shift),
+                    Binding::new(Gamepad(GamepadButtonType::LeftTrigger2)),
+                ],
+            ),
+            (
+                Action::OrbitRight,
+                vec![
+                    key(KeyCode::KeyE).with(Shift),
+                    Binding::new(Gamepad(GamepadButtonType::RightTrigger2)),
+                ],
+            ),
+            (
+                Action::RotateLeft,
+                vec![
+                    key(KeyCode::KeyQ),
+                    Binding::new(WheelUp).with(Shift),
+                    Binding::new(Gamepad(GamepadButtonType::LeftTrigger)),
+                ],
+            ),
+            (
+                Action::RotateRight,
+                vec![
+                    key(KeyCode::KeyE),
+                    Binding::new(WheelDown).with(Shift),
+                    Binding::new(Gamepad(GamepadButtonType::RightTrigger)),
+                ],
+            ),
+            (
+                Action::RotateBuilding,
+                vec![
+                    key(KeyCode::KeyR),
+                    Binding::new(Gamepad(GamepadButtonType::North)),
+                ],
+            ),
+            (
+                Action::Cancel,
+                vec![
+                    key(KeyCode::Escape),
+                    Binding::new(Gamepad(GamepadButtonType::East)),
+                ],
+            ),
+            (Action::Undo, vec![key(KeyCode::KeyZ).with(Ctrl)]),
+            (
+                Action::Redo,
+                vec![key(KeyCode::KeyZ).with(Ctrl).with(Shift)],
+            ),
+            (Action::Copy, vec![key(KeyCode::KeyC).with(Ctrl)]),
+            (Action::Paste, vec![key(KeyCode::KeyV).with(Ctrl)]),
+            (
+                Action::Export,
+                vec![key(KeyCode::KeyC).with(Ctrl).with(Shift)],
+            ),
+            (
+                Action::Import,
+                vec![key(KeyCode::KeyV).with(Ctrl).with(Shift)],
+            ),
+            (Action::Mirror, vec![key(KeyCode::KeyF)]),
+            (Action::Upgrade, vec![key(KeyCode::KeyU)]),
+            (Action::FocusUnlit, vec![key(KeyCode::Tab)]),
+            (Action::QuickSave, vec![key(KeyCode::F5)]),
+            (Action::QuickLoad, vec![key(KeyCode::F9)]),
+            (Action::LevelSelect, vec![key(KeyCode::Backspace)]),
+            (Action::Playtest, vec![key(KeyCode::F6)]),
+            (Action::SaveLevel, vec![key(KeyCode::KeyS).with(Ctrl)]),
+            (Action::NextBrush, vec![key(KeyCode::KeyB)]),
+            (Action::NextColor, vec![key(KeyCode::KeyC)]),
+            (Action::MoreInInventory, vec![key(KeyCode::Equal)]),
+            (Action::FewerInInventory, vec![key(KeyCode::Minus)]),
+            (Action::MapWider, vec![key(KeyCode::BracketRight)]),
+            (Action::MapNarrower, vec![key(KeyCode::BracketLeft)]),
+            (
+                Action::MapTaller,
+                vec![key(KeyCode::BracketRight).with(Shift)],
+            ),
+            (
+                Action::MapShorter,
+                vec![key(KeyCode::BracketLeft).with(Shift)],
+            ),
+        ]);
+        let digits = [
+            KeyCode::Digit1,
+            KeyCode::Digit2,
+            KeyCode::Digit3,
+            KeyCode::Digit4,
+            KeyCode::Digit5,
+            KeyCode::Digit6,
+            KeyCode::Digit7,
+            KeyCode::Digit8,
+            KeyCode::Digit9,
+            KeyCode::Digit0,
+        ];
+        for (slot, digit) in (1..).zip(digits) {
+            bindings.insert(Action::SelectSlot(slot), vec![key(digit)]);
+        }
+        let function_keys = [KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4];
+        for (slot, function_key) in (1..).zip(function_keys) {
+            bindings.insert(
+                Action::StoreViewpoint(slot),
+                vec![key(function_key).with(Ctrl)],
+            );
+            bindings.insert(Action::RecallViewpoint(slot), vec![key(function_key)]);
+        }
+        Self(bindings)
+    }
+}
+
+impl Bindings {
+    pub fn get(&self, action: Action) -> &[Binding] {
+        self.0.get(&action).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (Action, &Binding)> {
+        self.0
+            .iter()
+            .flat_map(|(action, bindings)| bindings.iter().map(|binding| (*action, binding)))
+    }
+
+    /// Every pair of actions that share a binding.
+    pub fn conflicts(&self) -> Vec<BindingsError> {
+        let bindings: Vec<(Action, &Binding)> = self.iter().collect();
+        let mut conflicts = Vec::new();
+        for (i, (first, binding)) in bindings.iter().enumerate() {
+            for (second, other) in &bindings[i + 1..] {
+                if first != second && binding.overlaps(other) {
+                    conflicts.push(BindingsError::Conflict {
+                        binding: (*binding).clone(),
+                        first: *first,
+                        second: *second,
+                    });
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Reads the bindings from `path`, refusing them if two actions share a binding. Actions the
+    /// file does not mention keep their defaults, so files from before an action existed still
+    /// bind it.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Vec<BindingsError>> {
+        let text = fs::read_to_string(path).map_err(|error| vec![error.into()])?;
+        let mut bindings: Self = ron::from_str(&text).map_err(|error| vec![error.into()])?;
+        for (action, defaults) in Self::default().0 {
+            bindings.0.entry(action).or_insert(defaults);
+        }
+        let conflicts = bindings.conflicts();
+        if conflicts.is_empty() {
+            Ok(bindings)
+        } else {
+            Err(conflicts)
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BindingsError> {
+        if let Some(folder) = path.as_ref().parent() {
+            fs::create_dir_all(folder)?;
+        }
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// The bindings from `path`, or the defaults if there are none or they do not load.
+fn load_bindings(path: &Path) -> Bindings {
+    if !path.exists() {
+        let bindings = Bindings::default();
+        if let Err(error) = bindings.save(path) {
+            warn!(
+                "Can't write default bindings to {}: {}",
+                path.display(),
+                error
+            );
+        }
+        return bindings;
+    }
+
+    match Bindings::load(path) {
+        Ok(bindings) => bindings,
+        Err(errors) => {
+            for error in errors {
+                warn!("{}: {}", path.display(), error);
+            }
+            warn!("Using the default bindings instead");
+            Bindings::default()
+        }
+    }
+}
+
+/// The actions being performed this frame. Systems read this rather than the raw input.
+#[derive(Resource, Default, Debug)]
+pub struct Actions {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    just_released: HashSet<Action>,
+}
+
+impl Actions {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    pub fn just_released(&self, action: Action) -> bool {
+        self.just_released.contains(&action)
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct ButtonState {
+    pressed: bool,
+    just_pressed: bool,
+    just_released: bool,
+}
+
+/// Resolves the raw input into actions. When bindings of the same button differ only in their
+/// modifiers, the one with the most modifiers held wins, so Ctrl+Shift+Z redoes without also
+/// undoing.
+fn update_actions(
+    bindings: Res<Bindings>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    gamepads: Res<Gamepads>,
+    mut ev_scroll: EventReader<MouseWheel>,
+    mut actions: ResMut<Actions>,
+) {
+    let scroll: f32 = ev_scroll.read().map(|event| event.y).sum();
+    let state = |button: InputButton| match button {
+        InputButton::Key(key) => ButtonState {
+            pressed: keys.pressed(key),
+            just_pressed: keys.just_pressed(key),
+            just_released: keys.just_released(key),
+        },
+        InputButton::Mouse(button) => ButtonState {
+            pressed: mouse.pressed(button),
+            just_pressed: mouse.just_pressed(button),
+            just_released: mouse.just_released(button),
+        },
+        InputButton::Gamepad(button_type) => gamepads
+            .iter()
+            .map(|gamepad| GamepadButton::new(gamepad, button_type))
+            .fold(ButtonState::default(), |state, button| ButtonState {
+                pressed: state.pressed || gamepad_buttons.pressed(button),
+                just_pressed: state.just_pressed || gamepad_buttons.just_pressed(button),
+                just_released: state.just_released || gamepad_buttons.just_released(button),
+            }),
+        InputButton::WheelUp | InputButton::WheelDown => {
+            let turned = if button == InputButton::WheelUp {
+                scroll > 0.0
+            } else {
+                scroll < 0.0
+            };
+            ButtonState {
+                pressed: turned,
+                just_pressed: turned,
+                just_released: false,
+            }
+        }
+    };
+    let held = |binding: &Binding| {
+        binding
+            .modifiers
+            .iter()
+            .all(|modifier| keys.any_pressed(modifier.keys()))
+    };
+
+    let active: Vec<(Action, &Binding)> = bindings
+        .iter()
+        .filter(|(_, binding)| held(binding))
+        .collect();
+    let mut pressed = HashSet::new();
+    let mut just_pressed = HashSet::new();
+    let mut just_released = HashSet::new();
+    for (action, binding) in &active {
+        let outranked = active.iter().any(|(_, other)| {
+            other.button == binding.button && other.modifiers.len() > binding.modifiers.len()
+        });
+        if outranked {
+            continue;
+        }
+        let state = state(binding.button);
+        if state.pressed {
+            pressed.insert(*action);
+        }
+        if state.just_pressed {
+            just_pressed.insert(*action);
+        }
+        if state.just_released {
+            just_released.insert(*action);
+        }
+    }
+    // releasing a modifier first still ends the action
+    just_released.extend(actions.pressed.difference(&pressed).copied());
+
+    actions.pressed = pressed;
+    actions.just_pressed = just_pressed;
+    actions.just_released = just_released;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: KeyCode) -> Binding {
+        Binding::new(InputButton::Key(key))
+    }
+
+    #[test]
+    fn default_bindings_do_not_conflict() {
+        let conflicts = Bindings::default().conflicts();
+        assert!(conflicts.is_empty(), "{:?}", conflicts);
+    }
+
+    #[test]
+    fn shared_bindings_are_reported() {
+        let mut bindings = Bindings::default();
+        bindings.0.insert(
+            Action::Undo,
+            vec![key(KeyCode::KeyZ)
+                .with(Modifier::Shift)
+                .with(Modifier::Ctrl)],
+        );
+        let conflicts = bindings.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert!(matches!(
+            conflicts[0],
+            BindingsError::Conflict {
+                first: Action::Undo,
+                second: Action::Redo,
+                ..
+            }
+        ));
+        assert_eq!(
+            conflicts[0].to_string(),
+            "Shift+Ctrl+Z is bound to both Undo and Redo"
+        );
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}_{}.ron", name, std::process::id()))
+    }
+
+    #[test]
+    fn unusable_files_fall_back_to_the_defaults() {
+        let malformed = temp_path("controls_malformed");
+        fs::write(&malformed, "{Place: [(button: Key(").unwrap();
+        assert!(matches!(
+            Bindings::load(&malformed).unwrap_err()[..],
+            [BindingsError::Ron(_)]
+        ));
+        assert_eq!(load_bindings(&malformed), Bindings::default());
+
+        let conflicting = temp_path("controls_conflicting");
+        fs::write(
+            &conflicting,
+            "{Undo: [(button: Key(KeyZ), modifiers: [Ctrl, Shift])]}",
+        )
+        .unwrap();
+        assert!(matches!(
+            Bindings::load(&conflicting).unwrap_err()[..],
+            [BindingsError::Conflict { .. }]
+        ));
+        assert_eq!(load_bindings(&conflicting), Bindings::default());
+
+        fs::remove_file(malformed).unwrap();
+        fs::remove_file(conflicting).unwrap();
+    }
+
+    #[test]
+    fn the_binding_with_the_most_modifiers_held_wins() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .init_resource::<ButtonInput<KeyCode>>()
+            .init_resource::<ButtonInput<MouseButton>>()
+            .init_resource::<ButtonInput<GamepadButton>>()
+            .init_resource::<Gamepads>()
+            .add_event::<MouseWheel>()
+            .init_resource::<Bindings>()
+            .init_resource::<Actions>()
+            .add_systems(Update, update_actions);
+        let press = |app: &mut App, keys: &[KeyCode]| {
+            let mut input = app.world.resource_mut::<ButtonInput<KeyCode>>();
+            input.reset_all();
+            for key in keys {
+                input.press(*key);
+            }
+            app.update();
+        };
+        let actions = |app: &App| {
+            let actions = app.world.resource::<Actions>();
+            [Action::OrbitLeft, Action::RotateLeft, Action::OrbitRight]
+                .map(|action| actions.just_pressed(action))
+        };
+
+        press(&mut app, &[KeyCode::KeyQ]);
+        assert_eq!(actions(&app), [false, true, false]);
+        press(&mut app, &[KeyCode::ShiftLeft, KeyCode::KeyQ]);
+        assert_eq!(actions(&app), [true, false, false]);
+        press(&mut app, &[KeyCode::ShiftRight, KeyCode::KeyQ]);
+        assert_eq!(actions(&app), [true, false, false]);
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    grid::{GridLayer, GridMap},
+    history::apply_build_commands,
+    laser::Intersection,
+    ColorWell, DeletionPending, GridPosition, LightColor,
+};
+
+pub struct EconomyPlugin;
+
+impl Plugin for EconomyPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Resources::new(STARTING_STOCK));
+
+        app.add_systems(Startup, spawn_stock_text);
+        // after the frame's building, so replays harvest the same light
+        app.add_systems(
+            Update,
+            (harvest_light, update_stock_text)
+                .chain()
+                .after(apply_build_commands),
+        );
+    }
+}
+
+/// Light the player starts with, enough for a first storage and a few mirrors.
+pub const STARTING_STOCK: [(LightColor, u32); 1] = [(LightColor::Orange, 20)];
+
+/// Light in stock, per colour. Production trickles in continuously, costs are whole units.
+#[derive(Resource, Clone, Default, Debug, Serialize, Deserialize)]
+pub struct Resources {
+    stock: BTreeMap<LightColor, f32>,
+}
+
+impl Resources {
+    pub fn new(stock: impl IntoIterator<Item = (LightColor, u32)>) -> Self {
+        Self {
+            stock: stock
+                .into_iter()
+                .map(|(color, amount)| (color, amount as f32))
+                .collect(),
+        }
+    }
+
+    /// Whole units of `color` in stock.
+    pub fn get(&self, color: LightColor) -> u32 {
+        self.stock.get(&color).copied().unwrap_or_default() as u32
+    }
+
+    pub fn add(&mut self, color: LightColor, amount: f32) {
+        *self.stock.entry(color).or_default() += amount;
+    }
+
+    pub fn can_afford(&self, cost: &BTreeMap<LightColor, u32>) -> bool {
+        cost.iter()
+            .all(|(color, amount)| self.get(*color) >= *amount)
+    }
+
+    /// Takes `cost` out of the stock, or nothing if there is not enough of every colour.
+    pub fn spend(&mut self, cost: &BTreeMap<LightColor, u32>) -> bool {
+        if !self.can_afford(cost) {
+            return false;
+        }
+        for (color, amount) in cost {
+            self.add(*color, -(*amount as f32));
+        }
+        true
+    }
+
+    pub fn refund(&mut self, cost: &BTreeMap<LightColor, u32>) {
+        for (color, amount) in cost {
+            self.add(*color, *amount as f32);
+        }
+    }
+}
+
+/// Harvests light from the colour well under it while its beam reaches a receiver. Whatever the
+/// mirrors on the way lose does not arrive.
+#[derive(Component, Debug)]
+pub struct Collector {
+    /// Units of light per second.
+    pub rate: f32,
+}
+
+fn harvest_light(
+    time: Res<Time>,
+    grid: Res<GridMap>,
+    mut resources: ResMut<Resources>,
+    collectors: Query<(&Collector, &GridPosition, &Intersection), Without<DeletionPending>>,
+    wells: Query<&ColorWell>,
+) {
+    for (collector, pivot, intersection) in collectors.iter() {
+        if intersection.delivers_to().is_none() {
+            continue;
+        }
+        let Some(well) = grid
+            .get(GridLayer::Ground, *pivot)
+            .and_then(|ground| wells.get(*ground).ok())
+        else {
+            continue;
+        };
+        resources.add(
+            well.color,
+            collector.rate * intersection.delivered() * time.delta_seconds(),
+        );
+    }
+}
+
+#[derive(Component)]
+struct StockText;
+
+fn spawn_stock_text(mut commands: Commands) {
+    commands.spawn((
+        TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Percent(1.),
+                top: Val::Percent(1.),
+                ..default()
+            },
+            ..default()
+        },
+        StockText,
+        Name::new("Stock"),
+    ));
+}
+
+fn update_stock_text(resources: Res<Resources>, mut query: Query<&mut Text, With<StockText>>) {
+    if !resources.is_changed() {
+        return;
+    }
+
+    for mut text in query.iter_mut() {
+        text.sections = resources
+            .stock
+            .keys()
+            .map(|color| TextSection {
+                value: format!("{:?} {}\n", color, resources.get(*color)),
+                style: TextStyle {
+                    font_size: 16.0,
+                    color: color.color(),
+                    ..default()
+                },
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+    use crate::{
+        building::Placeable,
+        grid::GridShape,
+        history::{BuildAction, BuildCommand, PlacedBuilding},
+        laser::LaserPlugin,
+        testing::build_app,
+        Building, Orientation,
+    };
+
+    fn placed(placeable: &str, x: i32, y: i32) -> PlacedBuilding {
+        PlacedBuilding {
+            placeable: Placeable(placeable.to_string()),
+            pivot: GridPosition { x, y },
+            orientation: Orientation::default(),
+        }
+    }
+
+    /// A blue well at the origin and ten orange to build with, a tenth of a second per frame.
+    fn app() -> App {
+        let mut app = build_app(GridShape::Square);
+        app.add_plugins(LaserPlugin)
+            .insert_resource(Resources::new([(LightColor::Orange, 10)]))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+                100,
+            )))
+            .add_systems(Update, harvest_light.after(apply_build_commands));
+        let well = app
+            .world
+            .spawn(ColorWell {
+                color: LightColor::Blue,
+            })
+            .id();
+        app.world
+            .resource_mut::<GridMap>()
+            .set(GridLayer::Ground, GridPosition { x: 0, y: 0 }, well)
+            .unwrap();
+        app
+    }
+
+    fn send(app: &mut App, action: BuildAction) {
+        app.world.send_event(BuildCommand::Do(action));
+        app.update();
+    }
+
+    fn stock(app: &App, color: LightColor) -> u32 {
+        app.world.resource::<Resources>().get(color)
+    }
+
+    fn building_count(app: &mut App) -> usize {
+        app.world
+            .query_filtered::<(), (With<Building>, Without<DeletionPending>)>()
+            .iter(&app.world)
+            .count()
+    }
+
+    #[test]
+    fn collectors_harvest_their_well_while_the_beam_arrives() {
+        let mut app = app();
+        send(
+            &mut app,
+            BuildAction::Place(vec![placed("collector", 0, 0), placed("storage", 0, 3)]),
+        );
+        assert_eq!(stock(&app, LightColor::Orange), 0);
+        for _ in 0..50 {
+            app.update();
+        }
+        // a unit a second for about five seconds
+        let blue = stock(&app, LightColor::Blue);
+        assert!((4..=5).contains(&blue), "harvested {}", blue);
+        assert_eq!(stock(&app, LightColor::Orange), 0);
+    }
+
+    #[test]
+    fn blocked_collectors_harvest_nothing() {
+        let mut app = app();
+        app.insert_resource(Resources::new([(LightColor::Orange, 20)]));
+        send(
+            &mut app,
+            BuildAction::Place(vec![
+                placed("collector", 0, 0),
+                placed("wall", 0, 1),
+                placed("storage", 0, 3),
+            ]),
+        );
+        for _ in 0..50 {
+            app.update();
+        }
+        assert_eq!(stock(&app, LightColor::Blue), 0);
+    }
+
+    #[test]
+    fn buildings_cost_light_and_removing_them_gives_it_back() {
+        let mut app = app();
+        app.insert_resource(Resources::new([(LightColor::Orange, 15)]));
+        send(&mut app, BuildAction::Place(vec![placed("storage", 3, 0)]));
+        assert_eq!(stock(&app, LightColor::Orange), 5);
+
+        // not enough left for a second one
+        send(&mut app, BuildAction::Place(vec![placed("storage", 5, 0)]));
+        assert_eq!(building_count(&mut app), 1);
+        assert_eq!(stock(&app, LightColor::Orange), 5);
+
+        send(&mut app, BuildAction::Remove(vec![placed("storage", 3, 0)]));
+        assert_eq!(building_count(&mut app), 0);
+        assert_eq!(stock(&app, LightColor::Orange), 15);
+    }
+}
@@ -0,0 +1,655 @@
+use std::path::PathBuf;
+
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashSet};
+
+use crate::{
+    building::{BuildRules, Builder, BuildingRegistry, Placeable},
+    controls::{Action, Actions, Bindings},
+    grid::{GridBounds, GridLayer, GridMap, GridSettings},
+    history::PlacedBuilding,
+    hotbar::StatusMessage,
+    laser::{ChangedIntersector, IntersectorType, LaserUpdateEvent, UpdateType},
+    level::{
+        CurrentLevel, Level, LevelBuilding, LevelReceiver, LevelWell, RequiredColor, TERRAIN_HEIGHT,
+    },
+    track_drags,
+    worldgen::{obstacle_assets, spawn_color_well, spawn_obstacle, Obstacle},
+    AppState, Building, ColorWell, DeletionPending, DragStart, Game, GridPosition, LightColor,
+    MouseGridPosition, Orientation,
+};
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(AppState::Editor), spawn_editor_panel);
+        app.add_systems(OnExit(AppState::Editor), despawn_editor_panel);
+        app.add_systems(
+            Update,
+            (
+                (edit_settings, resize_map, save_level),
+                (place_level_buildings, paint_terrain)
+                    .chain()
+                    .after(track_drags),
+                update_editor_panel,
+            )
+                .chain()
+                .run_if(in_state(AppState::Editor)),
+        );
+        app.add_systems(Update, playtest.run_if(resource_exists::<LevelEditor>));
+    }
+}
+
+/// Colours the editor cycles through, in the order of `LightColor`.
+const COLORS: [LightColor; 6] = [
+    LightColor::Red,
+    LightColor::Orange,
+    LightColor::Yellow,
+    LightColor::Green,
+    LightColor::Blue,
+    LightColor::Violet,
+];
+
+/// An editing session on the level in `CurrentLevel`: where it is saved to, and what the editor
+/// puts down when nothing is picked in the hotbar.
+#[derive(Resource)]
+pub struct LevelEditor {
+    path: PathBuf,
+    brush: Brush,
+    /// Colour of the wells and receivers put down next.
+    color: LightColor,
+    /// Cells the `Demolish` drag going on has already taken a layer off.
+    demolished: HashSet<GridPosition>,
+}
+
+impl LevelEditor {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            brush: Brush::Terrain,
+            color: LightColor::Orange,
+            demolished: HashSet::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum Brush {
+    Terrain,
+    Well,
+}
+
+/// What is on the map right now, to be written back into the level.
+#[derive(SystemParam)]
+struct LevelContents<'w, 's> {
+    wells: Query<'w, 's, (&'static GridPosition, &'static ColorWell)>,
+    obstacles: Query<'w, 's, &'static GridPosition, With<Obstacle>>,
+    buildings: Query<
+        'w,
+        's,
+        (
+            &'static Placeable,
+            &'static GridPosition,
+            &'static Orientation,
+            Option<&'static RequiredColor>,
+        ),
+        (With<Building>, Without<DeletionPending>),
+    >,
+}
+
+impl LevelContents<'_, '_> {
+    /// Replaces the terrain, wells and buildings of `level` with the ones on the map. Buildings
+    /// asked for a colour become receivers, the rest fixed buildings.
+    fn capture(&self, level: &mut Level) {
+        level.terrain = self.obstacles.iter().copied().collect();
+        level.wells = self
+            .wells
+            .iter()
+            .map(|(position, well)| LevelWell {
+                position: *position,
+                color: well.color,
+            })
+            .collect();
+        level.fixed.clear();
+        level.receivers.clear();
+        for (placeable, pivot, orientation, requires) in self.buildings.iter() {
+            match requires {
+                Some(requires) => level.receivers.push(LevelReceiver {
+                    placeable: placeable.clone(),
+                    pivot: *pivot,
+                    orientation: *orientation,
+                    color: requires.0,
+                }),
+                None => level.fixed.push(LevelBuilding {
+                    placeable: placeable.clone(),
+                    pivot: *pivot,
+                    orientation: *orientation,
+                }),
+            }
+        }
+
+        // query order is arbitrary, sort so saving twice gives the same file
+        let key = |position: &GridPosition| (position.y, position.x);
+        level.terrain.sort_by_key(key);
+        level.wells.sort_by_key(|well| key(&well.position));
+        level.fixed.sort_by_key(|building| key(&building.pivot));
+        level.receivers.sort_by_key(|receiver| key(&receiver.pivot));
+    }
+}
+
+/// Switches the brush and its colour, and counts the building picked in the hotbar in or out of
+/// the level's inventory. An inventory emptied completely puts no limit on the player.
+fn edit_settings(
+    actions: Res<Actions>,
+    mut editor: ResMut<LevelEditor>,
+    mut current: ResMut<CurrentLevel>,
+    mut game: ResMut<Game>,
+) {
+    if actions.just_pressed(Action::NextBrush) {
+        editor.brush = match editor.brush {
+            Brush::Terrain => Brush::Well,
+            Brush::Well => Brush::Terrain,
+        };
+        // the brushes paint with nothing picked
+        game.current_placeable = None;
+    }
+    if actions.just_pressed(Action::NextColor) {
+        let index = COLORS
+            .iter()
+            .position(|color| *color == editor.color)
+            .unwrap_or_default();
+        editor.color = COLORS[(index + 1) % COLORS.len()];
+    }
+
+    let more = actions.just_pressed(Action::MoreInInventory);
+    let fewer = actions.just_pressed(Action::FewerInInventory);
+    let Some(placeable) = game.current_placeable.clone().filter(|_| more || fewer) else {
+        return;
+    };
+    let Some(level) = current.0.as_mut() else {
+        return;
+    };
+    if more {
+        *level
+            .inventory
+            .get_or_insert_with(default)
+            .entry(placeable.clone())
+            .or_default() += 1;
+    }
+    if fewer {
+        if let Some(counts) = level.inventory.as_mut() {
+            match counts.get_mut(&placeable) {
+                Some(count) if *count > 1 => *count -= 1,
+                _ => {
+                    counts.remove(&placeable);
+                }
+            }
+            if counts.is_empty() {
+                level.inventory = None;
+            }
+        }
+    }
+}
+
+/// Adds or takes away a column or row on the far side of the map. Nothing is cleared to make it
+/// smaller, the edge has to be empty already.
+fn resize_map(
+    actions: Res<Actions>,
+    mut current: ResMut<CurrentLevel>,
+    mut grid_map: ResMut<GridMap>,
+    mut status: EventWriter<StatusMessage>,
+) {
+    let change: IVec2 = [
+        (Action::MapWider, IVec2::X),
+        (Action::MapNarrower, IVec2::NEG_X),
+        (Action::MapTaller, IVec2::Y),
+        (Action::MapShorter, IVec2::NEG_Y),
+    ]
+    .into_iter()
+    .filter(|(action, _)| actions.just_pressed(*action))
+    .map(|(_, change)| change)
+    .sum();
+    if change == IVec2::ZERO {
+        return;
+    }
+    let Some(level) = current.0.as_mut() else {
+        return;
+    };
+
+    let width = (level.width + change.x).max(1);
+    let height = (level.height + change.y).max(1);
+    let bounds = GridBounds::new(
+        GridPosition { x: 0, y: 0 },
+        GridPosition {
+            x: width - 1,
+            y: height - 1,
+        },
+    );
+    let cut_off = [GridLayer::Ground, GridLayer::Build]
+        .into_iter()
+        .flat_map(|layer| grid_map.iter_region(layer, level.bounds()))
+        .any(|(position, _)| !bounds.contains(position));
+    if cut_off {
+        status.send(StatusMessage::new(
+            "Clear the edge of the map before making it smaller",
+        ));
+        return;
+    }
+    level.width = width;
+    level.height = height;
+    grid_map.set_bounds(bounds);
+}
+
+/// `SaveLevel` writes the level as it is on the map to the editor's file.
+fn save_level(
+    actions: Res<Actions>,
+    editor: Res<LevelEditor>,
+    mut current: ResMut<CurrentLevel>,
+    contents: LevelContents,
+) {
+    if !actions.just_pressed(Action::SaveLevel) {
+        return;
+    }
+    let Some(level) = current.0.as_mut() else {
+        return;
+    };
+    contents.capture(level);
+    match level.write(&editor.path) {
+        Ok(()) => info!("Saved level {} to {}", level.name, editor.path.display()),
+        Err(error) => warn!(
+            "Could not save level to {}: {}",
+            editor.path.display(),
+            error
+        ),
+    }
+}
+
+/// Puts down the building picked in the hotbar, turned like its ghost. Receivers are asked for
+/// the editor's colour. `Demolish` takes buildings away on the cells it is dragged over, and
+/// leaves what is under them until the next drag.
+fn place_level_buildings(
+    mut commands: Commands,
+    mut builder: Builder,
+    registry: Res<BuildingRegistry>,
+    mut editor: ResMut<LevelEditor>,
+    game: Res<Game>,
+    actions: Res<Actions>,
+    mouse_grid_pos: Res<MouseGridPosition>,
+    drag: Res<DragStart>,
+    mut status: EventWriter<StatusMessage>,
+) {
+    if drag.demolish.is_some() && actions.pressed(Action::Demolish) {
+        let building = builder
+            .building_at(mouse_grid_pos.0)
+            .filter(|_| !editor.demolished.contains(&mouse_grid_pos.0));
+        if let Some(building) = building {
+            let cells: Vec<GridPosition> = registry
+                .get(&building.placeable)
+                .map(|definition| {
+                    definition
+                        .footprint
+                        .cells(building.pivot, building.orientation, builder.shape())
+                        .collect()
+                })
+                .unwrap_or_default();
+            // the level's buildings were never paid for, so nothing is refunded
+            builder.discard(&[building]);
+            editor.demolished.extend(cells);
+        }
+    } else if !editor.demolished.is_empty() {
+        editor.demolished.clear();
+    }
+
+    if drag.place.is_none() || !actions.just_pressed(Action::Place) {
+        return;
+    }
+    let Some(definition) = game
+        .current_placeable
+        .as_ref()
+        .and_then(|placeable| registry.get(placeable))
+    else {
+        return;
+    };
+    let building = PlacedBuilding {
+        placeable: definition.id.clone(),
+        pivot: mouse_grid_pos.0,
+        orientation: game.orientation,
+    };
+    if let Err(error) = builder.check_site(&building) {
+        status.send(StatusMessage(format!(
+            "Can't place {} here: {}",
+            definition.name, error
+        )));
+        return;
+    }
+    // the level hands its buildings out for free, so neither stock nor inventory is touched
+    if let [Some(entity)] = builder.restore(&[(building, None)])[..] {
+        if definition.intersector == Some(IntersectorType::Receiver) {
+            commands.entity(entity).insert(RequiredColor(editor.color));
+        }
+    }
+}
+
+/// With nothing picked in the hotbar, `Place` paints rock or wells over the cells it is dragged
+/// across, rock only where there is no well. `Demolish` clears the top one off each cell, once
+/// per drag and only where no building was taken away first.
+fn paint_terrain(
+    mut commands: Commands,
+    mut editor: ResMut<LevelEditor>,
+    game: Res<Game>,
+    actions: Res<Actions>,
+    mouse_grid_pos: Res<MouseGridPosition>,
+    drag: Res<DragStart>,
+    settings: Res<GridSettings>,
+    mut grid_map: ResMut<GridMap>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut ev_laser_update: EventWriter<LaserUpdateEvent>,
+    obstacles: Query<(), With<Obstacle>>,
+    mut rock: Local<Option<(Handle<Mesh>, Handle<StandardMaterial>)>>,
+) {
+    let cell = mouse_grid_pos.0;
+
+    if drag.demolish.is_some()
+        && actions.pressed(Action::Demolish)
+        && editor.demolished.insert(cell)
+    {
+        if let Some(entity) = grid_map
+            .get(GridLayer::Build, cell)
+            .copied()
+            .filter(|entity| obstacles.contains(*entity))
+        {
+            grid_map.remove(GridLayer::Build, cell).unwrap();
+            commands.entity(entity).despawn_recursive();
+            ev_laser_update.send(LaserUpdateEvent {
+                update_type: UpdateType::Remove,
+                intersectors: vec![ChangedIntersector {
+                    entity,
+                    intersector: IntersectorType::Blocker,
+                    grid_position: cell,
+                }],
+            });
+        } else if let Some(entity) = grid_map
+            .get(GridLayer::Ground, cell)
+            .copied()
+            .filter(|_| !grid_map.contains(GridLayer::Build, cell))
+        {
+            grid_map.remove(GridLayer::Ground, cell).unwrap();
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    if game.current_placeable.is_some()
+        || drag.place.is_none()
+        || !actions.pressed(Action::Place)
+        || !grid_map.bounds().contains(cell)
+    {
+        return;
+    }
+    match editor.brush {
+        Brush::Terrain
+            if !grid_map.contains(GridLayer::Build, cell)
+                && !grid_map.contains(GridLayer::Ground, cell) =>
+        {
+            let (mesh, material) =
+                rock.get_or_insert_with(|| obstacle_assets(&mut meshes, &mut materials));
+            let entity = spawn_obstacle(
+                &mut commands,
+                &mut grid_map,
+                &settings,
+                cell,
+                TERRAIN_HEIGHT,
+                mesh.clone(),
+                material.clone(),
+            );
+            ev_laser_update.send(LaserUpdateEvent {
+                update_type: UpdateType::Place,
+                intersectors: vec![ChangedIntersector {
+                    entity,
+                    intersector: IntersectorType::Blocker,
+                    grid_position: cell,
+                }],
+            });
+        }
+        Brush::Well if !grid_map.contains(GridLayer::Ground, cell) => {
+            spawn_color_well(
+                &mut commands,
+                &mut grid_map,
+                &settings,
+                cell,
+                editor.color,
+                &mut meshes,
+                &mut materials,
+            );
+        }
+        _ => {}
+    }
+}
+
+/// `Playtest` plays the level as it is on the map, and goes back to editing it as it was left.
+/// Whatever was built while playing is thrown away.
+fn playtest(
+    actions: Res<Actions>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut current: ResMut<CurrentLevel>,
+    contents: LevelContents,
+) {
+    if !actions.just_pressed(Action::Playtest) {
+        return;
+    }
+    match state.get() {
+        AppState::Editor => {
+            if let Some(level) = current.0.as_mut() {
+                contents.capture(level);
+            }
+            next_state.set(AppState::InGame);
+        }
+        AppState::InGame => next_state.set(AppState::Editor),
+        AppState::LevelSelect => {}
+    }
+}
+
+#[derive(Component)]
+struct EditorPanel;
+
+fn spawn_editor_panel(mut commands: Commands) {
+    commands.spawn((
+        TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                right: Val::Percent(1.),
+                top: Val::Percent(6.),
+                padding: UiRect::all(Val::Px(6.)),
+                ..default()
+            },
+            background_color: BackgroundColor(Color::BLACK.with_a(0.5)),
+            ..default()
+        },
+        EditorPanel,
+        Name::new("Editor Panel"),
+    ));
+}
+
+fn despawn_editor_panel(mut commands: Commands, panels: Query<Entity, With<EditorPanel>>) {
+    for panel in panels.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+}
+
+/// Shows the level's size and inventory, what the editor paints with and the keys for it.
+fn update_editor_panel(
+    editor: Res<LevelEditor>,
+    current: Res<CurrentLevel>,
+    game: Res<Game>,
+    bindings: Res<Bindings>,
+    mut panels: Query<&mut Text, With<EditorPanel>>,
+) {
+    if !editor.is_changed() && !current.is_changed() && !game.is_changed() && !bindings.is_changed()
+    {
+        return;
+    }
+    let Some(level) = &current.0 else {
+        return;
+    };
+    let key = |action| {
+        bindings
+            .get(action)
+            .first()
+            .map(ToString::to_string)
+            .unwrap_or_default()
+    };
+
+    let painting = match (&game.current_placeable, editor.brush) {
+        (Some(placeable), _) => format!("Placing {:?}", placeable),
+        (None, Brush::Terrain) => "Painting rock".to_string(),
+        (None, Brush::Well) => "Painting wells".to_string(),
+    };
+    let inventory = match &level.inventory {
+        Some(counts) => counts
+            .iter()
+            .map(|(placeable, count)| format!("\n  {:?} x{}", placeable, count))
+            .collect(),
+        None => " unlimited".to_string(),
+    };
+    let style = |color| TextStyle {
+        font_size: 16.0,
+        color,
+        ..default()
+    };
+    let sections = vec![
+        TextSection::new(
+            format!(
+                "{} ({} x {})\n{} [{}]\n",
+                level.name,
+                level.width,
+                level.height,
+                painting,
+                key(Action::NextBrush),
+            ),
+            style(Color::WHITE),
+        ),
+        TextSection::new(
+            format!("{:?} [{}]\n", editor.color, key(Action::NextColor)),
+            style(editor.color.color()),
+        ),
+        TextSection::new(
+            format!(
+                "Inventory [{}/{}]:{}\n\n{} test play, {} save to {}",
+                key(Action::MoreInInventory),
+                key(Action::FewerInInventory),
+                inventory,
+                key(Action::Playtest),
+                key(Action::SaveLevel),
+                editor.path.display(),
+            ),
+            style(Color::GRAY),
+        ),
+    ];
+    for mut text in panels.iter_mut() {
+        text.sections.clone_from(&sections);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::{
+        grid::GridShape,
+        history::{BuildAction, BuildCommand},
+        testing::build_app,
+    };
+
+    fn cell(x: i32, y: i32) -> GridPosition {
+        GridPosition { x, y }
+    }
+
+    fn placed(placeable: &str, pivot: GridPosition, orientation: u8) -> PlacedBuilding {
+        PlacedBuilding {
+            placeable: Placeable(placeable.to_string()),
+            pivot,
+            orientation: Orientation(orientation),
+        }
+    }
+
+    #[test]
+    fn captured_levels_survive_a_round_trip_through_their_file() {
+        let mut app = build_app(GridShape::Hexagonal);
+        app.world.run_system_once(
+            |mut commands: Commands,
+             mut grid_map: ResMut<GridMap>,
+             settings: Res<GridSettings>,
+             mut meshes: ResMut<Assets<Mesh>>,
+             mut materials: ResMut<Assets<StandardMaterial>>| {
+                for (position, color) in [
+                    (cell(1, 1), LightColor::Blue),
+                    (cell(5, 2), LightColor::Red),
+                ] {
+                    spawn_color_well(
+                        &mut commands,
+                        &mut grid_map,
+                        &settings,
+                        position,
+                        color,
+                        &mut meshes,
+                        &mut materials,
+                    );
+                }
+                let (mesh, material) = obstacle_assets(&mut meshes, &mut materials);
+                for position in [cell(3, 0), cell(3, 1)] {
+                    spawn_obstacle(
+                        &mut commands,
+                        &mut grid_map,
+                        &settings,
+                        position,
+                        TERRAIN_HEIGHT,
+                        mesh.clone(),
+                        material.clone(),
+                    );
+                }
+            },
+        );
+        app.world
+            .send_event(BuildCommand::Do(BuildAction::Place(vec![
+                placed("collector", cell(1, 1), 0),
+                placed("mirror", cell(1, 4), 5),
+                placed("storage", cell(6, 4), 2),
+            ])));
+        app.update();
+        let storage = *app
+            .world
+            .resource::<GridMap>()
+            .get(GridLayer::Build, cell(6, 4))
+            .unwrap();
+        app.world
+            .entity_mut(storage)
+            .insert(RequiredColor(LightColor::Violet));
+
+        let captured = app.world.run_system_once(|contents: LevelContents| {
+            let mut level = Level::blank(GridShape::Hexagonal);
+            contents.capture(&mut level);
+            level
+        });
+        assert_eq!(captured.terrain, [cell(3, 0), cell(3, 1)]);
+        assert_eq!(captured.wells.len(), 2);
+        assert_eq!(captured.fixed.len(), 2);
+        assert_eq!(
+            captured.receivers,
+            [LevelReceiver {
+                placeable: Placeable("storage".to_string()),
+                pivot: cell(6, 4),
+                orientation: Orientation(2),
+                color: LightColor::Violet,
+            }]
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("level_round_trip_{}.ron", std::process::id()));
+        captured.write(&path).unwrap();
+        let read = Level::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read, captured);
+    }
+}
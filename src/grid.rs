@@ -0,0 +1,809 @@
+use std::f32::consts::TAU;
+
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{GridDirection, GridPosition};
+
+/// Side length of a chunk in cells.
+pub const CHUNK_SIZE: i32 = 32;
+const CHUNK_AREA: usize = (CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+/// Tiling of the grid. Hexagonal grids use axial coordinates with pointy-top cells: `x` runs
+/// along world +x and `y` along the direction 60° counter-clockwise from it.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum GridShape {
+    #[default]
+    Square,
+    Hexagonal,
+}
+
+impl GridShape {
+    pub fn direction_count(self) -> u8 {
+        match self {
+            GridShape::Square => 4,
+            GridShape::Hexagonal => 6,
+        }
+    }
+
+    pub fn directions(self) -> impl Iterator<Item = GridDirection> {
+        (0..self.direction_count()).map(GridDirection)
+    }
+
+    /// Angle between neighbouring directions, in radians.
+    pub fn step_angle(self) -> f32 {
+        TAU / self.direction_count() as f32
+    }
+
+    /// Cell offset of one step in `direction`.
+    pub fn offset(self, direction: GridDirection) -> IVec2 {
+        match (self, direction.0) {
+            (GridShape::Square, 0) => IVec2::X,
+            (GridShape::Square, 1) => IVec2::Y,
+            (GridShape::Square, 2) => IVec2::NEG_X,
+            (GridShape::Square, _) => IVec2::NEG_Y,
+            (GridShape::Hexagonal, 0) => IVec2::new(1, 0),
+            (GridShape::Hexagonal, 1) => IVec2::new(0, 1),
+            (GridShape::Hexagonal, 2) => IVec2::new(-1, 1),
+            (GridShape::Hexagonal, 3) => IVec2::new(-1, 0),
+            (GridShape::Hexagonal, 4) => IVec2::new(0, -1),
+            (GridShape::Hexagonal, _) => IVec2::new(1, -1),
+        }
+    }
+
+    /// Turns a cell offset counter-clockwise by one direction step.
+    pub fn rotate_step(self, offset: IVec2) -> IVec2 {
+        match self {
+            GridShape::Square => offset.perp(),
+            GridShape::Hexagonal => IVec2::new(-offset.y, offset.x + offset.y),
+        }
+    }
+
+    /// Mirrors a cell offset across the axis through `GridDirection::FORWARD`.
+    pub fn mirror(self, offset: IVec2) -> IVec2 {
+        match self {
+            GridShape::Square => IVec2::new(-offset.x, offset.y),
+            GridShape::Hexagonal => IVec2::new(-offset.x, offset.x + offset.y),
+        }
+    }
+}
+
+/// How grid cells map onto the world's xz plane.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct GridSettings {
+    /// Distance between the centres of neighbouring cells.
+    pub cell_size: f32,
+    /// World position of the centre of cell (0, 0).
+    pub origin: Vec2,
+    pub shape: GridShape,
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        Self {
+            cell_size: 1.0,
+            origin: Vec2::ZERO,
+            shape: GridShape::Square,
+        }
+    }
+}
+
+impl GridSettings {
+    /// Centre of a cell on the xz plane (world z is returned as `y`).
+    pub fn grid_to_world(&self, position: GridPosition) -> Vec2 {
+        let (x, y) = (position.x as f32, position.y as f32);
+        let local = match self.shape {
+            GridShape::Square => Vec2::new(x, y),
+            GridShape::Hexagonal => Vec2::new(x + y / 2.0, y * 3f32.sqrt() / 2.0),
+        };
+        self.origin + local * self.cell_size
+    }
+
+    /// Cell containing a point on the xz plane.
+    pub fn world_to_grid(&self, world_position: Vec2) -> GridPosition {
+        let local = (world_position - self.origin) / self.cell_size;
+        match self.shape {
+            GridShape::Square => GridPosition {
+                x: local.x.round() as i32,
+                y: local.y.round() as i32,
+            },
+            GridShape::Hexagonal => {
+                let r = local.y * 2.0 / 3f32.sqrt();
+                let q = local.x - r / 2.0;
+                // round in cube coordinates and fix up the component that drifted the most
+                let s = -q - r;
+                let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+                let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+                if dq > dr && dq > ds {
+                    rq = -rr - rs;
+                } else if dr > ds {
+                    rr = -rq - rs;
+                }
+                GridPosition {
+                    x: rq as i32,
+                    y: rr as i32,
+                }
+            }
+        }
+    }
+
+    /// Unit vector on the xz plane pointing along `direction`.
+    pub fn direction_to_world(&self, direction: GridDirection) -> Vec2 {
+        Vec2::from_angle(direction.0 as f32 * self.shape.step_angle())
+    }
+
+    /// Outline of a cell, counter-clockwise; corner `i` closes the edge facing direction `i`.
+    pub fn cell_corners(&self, position: GridPosition) -> impl Iterator<Item = Vec2> {
+        let center = self.grid_to_world(position);
+        let step = self.shape.step_angle();
+        let radius = self.cell_size / 2.0 / (step / 2.0).cos();
+        (0..self.shape.direction_count())
+            .map(move |i| center + Vec2::from_angle((i as f32 + 0.5) * step) * radius)
+    }
+
+    /// The box dragged from `start` to `end`.
+    pub fn region(&self, start: GridPosition, end: GridPosition) -> GridRegion {
+        let (a, b) = (self.grid_to_world(start), self.grid_to_world(end));
+        // centres right on the edge of the box belong to it, whatever the rounding
+        let margin = Vec2::splat(self.cell_size * 1e-3);
+        let (min, max) = (a.min(b) - margin, a.max(b) + margin);
+        let corners = [min, max, Vec2::new(min.x, max.y), Vec2::new(max.x, min.y)]
+            .map(|corner| self.world_to_grid(corner));
+        let bounds = corners
+            .iter()
+            .fold(GridBounds::new(start, end), |bounds, corner| GridBounds {
+                min: GridPosition {
+                    x: bounds.min.x.min(corner.x),
+                    y: bounds.min.y.min(corner.y),
+                },
+                max: GridPosition {
+                    x: bounds.max.x.max(corner.x),
+                    y: bounds.max.y.max(corner.y),
+                },
+            });
+        GridRegion {
+            settings: *self,
+            bounds,
+            min,
+            max,
+        }
+    }
+
+    /// Straight run of cells from `start` towards `end`, along whichever grid direction is
+    /// closest to the line between them. Always contains `start`.
+    pub fn line(
+        &self,
+        start: GridPosition,
+        end: GridPosition,
+    ) -> impl Iterator<Item = GridPosition> {
+        let delta = self.grid_to_world(end) - self.grid_to_world(start);
+        let (direction, length) = self
+            .shape
+            .directions()
+            .map(|direction| (direction, self.direction_to_world(direction).dot(delta)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap();
+        let offset = self.shape.offset(direction);
+        let steps = (length / self.cell_size).round().max(0.0) as i32;
+        (0..=steps).map(move |step| start.offset(offset * step))
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum GridLayer {
+    Ground,
+    Build,
+    Laser,
+}
+
+/// Inclusive rectangle of grid cells.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct GridBounds {
+    pub min: GridPosition,
+    pub max: GridPosition,
+}
+
+impl GridBounds {
+    pub fn new(a: GridPosition, b: GridPosition) -> Self {
+        Self {
+            min: GridPosition {
+                x: a.x.min(b.x),
+                y: a.y.min(b.y),
+            },
+            max: GridPosition {
+                x: a.x.max(b.x),
+                y: a.y.max(b.y),
+            },
+        }
+    }
+
+    /// A `width` by `height` map with cell (0, 0) in the middle.
+    pub fn centred(width: i32, height: i32) -> Self {
+        let min = GridPosition {
+            x: -width / 2,
+            y: -height / 2,
+        };
+        Self::new(
+            min,
+            GridPosition {
+                x: min.x + width - 1,
+                y: min.y + height - 1,
+            },
+        )
+    }
+
+    pub fn contains(&self, position: GridPosition) -> bool {
+        (self.min.x..=self.max.x).contains(&position.x)
+            && (self.min.y..=self.max.y).contains(&position.y)
+    }
+}
+
+impl Default for GridBounds {
+    fn default() -> Self {
+        Self::centred(200, 200)
+    }
+}
+
+/// The cells a box dragged between two cells covers: those with their centre inside the
+/// rectangle on the xz plane spanned by the centres of the two. On square grids this is every
+/// cell of the `GridBounds` between them; on hexagonal ones the rows zigzag to follow the box.
+#[derive(Clone, Copy, Debug)]
+pub struct GridRegion {
+    settings: GridSettings,
+    /// Every cell of the region lies within these.
+    pub bounds: GridBounds,
+    min: Vec2,
+    max: Vec2,
+}
+
+impl GridRegion {
+    pub fn contains(&self, position: GridPosition) -> bool {
+        let centre = self.settings.grid_to_world(position);
+        self.bounds.contains(position)
+            && centre.cmpge(self.min).all()
+            && centre.cmple(self.max).all()
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = GridPosition> + '_ {
+        let bounds = self.bounds;
+        (bounds.min.y..=bounds.max.y)
+            .flat_map(move |y| (bounds.min.x..=bounds.max.x).map(move |x| GridPosition { x, y }))
+            .filter(|position| self.contains(*position))
+    }
+}
+
+/// First occupied cell found by `GridMap::raycast`.
+#[derive(Clone, Copy, Debug)]
+pub struct RaycastHit {
+    pub position: GridPosition,
+    pub entity: Entity,
+    /// Number of cells travelled to reach `position`.
+    pub distance: i32,
+}
+
+/// Chunk coordinate and index of a cell inside that chunk.
+fn chunk_of(position: GridPosition) -> (IVec2, usize) {
+    let chunk = IVec2::new(
+        position.x.div_euclid(CHUNK_SIZE),
+        position.y.div_euclid(CHUNK_SIZE),
+    );
+    let local = IVec2::new(
+        position.x.rem_euclid(CHUNK_SIZE),
+        position.y.rem_euclid(CHUNK_SIZE),
+    );
+    (chunk, (local.y * CHUNK_SIZE + local.x) as usize)
+}
+
+#[derive(Clone)]
+struct Chunk {
+    cells: Box<[Option<Entity>; CHUNK_AREA]>,
+    occupied: usize,
+    /// `GridMap::change_tick` of the last write to this chunk.
+    changed: u32,
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self {
+            cells: Box::new([None; CHUNK_AREA]),
+            occupied: 0,
+            changed: 0,
+        }
+    }
+}
+
+/// Entities on the grid, stored per layer in `CHUNK_SIZE`² chunks and limited to `bounds`.
+#[derive(Resource, Clone, Default)]
+pub struct GridMap {
+    bounds: GridBounds,
+    chunks: HashMap<(GridLayer, IVec2), Chunk>,
+    change_tick: u32,
+}
+
+impl GridMap {
+    pub fn new(bounds: GridBounds) -> Self {
+        Self {
+            bounds,
+            ..default()
+        }
+    }
+
+    pub fn bounds(&self) -> GridBounds {
+        self.bounds
+    }
+
+    /// Moves the edges of the map. Whatever lies outside the new bounds stays where it is, so
+    /// clear it first.
+    pub fn set_bounds(&mut self, bounds: GridBounds) {
+        self.bounds = bounds;
+    }
+
+    /// Incremented on every write; compare against `chunk_changed_since`.
+    pub fn change_tick(&self) -> u32 {
+        self.change_tick
+    }
+
+    pub fn get(&self, layer: GridLayer, position: GridPosition) -> Option<&Entity> {
+        let (chunk, index) = chunk_of(position);
+        self.chunks.get(&(layer, chunk))?.cells[index].as_ref()
+    }
+
+    pub fn set(
+        &mut self,
+        layer: GridLayer,
+        position: GridPosition,
+        value: Entity,
+    ) -> Result<(), ()> {
+        if !self.bounds.contains(position) || self.contains(layer, position) {
+            return Err(());
+        }
+
+        let (chunk, index) = chunk_of(position);
+        self.change_tick += 1;
+        let chunk = self.chunks.entry((layer, chunk)).or_default();
+        chunk.cells[index] = Some(value);
+        chunk.occupied += 1;
+        chunk.changed = self.change_tick;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, layer: GridLayer, position: GridPosition) -> Result<(), ()> {
+        let (chunk, index) = chunk_of(position);
+        let Some(chunk) = self.chunks.get_mut(&(layer, chunk)) else {
+            return Err(());
+        };
+        if chunk.cells[index].take().is_none() {
+            return Err(());
+        }
+
+        self.change_tick += 1;
+        chunk.occupied -= 1;
+        chunk.changed = self.change_tick;
+        Ok(())
+    }
+
+    pub fn contains(&self, layer: GridLayer, position: GridPosition) -> bool {
+        self.get(layer, position).is_some()
+    }
+
+    /// Whether every cell is inside the bounds and unoccupied on `layer`.
+    pub fn is_free(&self, layer: GridLayer, cells: impl IntoIterator<Item = GridPosition>) -> bool {
+        cells
+            .into_iter()
+            .all(|position| self.bounds.contains(position) && !self.contains(layer, position))
+    }
+
+    /// Points every cell of a footprint at `value`, or nothing if any cell is taken.
+    pub fn set_footprint(
+        &mut self,
+        layer: GridLayer,
+        cells: impl IntoIterator<Item = GridPosition>,
+        value: Entity,
+    ) -> Result<(), ()> {
+        let cells: Vec<GridPosition> = cells.into_iter().collect();
+        if !self.is_free(layer, cells.iter().copied()) {
+            return Err(());
+        }
+
+        for position in cells {
+            self.set(layer, position, value)?;
+        }
+        Ok(())
+    }
+
+    pub fn remove_footprint(
+        &mut self,
+        layer: GridLayer,
+        cells: impl IntoIterator<Item = GridPosition>,
+    ) -> Result<(), ()> {
+        for position in cells {
+            self.remove(layer, position)?;
+        }
+        Ok(())
+    }
+
+    /// Occupied cells of `layer` inside `region`, visiting only the chunks it overlaps.
+    pub fn iter_region(
+        &self,
+        layer: GridLayer,
+        region: GridBounds,
+    ) -> impl Iterator<Item = (GridPosition, Entity)> + '_ {
+        let (min_chunk, _) = chunk_of(region.min);
+        let (max_chunk, _) = chunk_of(region.max);
+        (min_chunk.y..=max_chunk.y)
+            .flat_map(move |y| (min_chunk.x..=max_chunk.x).map(move |x| IVec2::new(x, y)))
+            .filter_map(move |chunk| {
+                self.chunks
+                    .get(&(layer, chunk))
+                    .filter(|c| c.occupied > 0)
+                    .map(|c| (chunk, c))
+            })
+            .flat_map(move |(chunk, c)| {
+                c.cells.iter().enumerate().filter_map(move |(index, cell)| {
+                    let position = GridPosition {
+                        x: chunk.x * CHUNK_SIZE + index as i32 % CHUNK_SIZE,
+                        y: chunk.y * CHUNK_SIZE + index as i32 / CHUNK_SIZE,
+                    };
+                    cell.filter(|_| region.contains(position))
+                        .map(|entity| (position, entity))
+                })
+            })
+    }
+
+    /// The cells sharing an edge with `position`, with whatever occupies them.
+    pub fn neighbours(
+        &self,
+        layer: GridLayer,
+        position: GridPosition,
+        shape: GridShape,
+    ) -> impl Iterator<Item = (GridDirection, GridPosition, Option<Entity>)> + '_ {
+        shape.directions().map(move |direction| {
+            let neighbour = position.offset(shape.offset(direction));
+            (direction, neighbour, self.get(layer, neighbour).copied())
+        })
+    }
+
+    /// First occupied cell on `layer` strictly after `from` along `direction`, giving up after
+    /// `max_distance` cells or at the edge of the bounds.
+    pub fn raycast(
+        &self,
+        layer: GridLayer,
+        from: GridPosition,
+        direction: GridDirection,
+        max_distance: i32,
+        shape: GridShape,
+    ) -> Option<RaycastHit> {
+        let mut position = from;
+        for distance in 1..=max_distance {
+            position = position.offset(shape.offset(direction));
+            if !self.bounds.contains(position) {
+                return None;
+            }
+            if let Some(entity) = self.get(layer, position) {
+                return Some(RaycastHit {
+                    position,
+                    entity: *entity,
+                    distance,
+                });
+            }
+        }
+        None
+    }
+
+    /// Coordinates of the chunks of `layer` written to after `tick`.
+    pub fn chunks_changed_since(
+        &self,
+        layer: GridLayer,
+        tick: u32,
+    ) -> impl Iterator<Item = IVec2> + '_ {
+        self.chunks
+            .iter()
+            .filter(move |((chunk_layer, _), chunk)| *chunk_layer == layer && chunk.changed > tick)
+            .map(|((_, coordinate), _)| *coordinate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(x: i32, y: i32) -> GridPosition {
+        GridPosition { x, y }
+    }
+
+    fn entity(index: u32) -> Entity {
+        Entity::from_raw(index)
+    }
+
+    #[test]
+    fn centred_bounds_hold_the_requested_size() {
+        let bounds = GridBounds::centred(500, 500);
+        assert_eq!(bounds.min, cell(-250, -250));
+        assert_eq!(bounds.max, cell(249, 249));
+        assert!(GridMap::new(bounds)
+            .set(GridLayer::Build, cell(249, -250), entity(0))
+            .is_ok());
+
+        let odd = GridBounds::centred(3, 1);
+        assert_eq!((odd.min, odd.max), (cell(-1, 0), cell(1, 0)));
+    }
+
+    #[test]
+    fn set_stays_inside_the_bounds() {
+        let mut grid = GridMap::new(GridBounds::centred(4, 4));
+        assert!(grid.set(GridLayer::Build, cell(2, 0), entity(0)).is_err());
+        assert!(grid.set(GridLayer::Build, cell(1, 1), entity(0)).is_ok());
+        assert!(grid.set(GridLayer::Build, cell(1, 1), entity(1)).is_err());
+        assert!(grid.set(GridLayer::Ground, cell(1, 1), entity(1)).is_ok());
+    }
+
+    #[test]
+    fn raycast_stops_at_the_first_occupied_cell() {
+        let mut grid = GridMap::new(GridBounds::centred(200, 200));
+        grid.set(GridLayer::Build, cell(0, 0), entity(0)).unwrap();
+        grid.set(GridLayer::Build, cell(5, 0), entity(1)).unwrap();
+        grid.set(GridLayer::Build, cell(9, 0), entity(2)).unwrap();
+        grid.set(GridLayer::Ground, cell(3, 0), entity(3)).unwrap();
+
+        let east = GridDirection(0);
+        let hit = grid
+            .raycast(GridLayer::Build, cell(0, 0), east, 20, GridShape::Square)
+            .unwrap();
+        assert_eq!(hit.position, cell(5, 0));
+        assert_eq!(hit.entity, entity(1));
+        assert_eq!(hit.distance, 5);
+
+        let ground = grid
+            .raycast(GridLayer::Ground, cell(0, 0), east, 20, GridShape::Square)
+            .unwrap();
+        assert_eq!(ground.entity, entity(3));
+    }
+
+    #[test]
+    fn raycast_gives_up_at_max_distance_and_bounds() {
+        let mut grid = GridMap::new(GridBounds::centred(20, 20));
+        grid.set(GridLayer::Build, cell(5, 0), entity(0)).unwrap();
+        let east = GridDirection(0);
+        assert!(grid
+            .raycast(GridLayer::Build, cell(0, 0), east, 4, GridShape::Square)
+            .is_none());
+        assert!(grid
+            .raycast(GridLayer::Build, cell(0, 0), east, 5, GridShape::Square)
+            .is_some());
+
+        // nothing to the west before the edge, however far the ray is allowed to go
+        let west = GridDirection(2);
+        assert!(grid
+            .raycast(GridLayer::Build, cell(0, 0), west, 1000, GridShape::Square)
+            .is_none());
+    }
+
+    #[test]
+    fn raycast_follows_hexagonal_directions() {
+        let mut grid = GridMap::new(GridBounds::centred(40, 40));
+        grid.set(GridLayer::Build, cell(-3, 3), entity(0)).unwrap();
+        let hit = grid
+            .raycast(
+                GridLayer::Build,
+                cell(0, 0),
+                GridDirection(2),
+                10,
+                GridShape::Hexagonal,
+            )
+            .unwrap();
+        assert_eq!((hit.position, hit.distance), (cell(-3, 3), 3));
+    }
+
+    #[test]
+    fn iter_region_spans_chunks() {
+        let mut grid = GridMap::new(GridBounds::centred(200, 200));
+        let inside = [
+            cell(-1, -1),
+            cell(0, 0),
+            cell(31, 31),
+            cell(32, 5),
+            cell(40, 40),
+        ];
+        let outside = [cell(-2, 0), cell(41, 0), cell(0, 41), cell(-40, -40)];
+        for (index, &position) in inside.iter().chain(&outside).enumerate() {
+            grid.set(GridLayer::Build, position, entity(index as u32))
+                .unwrap();
+        }
+        grid.set(GridLayer::Ground, cell(1, 1), entity(100))
+            .unwrap();
+
+        let mut found: Vec<(GridPosition, Entity)> = grid
+            .iter_region(
+                GridLayer::Build,
+                GridBounds::new(cell(-1, -1), cell(40, 40)),
+            )
+            .collect();
+        found.sort_by_key(|(position, _)| (position.y, position.x));
+        let mut expected: Vec<(GridPosition, Entity)> = inside
+            .iter()
+            .enumerate()
+            .map(|(index, &position)| (position, entity(index as u32)))
+            .collect();
+        expected.sort_by_key(|(position, _)| (position.y, position.x));
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn iter_region_skips_emptied_cells() {
+        let mut grid = GridMap::new(GridBounds::centred(100, 100));
+        grid.set(GridLayer::Build, cell(3, 3), entity(0)).unwrap();
+        grid.remove(GridLayer::Build, cell(3, 3)).unwrap();
+        let region = GridBounds::new(cell(-50, -50), cell(49, 49));
+        assert_eq!(grid.iter_region(GridLayer::Build, region).count(), 0);
+    }
+
+    #[test]
+    fn neighbours_report_every_direction() {
+        let mut grid = GridMap::new(GridBounds::centred(20, 20));
+        grid.set(GridLayer::Build, cell(1, 0), entity(0)).unwrap();
+        grid.set(GridLayer::Build, cell(1, 1), entity(1)).unwrap();
+
+        let square: Vec<_> = grid
+            .neighbours(GridLayer::Build, cell(0, 0), GridShape::Square)
+            .collect();
+        assert_eq!(
+            square,
+            vec![
+                (GridDirection(0), cell(1, 0), Some(entity(0))),
+                (GridDirection(1), cell(0, 1), None),
+                (GridDirection(2), cell(-1, 0), None),
+                (GridDirection(3), cell(0, -1), None),
+            ]
+        );
+
+        // (1, 1) is not adjacent on either grid, while (1, -1) is on a hexagonal one
+        let hex: Vec<_> = grid
+            .neighbours(GridLayer::Build, cell(0, 0), GridShape::Hexagonal)
+            .collect();
+        assert_eq!(hex.len(), 6);
+        assert_eq!(
+            hex.iter()
+                .filter_map(|(_, position, entity)| entity.map(|_| *position))
+                .collect::<Vec<_>>(),
+            vec![cell(1, 0)]
+        );
+        assert!(hex.iter().any(|(_, position, _)| *position == cell(1, -1)));
+    }
+
+    #[test]
+    fn chunks_changed_since_tracks_writes() {
+        let mut grid = GridMap::new(GridBounds::centred(200, 200));
+        grid.set(GridLayer::Build, cell(0, 0), entity(0)).unwrap();
+        grid.set(GridLayer::Build, cell(-1, 0), entity(1)).unwrap();
+        let tick = grid.change_tick();
+        assert_eq!(grid.chunks_changed_since(GridLayer::Build, tick).count(), 0);
+
+        grid.set(GridLayer::Build, cell(33, 0), entity(2)).unwrap();
+        grid.set(GridLayer::Ground, cell(0, 0), entity(3)).unwrap();
+        assert_eq!(
+            grid.chunks_changed_since(GridLayer::Build, tick)
+                .collect::<Vec<_>>(),
+            vec![IVec2::new(1, 0)]
+        );
+        assert_eq!(
+            grid.chunks_changed_since(GridLayer::Ground, tick)
+                .collect::<Vec<_>>(),
+            vec![IVec2::new(0, 0)]
+        );
+
+        let tick = grid.change_tick();
+        grid.remove(GridLayer::Build, cell(-1, 0)).unwrap();
+        assert_eq!(
+            grid.chunks_changed_since(GridLayer::Build, tick)
+                .collect::<Vec<_>>(),
+            vec![IVec2::new(-1, 0)]
+        );
+        // failed writes change nothing
+        let tick = grid.change_tick();
+        assert!(grid.remove(GridLayer::Build, cell(-1, 0)).is_err());
+        assert!(grid.set(GridLayer::Build, cell(0, 0), entity(4)).is_err());
+        assert_eq!(grid.change_tick(), tick);
+    }
+
+    /// Both shapes, away from the default scale and origin so neither hides a mistake.
+    fn grid_settings() -> [GridSettings; 2] {
+        [GridShape::Square, GridShape::Hexagonal].map(|shape| GridSettings {
+            cell_size: 2.5,
+            origin: Vec2::new(-3.0, 7.25),
+            shape,
+        })
+    }
+
+    fn test_cells() -> impl Iterator<Item = GridPosition> {
+        (-12..=12).flat_map(|y| (-12..=12).map(move |x| cell(x * 7, y * 5)))
+    }
+
+    #[test]
+    fn cell_centres_round_trip() {
+        for settings in grid_settings() {
+            for position in test_cells() {
+                let centre = settings.grid_to_world(position);
+                assert_eq!(
+                    settings.world_to_grid(centre),
+                    position,
+                    "{:?}",
+                    settings.shape
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn points_either_side_of_an_edge() {
+        for settings in grid_settings() {
+            for position in test_cells() {
+                let centre = settings.grid_to_world(position);
+                for direction in settings.shape.directions() {
+                    let towards = settings.direction_to_world(direction) * settings.cell_size;
+                    let neighbour = position.offset(settings.shape.offset(direction));
+                    assert_eq!(settings.world_to_grid(centre + towards * 0.49), position);
+                    assert_eq!(settings.world_to_grid(centre + towards * 0.51), neighbour);
+                    assert!(settings
+                        .grid_to_world(neighbour)
+                        .abs_diff_eq(centre + towards, 1e-3));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn points_near_corners() {
+        for settings in grid_settings() {
+            for position in test_cells() {
+                let centre = settings.grid_to_world(position);
+                for corner in settings.cell_corners(position) {
+                    let inside = corner.lerp(centre, 0.02);
+                    assert_eq!(settings.world_to_grid(inside), position);
+                    let outside = corner.lerp(centre, -0.02);
+                    assert_ne!(settings.world_to_grid(outside), position);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn square_region_matches_bounds() {
+        let settings = GridSettings::default();
+        let region = settings.region(cell(3, -2), cell(-1, 4));
+        let bounds = GridBounds::new(cell(-1, -2), cell(3, 4));
+        assert_eq!(region.bounds, bounds);
+        assert_eq!(region.cells().count(), 5 * 7);
+        assert!(region.cells().all(|position| bounds.contains(position)));
+    }
+
+    #[test]
+    fn hexagonal_region_follows_the_box() {
+        let settings = GridSettings {
+            shape: GridShape::Hexagonal,
+            ..default()
+        };
+        // a drag along a row covers just that row
+        let row = settings.region(cell(0, 0), cell(4, 0));
+        assert_eq!(
+            row.cells().collect::<Vec<_>>(),
+            (0..=4).map(|x| cell(x, 0)).collect::<Vec<_>>()
+        );
+
+        // every cell with its centre in the box, and only those, however the axial bounds lie
+        let start = cell(2, -3);
+        let end = cell(-4, 5);
+        let region = settings.region(start, end);
+        let (a, b) = (settings.grid_to_world(start), settings.grid_to_world(end));
+        let (min, max) = (a.min(b), a.max(b));
+        for y in -20..=20 {
+            for x in -20..=20 {
+                let centre = settings.grid_to_world(cell(x, y));
+                let inside = centre.cmpge(min - 1e-4).all() && centre.cmple(max + 1e-4).all();
+                assert_eq!(region.contains(cell(x, y)), inside, "{:?}", cell(x, y));
+            }
+        }
+        assert!(region.contains(start) && region.contains(end));
+        // the axial bounds between the corners alone hold cells well outside the box
+        assert!(!region.contains(cell(2, 5)));
+    }
+}
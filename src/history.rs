@@ -0,0 +1,410 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    building::{Builder, Placeable},
+    controls::{Action, Actions},
+    hotbar::StatusMessage,
+    GridPosition, Orientation,
+};
+
+/// A building as the history records it: enough to put it back exactly where it was.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct PlacedBuilding {
+    pub placeable: Placeable,
+    pub pivot: GridPosition,
+    pub orientation: Orientation,
+}
+
+/// A reversible change to the buildings on the grid. Buildings are addressed by their pivot
+/// rather than their entity, which does not survive being removed and placed again.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum BuildAction {
+    Place(Vec<PlacedBuilding>),
+    Remove(Vec<PlacedBuilding>),
+    Rotate {
+        pivot: GridPosition,
+        from: Orientation,
+        to: Orientation,
+    },
+    /// Swaps the building at `pivot` for another tier, keeping its entity.
+    Upgrade {
+        pivot: GridPosition,
+        from: Placeable,
+        to: Placeable,
+    },
+}
+
+impl BuildAction {
+    /// The action that takes this one back.
+    pub fn inverse(&self) -> Self {
+        match self {
+            BuildAction::Place(buildings) => {
+                BuildAction::Remove(buildings.iter().rev().cloned().collect())
+            }
+            BuildAction::Remove(buildings) => {
+                BuildAction::Place(buildings.iter().rev().cloned().collect())
+            }
+            BuildAction::Rotate { pivot, from, to } => BuildAction::Rotate {
+                pivot: *pivot,
+                from: *to,
+                to: *from,
+            },
+            BuildAction::Upgrade { pivot, from, to } => BuildAction::Upgrade {
+                pivot: *pivot,
+                from: to.clone(),
+                to: from.clone(),
+            },
+        }
+    }
+
+    /// What is left of this action once `applied`, the part `Builder::apply` carried out, is
+    /// taken away. `None` if it all took effect.
+    pub fn remainder(&self, applied: Option<&BuildAction>) -> Option<BuildAction> {
+        let left = |all: &[PlacedBuilding], done: &[PlacedBuilding]| -> Vec<PlacedBuilding> {
+            all.iter()
+                .filter(|building| !done.contains(building))
+                .cloned()
+                .collect()
+        };
+        match (self, applied) {
+            (_, None) => Some(self.clone()),
+            (BuildAction::Place(all), Some(BuildAction::Place(done))) => {
+                Some(BuildAction::Place(left(all, done))).filter(|_| all.len() > done.len())
+            }
+            (BuildAction::Remove(all), Some(BuildAction::Remove(done))) => {
+                Some(BuildAction::Remove(left(all, done))).filter(|_| all.len() > done.len())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A request to change the buildings, from the player's input or a replay. Input systems only
+/// send these; `apply_build_commands` carries them out in the order they were sent.
+#[derive(Event, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum BuildCommand {
+    Do(BuildAction),
+    Undo,
+    Redo,
+}
+
+/// Actions the player performed, most recent last, and the ones they undid since.
+#[derive(Resource, Default)]
+pub struct History {
+    done: Vec<BuildAction>,
+    undone: Vec<BuildAction>,
+}
+
+impl History {
+    /// Records an action that just took effect. Whatever was undone before is lost.
+    pub fn push(&mut self, action: BuildAction) {
+        self.done.push(action);
+        self.undone.clear();
+    }
+
+    /// Takes back the last action. Whatever part of it cannot be taken back right now, e.g. for
+    /// lack of stock, stays the last action, so the next undo tries it again; that part is
+    /// returned as the error.
+    pub fn undo(&mut self, builder: &mut Builder) -> Result<(), BuildAction> {
+        let Some(action) = self.done.pop() else {
+            return Ok(());
+        };
+        let inverse = action.inverse();
+        let applied = builder.apply(&inverse);
+        if let Some(applied) = &applied {
+            self.undone.push(applied.inverse());
+        }
+        match inverse.remainder(applied.as_ref()) {
+            Some(left) => {
+                let left = left.inverse();
+                self.done.push(left.clone());
+                Err(left)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Forgets everything, e.g. when the buildings it refers to are replaced by a loaded game.
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+    }
+
+    /// Performs the last undone action again. Like `undo`, the part that cannot be done right
+    /// now stays on the stack and is returned as the error.
+    pub fn redo(&mut self, builder: &mut Builder) -> Result<(), BuildAction> {
+        let Some(action) = self.undone.pop() else {
+            return Ok(());
+        };
+        let applied = builder.apply(&action);
+        if let Some(applied) = &applied {
+            self.done.push(applied.clone());
+        }
+        match action.remainder(applied.as_ref()) {
+            Some(left) => {
+                self.undone.push(left.clone());
+                Err(left)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// `Undo` takes back the last building action, `Redo` performs it again.
+pub fn undo_redo(actions: Res<Actions>, mut build_commands: EventWriter<BuildCommand>) {
+    if actions.just_pressed(Action::Undo) {
+        build_commands.send(BuildCommand::Undo);
+    }
+    if actions.just_pressed(Action::Redo) {
+        build_commands.send(BuildCommand::Redo);
+    }
+}
+
+/// Carries out this frame's build commands and records what took effect in the history. The
+/// only place the player's changes to the buildings are made.
+pub fn apply_build_commands(
+    mut build_commands: EventReader<BuildCommand>,
+    mut history: ResMut<History>,
+    mut builder: Builder,
+    mut status: EventWriter<StatusMessage>,
+) {
+    for command in build_commands.read() {
+        match command {
+            BuildCommand::Do(action) => match builder.apply(action) {
+                Some(applied) => history.push(applied),
+                None => {
+                    if let BuildAction::Rotate { pivot, .. } = action {
+                        status.send(StatusMessage(format!(
+                            "Can't rotate the building at {:?} here",
+                            pivot
+                        )));
+                    }
+                }
+            },
+            BuildCommand::Undo => {
+                if let Err(left) = history.undo(&mut builder) {
+                    warn!("Could not undo {:?}", left);
+                    status.send(StatusMessage::new("Can't undo that right now"));
+                }
+            }
+            BuildCommand::Redo => {
+                if let Err(left) = history.redo(&mut builder) {
+                    warn!("Could not redo {:?}", left);
+                    status.send(StatusMessage::new("Can't redo that right now"));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bevy::ecs::system::RunSystemOnce;
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        economy::Resources,
+        grid::{GridLayer, GridMap, GridShape},
+        level::Inventory,
+        testing::build_app,
+        Building, DeletionPending, LightColor,
+    };
+
+    const PLACEABLES: [&str; 4] = ["mirror", "low_loss_mirror", "wall", "storage"];
+
+    /// The buildings standing, sorted so two sets of them compare equal.
+    fn buildings(app: &mut App) -> Vec<PlacedBuilding> {
+        let mut query = app
+            .world
+            .query_filtered::<(&Placeable, &GridPosition, &Orientation), (
+                With<Building>,
+                Without<DeletionPending>,
+            )>();
+        let mut buildings: Vec<PlacedBuilding> = query
+            .iter(&app.world)
+            .map(|(placeable, pivot, orientation)| PlacedBuilding {
+                placeable: placeable.clone(),
+                pivot: *pivot,
+                orientation: *orientation,
+            })
+            .collect();
+        buildings.sort_by_key(|building| (building.pivot.x, building.pivot.y));
+        buildings
+    }
+
+    fn send(app: &mut App, command: BuildCommand) {
+        app.world.send_event(command);
+        app.update();
+    }
+
+    /// Places a few buildings, or removes or turns one that stands. Cells are drawn from a
+    /// small area, so plenty of placements and rotations collide.
+    fn random_action(rng: &mut StdRng, app: &mut App, shape: GridShape) -> Option<BuildAction> {
+        let standing = buildings(app);
+        let random_building = |rng: &mut StdRng| PlacedBuilding {
+            placeable: Placeable(PLACEABLES.choose(rng).unwrap().to_string()),
+            pivot: GridPosition {
+                x: rng.gen_range(-6..=6),
+                y: rng.gen_range(-6..=6),
+            },
+            orientation: Orientation(rng.gen_range(0..shape.direction_count())),
+        };
+        match rng.gen_range(0..4) {
+            0 | 1 => {
+                let count = rng.gen_range(1..=3);
+                Some(BuildAction::Place(
+                    (0..count).map(|_| random_building(rng)).collect(),
+                ))
+            }
+            2 => standing
+                .choose(rng)
+                .map(|building| BuildAction::Remove(vec![building.clone()])),
+            _ => standing.choose(rng).map(|building| BuildAction::Rotate {
+                pivot: building.pivot,
+                from: building.orientation,
+                to: building
+                    .orientation
+                    .turned(rng.gen_range(1..shape.direction_count() as i32), shape),
+            }),
+        }
+    }
+
+    fn undo_all(app: &mut App) {
+        for _ in 0..1000 {
+            if app.world.resource::<History>().done.is_empty() {
+                return;
+            }
+            send(app, BuildCommand::Undo);
+        }
+        panic!("undo never emptied the history");
+    }
+
+    /// Plays a random mix of actions, undos and redos, then undoes everything. The grid has to
+    /// end up empty, and redoing everything has to bring back what stood before.
+    fn undo_back_to_empty(shape: GridShape, seed: u64) {
+        let mut app = build_app(shape);
+        let mut rng = StdRng::seed_from_u64(seed);
+        for _ in 0..150 {
+            let command = match rng.gen_range(0..10) {
+                0 => BuildCommand::Undo,
+                1 => BuildCommand::Redo,
+                _ => match random_action(&mut rng, &mut app, shape) {
+                    Some(action) => BuildCommand::Do(action),
+                    None => continue,
+                },
+            };
+            send(&mut app, command);
+        }
+        let before = buildings(&mut app);
+        assert!(!before.is_empty());
+        // actions undone during play are still there to redo, under the ones undone now
+        let undone_before = app.world.resource::<History>().undone.len();
+
+        undo_all(&mut app);
+        assert_eq!(buildings(&mut app), vec![]);
+        let grid = app.world.resource::<GridMap>();
+        assert_eq!(grid.iter_region(GridLayer::Build, grid.bounds()).count(), 0);
+
+        while app.world.resource::<History>().undone.len() > undone_before {
+            send(&mut app, BuildCommand::Redo);
+        }
+        assert_eq!(buildings(&mut app), before);
+    }
+
+    #[test]
+    fn undo_random_actions_on_square_grid() {
+        for seed in 0..8 {
+            undo_back_to_empty(GridShape::Square, seed);
+        }
+    }
+
+    #[test]
+    fn undo_random_actions_on_hexagonal_grid() {
+        for seed in 0..8 {
+            undo_back_to_empty(GridShape::Hexagonal, seed);
+        }
+    }
+
+    fn mirror_at(x: i32) -> PlacedBuilding {
+        PlacedBuilding {
+            placeable: Placeable("mirror".to_string()),
+            pivot: GridPosition { x, y: 0 },
+            orientation: Orientation::default(),
+        }
+    }
+
+    #[test]
+    fn failed_undo_stays_on_the_stack() {
+        let mut app = build_app(GridShape::Square);
+        let mirrors = vec![mirror_at(0), mirror_at(1)];
+        send(
+            &mut app,
+            BuildCommand::Do(BuildAction::Place(mirrors.clone())),
+        );
+        send(
+            &mut app,
+            BuildCommand::Do(BuildAction::Remove(mirrors.clone())),
+        );
+
+        // putting the mirrors back costs 2 orange each
+        *app.world.resource_mut::<Resources>() = Resources::default();
+        send(&mut app, BuildCommand::Undo);
+        assert_eq!(buildings(&mut app), vec![]);
+        assert_eq!(
+            app.world.resource::<History>().done.last(),
+            Some(&BuildAction::Remove(mirrors.clone()))
+        );
+
+        // enough for one of them: that one comes back, the other is left to undo
+        *app.world.resource_mut::<Resources>() = Resources::new([(LightColor::Orange, 3)]);
+        send(&mut app, BuildCommand::Undo);
+        assert_eq!(buildings(&mut app).len(), 1);
+        let history = app.world.resource::<History>();
+        assert_eq!(history.done.len(), 2);
+        assert_eq!(history.undone.len(), 1);
+
+        app.world
+            .resource_mut::<Resources>()
+            .add(LightColor::Orange, 10.0);
+        undo_all(&mut app);
+        assert_eq!(buildings(&mut app), vec![]);
+    }
+
+    #[test]
+    fn removing_refunds_but_discarding_does_not() {
+        let mut app = build_app(GridShape::Square);
+        let mirror = Placeable("mirror".to_string());
+        app.insert_resource(Resources::new([(LightColor::Orange, 10)]))
+            .insert_resource(Inventory::limited(BTreeMap::from([(mirror.clone(), 2)])));
+        send(
+            &mut app,
+            BuildCommand::Do(BuildAction::Place(vec![mirror_at(0), mirror_at(1)])),
+        );
+        let stock = |app: &App| {
+            (
+                app.world.resource::<Resources>().get(LightColor::Orange),
+                app.world.resource::<Inventory>().count(&mirror),
+            )
+        };
+        assert_eq!(stock(&app), (6, Some(0)));
+
+        send(
+            &mut app,
+            BuildCommand::Do(BuildAction::Remove(vec![mirror_at(0)])),
+        );
+        assert_eq!(stock(&app), (8, Some(1)));
+
+        // the way the level editor takes buildings away
+        let discarded = app
+            .world
+            .run_system_once(|mut builder: Builder| builder.discard(&[mirror_at(1)]));
+        app.update();
+        assert_eq!(discarded, 1);
+        assert_eq!(buildings(&mut app), vec![]);
+        assert_eq!(stock(&app), (8, Some(1)));
+    }
+}
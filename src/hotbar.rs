@@ -0,0 +1,424 @@
+use bevy::{input::mouse::MouseWheel, prelude::*, ui::FocusPolicy};
+
+use crate::{
+    building::{BuildingDefinition, BuildingModel, BuildingRegistry, Placeable},
+    controls::{Action, Actions, Bindings},
+    economy::Resources,
+    level::Inventory,
+    Game,
+};
+
+pub struct HotbarPlugin;
+
+impl Plugin for HotbarPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Hotbar>();
+        app.add_event::<StatusMessage>();
+
+        app.add_systems(Startup, spawn_hotbar);
+        app.add_systems(
+            PreUpdate,
+            track_hotbar_hover.after(bevy::ui::UiSystem::Focus),
+        );
+        app.add_systems(
+            Update,
+            (
+                (scroll_hotbar, build_hotbar_slots, update_hotbar_slots).chain(),
+                select_placeable,
+                show_status,
+            ),
+        );
+    }
+}
+
+/// One slot per `SelectSlot` action; more building types than that scroll.
+const VISIBLE_SLOTS: usize = 10;
+
+const SLOT_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.8);
+const SLOT_HOVERED_COLOR: Color = Color::rgba(0.2, 0.2, 0.2, 0.9);
+const SLOT_SELECTED_COLOR: Color = Color::rgba(0.15, 0.25, 0.4, 0.95);
+
+/// How long a status message stays above the hotbar.
+const STATUS_SECONDS: f32 = 4.0;
+
+/// Feedback for the player, such as why something could not be built. Shown above the hotbar
+/// for a few seconds and written to the log.
+#[derive(Event, Clone, Debug)]
+pub struct StatusMessage(pub String);
+
+impl StatusMessage {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self(message.into())
+    }
+}
+
+/// Scroll position of the hotbar, and whether the pointer is over it.
+#[derive(Resource, Default)]
+pub struct Hotbar {
+    scroll: usize,
+    hovered: bool,
+}
+
+impl Hotbar {
+    /// Clicks and scrolling while this is true belong to the hotbar, not the world.
+    pub fn hovered(&self) -> bool {
+        self.hovered
+    }
+}
+
+/// Every definition the inventory offers, in hotbar order: by `hotbar_slot` first, the rest by
+/// id. Upgrade tiers are reached by upgrading, so they get no slot.
+fn hotbar_order<'a>(
+    registry: &'a BuildingRegistry,
+    inventory: &Inventory,
+) -> Vec<&'a BuildingDefinition> {
+    let upgrades: Vec<&Placeable> = registry
+        .iter()
+        .filter_map(|definition| definition.upgrade.as_ref())
+        .collect();
+    let mut definitions: Vec<&BuildingDefinition> = registry
+        .iter()
+        .filter(|definition| {
+            !upgrades.contains(&&definition.id) && inventory.offers(&definition.id)
+        })
+        .collect();
+    definitions.sort_by_key(|definition| definition.hotbar_slot.unwrap_or(u32::MAX));
+    definitions
+}
+
+#[derive(Component)]
+struct HotbarRoot;
+
+#[derive(Component)]
+struct HotbarSlot(Placeable);
+
+#[derive(Component)]
+struct HotbarCost(Placeable);
+
+/// The latest `StatusMessage`, hidden again once `shown` runs out.
+#[derive(Component)]
+struct StatusText {
+    shown: Timer,
+}
+
+fn spawn_hotbar(mut commands: Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Px(0.),
+                    width: Val::Percent(100.),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(4.),
+                    ..default()
+                },
+                ..default()
+            },
+            Name::new("Hotbar"),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle {
+                    visibility: Visibility::Hidden,
+                    ..TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font_size: 16.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    )
+                    .with_background_color(Color::BLACK.with_a(0.5))
+                },
+                StatusText {
+                    shown: Timer::from_seconds(STATUS_SECONDS, TimerMode::Once),
+                },
+            ));
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        column_gap: Val::Px(4.),
+                        padding: UiRect::all(Val::Px(4.)),
+                        ..default()
+                    },
+                    background_color: BackgroundColor(Color::BLACK.with_a(0.5)),
+                    focus_policy: FocusPolicy::Block,
+                    ..default()
+                },
+                Interaction::default(),
+                HotbarRoot,
+            ));
+        });
+}
+
+fn track_hotbar_hover(mut hotbar: ResMut<Hotbar>, interactions: Query<&Interaction>) {
+    let hovered = interactions
+        .iter()
+        .any(|interaction| *interaction != Interaction::None);
+    if hotbar.hovered != hovered {
+        hotbar.hovered = hovered;
+    }
+}
+
+/// The mouse wheel scrolls the hotbar while the pointer is over it.
+fn scroll_hotbar(
+    mut hotbar: ResMut<Hotbar>,
+    registry: Res<BuildingRegistry>,
+    inventory: Res<Inventory>,
+    mut ev_scroll: EventReader<MouseWheel>,
+) {
+    let scroll: f32 = ev_scroll.read().map(|event| event.y).sum();
+    let max_scroll = hotbar_order(&registry, &inventory)
+        .len()
+        .saturating_sub(VISIBLE_SLOTS);
+    let target = if hotbar.hovered && scroll != 0.0 {
+        hotbar
+            .scroll
+            .saturating_add_signed(-scroll.signum() as isize)
+    } else {
+        hotbar.scroll
+    };
+    // the registry can shrink under the current scroll position
+    let target = target.min(max_scroll);
+    if hotbar.scroll != target {
+        hotbar.scroll = target;
+    }
+}
+
+/// Respawns the visible slots when the scroll position or the building types change.
+fn build_hotbar_slots(
+    mut commands: Commands,
+    hotbar: Res<Hotbar>,
+    registry: Res<BuildingRegistry>,
+    bindings: Res<Bindings>,
+    inventory: Res<Inventory>,
+    roots: Query<Entity, With<HotbarRoot>>,
+) {
+    if !hotbar.is_changed()
+        && !registry.is_changed()
+        && !bindings.is_changed()
+        && !inventory.is_changed()
+    {
+        return;
+    }
+    let Ok(root) = roots.get_single() else {
+        return;
+    };
+
+    let definitions = hotbar_order(&registry, &inventory);
+    let hidden_before = hotbar.scroll > 0;
+    let hidden_after = definitions.len() > hotbar.scroll + VISIBLE_SLOTS;
+    let label = |text: &str, font_size: f32, color: Color| {
+        TextBundle::from_section(
+            text,
+            TextStyle {
+                font_size,
+                color,
+                ..default()
+            },
+        )
+    };
+
+    commands.entity(root).despawn_descendants();
+    commands.entity(root).with_children(|parent| {
+        let arrow_color = Color::WHITE.with_a(if hidden_before { 0.8 } else { 0.1 });
+        parent.spawn(label("<", 24.0, arrow_color).with_style(Style {
+            align_self: AlignSelf::Center,
+            ..default()
+        }));
+
+        let visible = definitions.iter().skip(hotbar.scroll).take(VISIBLE_SLOTS);
+        for (slot, definition) in (1..).zip(visible) {
+            let hotkey = bindings
+                .get(Action::SelectSlot(slot))
+                .first()
+                .map(ToString::to_string)
+                .unwrap_or_default();
+            let icon_color = match &definition.model {
+                BuildingModel::Primitive { material, .. } => material.base_color,
+                BuildingModel::Scene(_) => Color::GRAY,
+            };
+
+            parent
+                .spawn((
+                    ButtonBundle {
+                        style: Style {
+                            width: Val::Px(72.),
+                            flex_direction: FlexDirection::Column,
+                            align_items: AlignItems::Center,
+                            padding: UiRect::all(Val::Px(4.)),
+                            border: UiRect::all(Val::Px(2.)),
+                            ..default()
+                        },
+                        background_color: BackgroundColor(SLOT_COLOR),
+                        ..default()
+                    },
+                    HotbarSlot(definition.id.clone()),
+                    Name::new(format!("Hotbar {}", definition.name)),
+                ))
+                .with_children(|slot| {
+                    slot.spawn(label(&hotkey, 12.0, Color::GRAY).with_style(Style {
+                        align_self: AlignSelf::FlexStart,
+                        ..default()
+                    }));
+                    slot.spawn(NodeBundle {
+                        style: Style {
+                            width: Val::Px(32.),
+                            height: Val::Px(32.),
+                            ..default()
+                        },
+                        background_color: BackgroundColor(icon_color),
+                        ..default()
+                    });
+                    slot.spawn(label(&definition.name, 14.0, Color::WHITE));
+                    slot.spawn((
+                        label("", 12.0, Color::WHITE),
+                        HotbarCost(definition.id.clone()),
+                    ));
+                });
+        }
+
+        let arrow_color = Color::WHITE.with_a(if hidden_after { 0.8 } else { 0.1 });
+        parent.spawn(label(">", 24.0, arrow_color).with_style(Style {
+            align_self: AlignSelf::Center,
+            ..default()
+        }));
+    });
+}
+
+/// Highlights the current placeable and shows each cost next to the stock of that colour, and
+/// how many are left when the inventory is limited. What the player cannot afford is dimmed.
+fn update_hotbar_slots(
+    game: Res<Game>,
+    resources: Res<Resources>,
+    inventory: Res<Inventory>,
+    registry: Res<BuildingRegistry>,
+    mut slots: Query<(
+        &HotbarSlot,
+        &Interaction,
+        &mut BackgroundColor,
+        &mut BorderColor,
+    )>,
+    mut costs: Query<(&HotbarCost, &mut Text)>,
+    new_costs: Query<(), Added<HotbarCost>>,
+) {
+    for (slot, interaction, mut background, mut border) in slots.iter_mut() {
+        let selected = game.current_placeable.as_ref() == Some(&slot.0);
+        background.0 = match (selected, interaction) {
+            (true, _) => SLOT_SELECTED_COLOR,
+            (false, Interaction::None) => SLOT_COLOR,
+            (false, _) => SLOT_HOVERED_COLOR,
+        };
+        border.0 = if selected {
+            crate::SELECTION_COLOR
+        } else {
+            Color::NONE
+        };
+    }
+
+    if !resources.is_changed() && !inventory.is_changed() && new_costs.is_empty() {
+        return;
+    }
+    for (cost, mut text) in costs.iter_mut() {
+        let Some(definition) = registry.get(&cost.0) else {
+            continue;
+        };
+        let mut sections = if definition.cost.is_empty() {
+            vec![TextSection::new(
+                "free",
+                TextStyle {
+                    font_size: 12.0,
+                    color: Color::GRAY,
+                    ..default()
+                },
+            )]
+        } else {
+            definition
+                .cost
+                .iter()
+                .map(|(color, amount)| {
+                    let stock = resources.get(*color);
+                    let alpha = if stock >= *amount { 1.0 } else { 0.35 };
+                    TextSection::new(
+                        format!("{}/{} ", amount, stock),
+                        TextStyle {
+                            font_size: 12.0,
+                            color: color.color().with_a(alpha),
+                            ..default()
+                        },
+                    )
+                })
+                .collect()
+        };
+        if let Some(count) = inventory.count(&definition.id) {
+            let alpha = if count > 0 { 1.0 } else { 0.35 };
+            sections.push(TextSection::new(
+                format!("x{}", count),
+                TextStyle {
+                    font_size: 12.0,
+                    color: Color::WHITE.with_a(alpha),
+                    ..default()
+                },
+            ));
+        }
+        text.sections = sections;
+    }
+}
+
+/// Clicking a slot or its `SelectSlot` action picks that building, `Cancel` puts it away.
+fn select_placeable(
+    mut game: ResMut<Game>,
+    hotbar: Res<Hotbar>,
+    registry: Res<BuildingRegistry>,
+    inventory: Res<Inventory>,
+    actions: Res<Actions>,
+    slots: Query<(&HotbarSlot, &Interaction), Changed<Interaction>>,
+) {
+    for (slot, interaction) in slots.iter() {
+        if *interaction == Interaction::Pressed {
+            game.current_placeable = if game.current_placeable.as_ref() == Some(&slot.0) {
+                None
+            } else {
+                Some(slot.0.clone())
+            };
+        }
+    }
+
+    let visible = hotbar_order(&registry, &inventory)
+        .into_iter()
+        .skip(hotbar.scroll)
+        .take(VISIBLE_SLOTS);
+    for (slot, definition) in (1..).zip(visible) {
+        if actions.just_pressed(Action::SelectSlot(slot)) {
+            game.current_placeable = Some(definition.id.clone());
+        }
+    }
+
+    if actions.just_pressed(Action::Cancel) {
+        game.current_placeable = None;
+    }
+}
+
+/// Logs every status message and shows the latest one above the hotbar.
+fn show_status(
+    time: Res<Time>,
+    mut messages: EventReader<StatusMessage>,
+    mut status: Query<(&mut StatusText, &mut Text, &mut Visibility)>,
+) {
+    let Ok((mut status, mut text, mut visibility)) = status.get_single_mut() else {
+        messages.clear();
+        return;
+    };
+    for message in messages.read() {
+        info!("{}", message.0);
+        text.sections[0].value.clone_from(&message.0);
+        status.shown.reset();
+        *visibility = Visibility::Inherited;
+    }
+    if status.shown.tick(time.delta()).just_finished() {
+        *visibility = Visibility::Hidden;
+    }
+}
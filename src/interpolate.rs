@@ -1,42 +1,72 @@
-use bevy::math::{Vec2, Vec3};
+use bevy::math::{Quat, Vec2, Vec3};
 use flo_curves::bezier::Curve;
 use flo_curves::{BezierCurve, Coord2, Coordinate2D};
+use serde::{Deserialize, Serialize};
 
-fn interpolate(from: f32, to: f32, t: f32) -> f32 {
-    // todo: curve from resource
-    let c = Curve {
-        start_point: Coord2::from((0., 0.)),
-        end_point: Coord2::from((1., 1.)),
-        control_points: (Coord2::from((0.2, 0.75)), Coord2::from((0.68, 1.))),
-    };
-
-    let t = t.clamp(0.0, 1.0);
-    let step = c.point_at_pos(t as f64).y() as f32;
-    from + (to - from) * step
-}
-
+/// Blends linearly from `self` at `t = 0` to `to` at `t = 1`. Easing is applied to `t` first.
 pub(crate) trait Interpolate {
     fn interpolate(&self, to: Self, t: f32) -> Self;
 }
 
 impl Interpolate for f32 {
     fn interpolate(&self, to: f32, t: f32) -> f32 {
-        interpolate(*self, to, t) as f32
+        self + (to - self) * t
     }
 }
 
 impl Interpolate for Vec3 {
     fn interpolate(&self, to: Vec3, t: f32) -> Vec3 {
-        Vec3::new(
-            self.x.interpolate(to.x, t),
-            self.y.interpolate(to.y, t),
-            self.z.interpolate(to.z, t),
-        )
+        self.lerp(to, t)
     }
 }
 
 impl Interpolate for Vec2 {
     fn interpolate(&self, to: Vec2, t: f32) -> Vec2 {
-        Vec2::new(self.x.interpolate(to.x, t), self.y.interpolate(to.y, t))
+        self.lerp(to, t)
+    }
+}
+
+impl Interpolate for Quat {
+    fn interpolate(&self, to: Quat, t: f32) -> Quat {
+        self.slerp(to, t)
+    }
+}
+
+/// How progress through a tween maps to progress towards its target.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Easing {
+    Linear,
+    /// A CSS-style timing curve from (0, 0) to (1, 1) with these two control points.
+    CubicBezier(Vec2, Vec2),
+}
+
+impl Easing {
+    /// Progress towards the target once fraction `t` of the time has passed.
+    pub fn ease(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match *self {
+            Easing::Linear => t,
+            Easing::CubicBezier(first, second) => {
+                let curve = Curve {
+                    start_point: Coord2(0., 0.),
+                    end_point: Coord2(1., 1.),
+                    control_points: (
+                        Coord2(first.x as f64, first.y as f64),
+                        Coord2(second.x as f64, second.y as f64),
+                    ),
+                };
+                // the curve is parametric, so find the parameter at which it reaches x = t
+                let (mut low, mut high) = (0.0, 1.0);
+                for _ in 0..32 {
+                    let mid = (low + high) / 2.0;
+                    if curve.point_at_pos(mid).x() < t as f64 {
+                        low = mid;
+                    } else {
+                        high = mid;
+                    }
+                }
+                curve.point_at_pos((low + high) / 2.0).y() as f32
+            }
+        }
     }
 }
@@ -1,10 +1,16 @@
 use std::borrow::BorrowMut;
 
-use bevy::{pbr::NotShadowCaster, prelude::*};
+use bevy::{pbr::NotShadowCaster, prelude::*, utils::HashMap};
 use bevy_inspector_egui::InspectorOptions;
 use rand::random;
+use serde::{Deserialize, Serialize};
 
-use crate::{AnimateTransform, GridLayer, GridMap, GridPosition};
+use crate::{
+    grid::{GridLayer, GridMap, GridSettings, GridShape},
+    tween::{Animation, Tween},
+    ColorWell, DeletionPending, Footprint, FootprintEntry, GridDirection, GridPosition, LightColor,
+    Orientation,
+};
 
 pub struct LaserPlugin;
 
@@ -12,260 +18,380 @@ impl Plugin for LaserPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Update, animate_laser);
 
-        app.add_systems(Update, update_laser);
-        app.add_systems(Update, intersection_system);
+        // buildings are spawned through commands during Update, so trace once they exist
+        app.add_systems(PostUpdate, update_laser);
 
         app.add_event::<LaserUpdateEvent>();
 
         app.register_type::<Laser>();
         app.register_type::<Intersection>();
         app.register_type::<IntersectorType>();
+        app.register_type::<Efficiency>();
     }
 }
 
+/// Number of cells a beam travels before it fades out.
+const MAX_BEAM_LENGTH: i32 = 10;
+
+/// Upper bound on segments per emitter, so mirror loops terminate.
+const MAX_BOUNCES: usize = 32;
+
 #[derive(Component, InspectorOptions, Reflect, Debug, Copy, Clone)]
 pub struct Laser {
     pub source: Option<Entity>,
     pub from_intersector: Option<Entity>,
     pub to_intersector: Option<Entity>,
     pub index: usize,
-    pub direction: Vec2,
+    pub direction: GridDirection,
     pub start: GridPosition,
     pub end: GridPosition,
+    /// Colour of the well under the emitter, `None` for emitters that stand on none.
+    pub color: Option<LightColor>,
 }
 
-impl Default for Laser {
-    fn default() -> Self {
-        Laser {
-            source: None,
-            from_intersector: None,
-            to_intersector: None,
-            index: 0,
-            direction: Vec2::ZERO,
-            start: GridPosition { x: 0, y: 0 },
-            end: GridPosition { x: 0, y: 0 },
-        }
-    }
-}
-
-#[derive(Component, Debug, Reflect, InspectorOptions)]
-struct Intersection {
+#[derive(Component, Debug, Default, Reflect, InspectorOptions)]
+pub struct Intersection {
     source: Option<Entity>,
     laser_in: Option<Entity>,
     laser_out: Option<Entity>,
-    laser_out_direction: Vec2,
+    laser_out_direction: Option<GridDirection>,
     from: Option<Entity>,
     to: Option<Entity>,
+    /// Footprint cell and face (in the building's own frame) the incoming beam enters through.
+    entry: Option<FootprintEntry>,
+    /// Receiver at the end of the beam this building emits, if the beam reaches one.
+    delivers_to: Option<Entity>,
+    /// Share of the emitted light that arrives at `delivers_to`.
+    delivered: f32,
 }
 
-impl Default for Intersection {
-    fn default() -> Self {
-        Intersection {
-            source: None,
-            laser_in: None,
-            laser_out: None,
-            laser_out_direction: Vec2::ZERO,
-            from: None,
-            to: None,
-        }
+impl Intersection {
+    pub fn delivers_to(&self) -> Option<Entity> {
+        self.delivers_to
+    }
+
+    pub fn delivered(&self) -> f32 {
+        self.delivered
     }
 }
 
+/// Share of a beam's light that makes it past an intersector, e.g. off a mirror. Intersectors
+/// without one lose nothing.
+#[derive(Component, Debug, Clone, Copy, Reflect)]
+pub struct Efficiency(pub f32);
+
 #[derive(Debug)]
 pub enum UpdateType {
     Remove,
-    Update,
     Place,
+    Rotate,
+    Upgrade,
 }
 
-#[derive(Debug, Component, Copy, Clone, Reflect, PartialEq)]
+#[derive(Debug, Component, Copy, Clone, Reflect, PartialEq, Serialize, Deserialize)]
 pub enum IntersectorType {
     Emitter,
     Reflector,
+    /// Absorbs beams without doing anything with them.
+    Blocker,
+    /// Absorbs beams and stores their light, which is what makes collectors produce.
+    Receiver,
 }
 
+/// Intersectors placed or removed by one player action. Beams are retraced once per frame no
+/// matter how many arrive, so batch actions send a single event.
 #[derive(Event, Debug)]
 pub struct LaserUpdateEvent {
-    pub entity: Entity,
     pub update_type: UpdateType,
+    pub intersectors: Vec<ChangedIntersector>,
+}
+
+#[derive(Debug)]
+pub struct ChangedIntersector {
+    pub entity: Entity,
     pub intersector: IntersectorType,
     pub grid_position: GridPosition,
 }
 
-#[derive(Component)]
-struct UpdatePending;
+/// The parts of a building the tracer needs to route a beam through it.
+#[derive(Clone, Copy)]
+pub struct TracedIntersector<'a> {
+    pub kind: IntersectorType,
+    pub pivot: GridPosition,
+    pub orientation: Orientation,
+    pub footprint: &'a Footprint,
+}
 
-fn intersection_system(
-    mut commands: Commands,
-    grid: Res<GridMap>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut set: ParamSet<(
-        Query<(Entity, &mut Intersection, &IntersectorType), With<UpdatePending>>,
-        Query<&mut Intersection>,
-    )>,
-) {
-    let mut laser_entity: Option<Entity> = None;
-    let mut source: Option<Entity> = None;
-    let mut from_intersector: Option<Entity> = None;
-    let mut to_intersector: Option<Entity> = None;
-    for (intersection_entity, mut intersection, intersection_type) in set.p0().iter_mut() {
-        // remove update pending
-        commands
-            .entity(intersection_entity)
-            .remove::<UpdatePending>();
-        println!("Update intersection: {:?}", intersection_entity);
-
-        // remove all connected lasers
-        // if let Some(laser_in) = intersection.laser_in {
-        //     println!("Remove laser in: {:?}", laser_in);
-        //     commands.entity(laser_in).despawn_recursive();
-        // }
-        //
-        // if let Some(laser_out) = intersection.laser_out {
-        //     println!("Remove laser out: {:?}", laser_out);
-        //     commands.entity(laser_out).despawn_recursive();
-        // }
-
-        if let Some(to_intersector_entity) = intersection.to {
-            to_intersector = Some(to_intersector_entity);
-        }
+/// One straight piece of a traced beam.
+#[derive(Debug)]
+pub struct BeamSegment {
+    pub laser: Laser,
+    pub entry: Option<FootprintEntry>,
+}
 
-        match intersection_type {
-            IntersectorType::Emitter => {
-                source = Some(intersection_entity);
-                from_intersector = Some(intersection_entity);
+/// Walks the beam of `source` across the build layer, reflecting off mirrors until it is
+/// absorbed, fades out or exceeds `MAX_BOUNCES`. Entities `intersectors` does not know about
+/// (e.g. buildings pending deletion) are transparent.
+pub fn trace_beam<'a>(
+    grid: &GridMap,
+    shape: GridShape,
+    source: Entity,
+    intersectors: impl Fn(Entity) -> Option<TracedIntersector<'a>>,
+) -> Vec<BeamSegment> {
+    let mut segments = Vec::new();
+    let Some(emitter) = intersectors(source) else {
+        return segments;
+    };
+
+    let mut direction = GridDirection::FORWARD.rotated(emitter.orientation, shape);
+    let mut from = source;
+    let mut start = emitter.pivot;
+
+    for index in 0..MAX_BOUNCES {
+        let mut end = start;
+        let mut remaining = MAX_BEAM_LENGTH;
+        let mut hit = None;
+        while let Some(ray) = grid.raycast(GridLayer::Build, end, direction, remaining, shape) {
+            end = ray.position;
+            remaining -= ray.distance;
+            // pass through the footprint the beam left from
+            if ray.entity == from {
+                continue;
             }
-            IntersectorType::Reflector => {
-                if intersection.laser_in.is_none() {
-                    return;
-                }
+            if let Some(intersector) = intersectors(ray.entity) {
+                hit = Some((ray.entity, intersector));
+                break;
             }
         }
-
-        // spawn laser (check for collisions in laser_direction)
-        let max_length = 10;
-        let start_pos = GridPosition {
-            x: intersection.laser_out_direction.x as i32,
-            y: intersection.laser_out_direction.y as i32,
-        };
-        let mut end_pos = GridPosition {
-            x: intersection.laser_out_direction.x as i32 * max_length,
-            y: intersection.laser_out_direction.y as i32 * max_length,
-        };
-
-        for i in 1..max_length {
-            let next_grid_pos = GridPosition {
-                x: intersection.laser_out_direction.x as i32 * i,
-                y: intersection.laser_out_direction.y as i32 * i,
-            };
-            if grid.contains(GridLayer::Build, next_grid_pos) {
-                end_pos = next_grid_pos;
-                // insert update pending
-                let entity = grid.get(GridLayer::Build, next_grid_pos).unwrap();
-                to_intersector = Some(*entity);
+        if hit.is_none() {
+            // nothing in the way, fade out after the remaining length or at the edge of the map
+            for _ in 0..remaining {
+                let next = end.offset(shape.offset(direction));
+                if !grid.bounds().contains(next) {
+                    break;
+                }
+                end = next;
             }
         }
+        if end == start {
+            break;
+        }
 
-        let laser = spawn_laser(
-            &mut commands,
-            Laser {
-                source,
-                from_intersector,
-                to_intersector,
-                index: 0,
-                direction: intersection.laser_out_direction,
-                start: start_pos,
-                end: end_pos,
+        let entry = hit.and_then(|(_, intersector)| {
+            intersector.footprint.entry(
+                intersector.pivot,
+                intersector.orientation,
+                end,
+                direction,
+                shape,
+            )
+        });
+        segments.push(BeamSegment {
+            laser: Laser {
+                source: Some(source),
+                from_intersector: Some(from),
+                to_intersector: hit.map(|(entity, _)| entity),
+                index,
+                direction,
+                start,
+                end,
+                color: None,
             },
-            meshes.borrow_mut(),
-            materials.borrow_mut(),
-        );
-        laser_entity = Some(laser);
-
-        // insert data to laser
+            entry,
+        });
 
-        // insert intersectiondata to current intersection
-        intersection.source = source;
-        intersection.laser_out = Some(laser);
+        let Some((entity, intersector)) = hit else {
+            break;
+        };
+        match intersector.kind {
+            IntersectorType::Emitter | IntersectorType::Blocker | IntersectorType::Receiver => {
+                break
+            }
+            IntersectorType::Reflector => {
+                direction = reflect(direction, intersector.orientation, shape);
+                from = entity;
+                start = end;
+            }
+        }
     }
 
-    if let Some(child_entity) = to_intersector {
-        // insert update pending on child entity
-        println!("Insert update pending on child entity: {:?}", child_entity);
-        commands.entity(child_entity).insert(UpdatePending);
+    segments
+}
 
-        // get child intersection
-        let mut set_p1 = set.p1();
-        let mut to_intersection = set_p1.get_mut(child_entity).unwrap();
+/// Mirrors sit half a step off the grid axes, turned by `orientation`: diagonally on square
+/// grids (90° turns) and at 30° on hexagonal ones (60° or 120° turns). Reflecting the travel
+/// direction across that line is `2 * line - direction` in half steps.
+fn reflect(direction: GridDirection, orientation: Orientation, shape: GridShape) -> GridDirection {
+    let mirror_line = 2 * orientation.steps() as i32 + 1;
+    GridDirection::from_steps(mirror_line - direction.steps() as i32, shape)
+}
 
-        // set laser_in to spawned laser
-        to_intersection.source = source;
-        to_intersection.laser_in = laser_entity;
-        to_intersection.from = from_intersector;
-        to_intersection.to = to_intersector;
+/// Orientation of a building mirrored across the axis it faces along by default. Mirrors sit half
+/// a step off that axis, so they end up one step further round than everything else.
+pub fn mirrored_orientation(
+    kind: Option<IntersectorType>,
+    orientation: Orientation,
+    shape: GridShape,
+) -> Orientation {
+    let steps = -(orientation.steps() as i32);
+    match kind {
+        Some(IntersectorType::Reflector) => Orientation::default().turned(steps + 1, shape),
+        _ => Orientation::default().turned(steps, shape),
     }
 }
 
 fn update_laser(
     mut commands: Commands,
     mut events: EventReader<LaserUpdateEvent>,
+    grid: Res<GridMap>,
+    settings: Res<GridSettings>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    q_intersector: Query<
+        (
+            Entity,
+            &IntersectorType,
+            &GridPosition,
+            &Orientation,
+            &Footprint,
+        ),
+        Without<DeletionPending>,
+    >,
+    q_efficiency: Query<&Efficiency>,
+    q_wells: Query<&ColorWell>,
     q_laser: Query<(Entity, &Laser)>,
 ) {
+    if events.is_empty() {
+        return;
+    }
     for ev in events.read() {
-        println!("LaserUpdateEvent: {:?}", ev);
-        match ev.update_type {
-            UpdateType::Place => {
-                // Place
-                match ev.intersector {
-                    IntersectorType::Emitter => {
-                        // Emitter
-                        commands.entity(ev.entity).insert((
-                            Intersection {
-                                laser_out_direction: Vec2::new(0.0, 1.0),
-                                ..default()
-                            },
-                            UpdatePending,
-                        ));
-                    }
-                    IntersectorType::Reflector => {
-                        // Reflector
-                        commands.entity(ev.entity).insert((
-                            Intersection {
-                                laser_out_direction: Vec2::new(1.0, 0.0),
-                                ..default()
-                            },
-                            UpdatePending,
-                        ));
-                    }
+        for changed in &ev.intersectors {
+            debug!(
+                "{:?} {:?} {:?} at {:?}",
+                ev.update_type, changed.intersector, changed.entity, changed.grid_position
+            );
+        }
+    }
+
+    let lookup = |entity| {
+        q_intersector
+            .get(entity)
+            .ok()
+            .map(
+                |(_, kind, pivot, orientation, footprint)| TracedIntersector {
+                    kind: *kind,
+                    pivot: *pivot,
+                    orientation: *orientation,
+                    footprint,
+                },
+            )
+    };
+
+    // keep lasers whose segment did not change so they don't replay their grow animation
+    let mut stale_lasers: HashMap<
+        (
+            GridPosition,
+            GridPosition,
+            GridDirection,
+            Option<LightColor>,
+        ),
+        Entity,
+    > = q_laser
+        .iter()
+        .map(|(entity, laser)| {
+            (
+                (laser.start, laser.end, laser.direction, laser.color),
+                entity,
+            )
+        })
+        .collect();
+    let mut intersections: HashMap<Entity, Intersection> = HashMap::new();
+
+    for (emitter, intersector_type, pivot, ..) in q_intersector.iter() {
+        if *intersector_type != IntersectorType::Emitter {
+            continue;
+        }
+        let color = grid
+            .get(GridLayer::Ground, *pivot)
+            .and_then(|ground| q_wells.get(*ground).ok())
+            .map(|well| well.color);
+
+        let segments = trace_beam(&grid, settings.shape, emitter, lookup);
+        let receiver = segments
+            .last()
+            .and_then(|segment| segment.laser.to_intersector)
+            .filter(|entity| {
+                lookup(*entity).is_some_and(|target| target.kind == IntersectorType::Receiver)
+            });
+        // every segment after the first was reflected off the intersector it starts from
+        let delivered = segments
+            .iter()
+            .skip(1)
+            .filter_map(|segment| segment.laser.from_intersector)
+            .map(|entity| {
+                q_efficiency
+                    .get(entity)
+                    .map_or(1.0, |efficiency| efficiency.0)
+            })
+            .product();
+        let intersection = intersections.entry(emitter).or_default();
+        intersection.delivers_to = receiver;
+        intersection.delivered = delivered;
+
+        for (index, segment) in segments.into_iter().enumerate() {
+            let laser = Laser {
+                color,
+                ..segment.laser
+            };
+            let laser_entity = match stale_lasers.remove(&(
+                laser.start,
+                laser.end,
+                laser.direction,
+                laser.color,
+            )) {
+                Some(entity) => {
+                    commands.entity(entity).insert(laser);
+                    entity
                 }
+                None => spawn_laser(
+                    &mut commands,
+                    &settings,
+                    laser,
+                    // beams grow out one segment after the other
+                    index as f32 * 0.15,
+                    meshes.borrow_mut(),
+                    materials.borrow_mut(),
+                ),
+            };
+
+            if let Some(from) = laser.from_intersector {
+                let intersection = intersections.entry(from).or_default();
+                intersection.source = laser.source;
+                intersection.laser_out = Some(laser_entity);
+                intersection.laser_out_direction = Some(laser.direction);
+                intersection.to = laser.to_intersector;
             }
-            UpdateType::Update => {
-                // Update
-            }
-            UpdateType::Remove => {
-                // Remove
-                match ev.intersector {
-                    IntersectorType::Emitter => {
-                        // Emitter
-                        for (laser_entity, laser) in q_laser.iter() {
-                            println!("Laser: {:?}, source: {:?}", laser_entity, laser.source);
-                            if laser.source == Some(ev.entity) {
-                                commands.entity(laser_entity).despawn_recursive();
-                            }
-                        }
-                    }
-                    IntersectorType::Reflector => {
-                        // Reflector
-                    }
-                }
+
+            if let Some(to) = laser.to_intersector {
+                let intersection = intersections.entry(to).or_default();
+                intersection.source = laser.source;
+                intersection.laser_in = Some(laser_entity);
+                intersection.from = laser.from_intersector;
+                intersection.entry = segment.entry;
             }
         }
     }
-}
 
-// recursive function to walk the laser with inputs (grid_pos, direction)
-// if the next grid_pos is a building, spawn laser with appropriate length
+    for entity in stale_lasers.into_values() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    for (entity, ..) in q_intersector.iter() {
+        commands
+            .entity(entity)
+            .insert(intersections.remove(&entity).unwrap_or_default());
+    }
+}
 
 fn animate_laser(time: Res<Time>, mut query: Query<&mut Transform, With<Laser>>) {
     for mut transform in &mut query {
@@ -275,18 +401,17 @@ fn animate_laser(time: Res<Time>, mut query: Query<&mut Transform, With<Laser>>)
 
 fn spawn_laser(
     commands: &mut Commands,
+    settings: &GridSettings,
     laser: Laser,
+    delay: f32,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<StandardMaterial>>,
 ) -> Entity {
-    let laser_length =
-        ((laser.start.x - laser.end.x) as f32 + (laser.start.y - laser.end.y) as f32).abs();
-    println!("Laser length: {}", laser_length);
-    let position = Vec2::new(laser.end.x as f32 / 2.0, laser.end.y as f32 / 2.0);
-    println!(
-        "Position: {:?}, GridFrom: {:?}, GridTo: {:?}",
-        position, laser.start, laser.end
-    );
+    let start = settings.grid_to_world(laser.start);
+    let end = settings.grid_to_world(laser.end);
+    let laser_length = start.distance(end);
+    let position = (start + end) / 2.0;
+    let direction = settings.direction_to_world(laser.direction);
 
     let laser_entity = (
         PbrBundle {
@@ -299,23 +424,165 @@ fn spawn_laser(
                 perceptual_roughness: 0.5,
                 thickness: 4.0,
                 ior: 1.18,
-                emissive: Color::ORANGE_RED * 40.0,
+                emissive: laser.color.map_or(Color::ORANGE_RED, LightColor::color) * 40.0,
                 ..default()
             }),
-            transform: Transform::from_translation(Vec3::new(0.0, 0.25, 0.0))
+            transform: Transform::from_translation(Vec3::new(start.x, 0.5, start.y))
                 .with_scale(Vec3::new(0.04, 0.0, 0.06))
-                .with_rotation(Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2)),
+                .with_rotation(Quat::from_rotation_arc(
+                    Vec3::Y,
+                    Vec3::new(direction.x, 0.0, direction.y),
+                )),
             ..Default::default()
         },
-        AnimateTransform {
-            target_scale: Vec3::new(0.03, laser_length - 0.5, 0.04),
-            target_position: Vec3::new(position.x, 0.5, position.y),
-            duration: 2.5,
-            ..Default::default()
-        },
-        Laser { ..laser },
+        Animation::default().wait(delay).then(
+            Tween::new(2.5)
+                .translation(Vec3::new(position.x, 0.5, position.y))
+                .scale(Vec3::new(0.03, laser_length - 0.5, 0.04)),
+        ),
+        laser,
         NotShadowCaster,
         Name::new("Laser"),
     );
     commands.spawn(laser_entity).id()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::GridBounds;
+
+    /// A map of single-cell intersectors, the first of them the emitter.
+    struct Setup {
+        shape: GridShape,
+        grid: GridMap,
+        intersectors: Vec<(IntersectorType, GridPosition, Orientation)>,
+    }
+
+    impl Setup {
+        fn new(shape: GridShape) -> Self {
+            Self {
+                shape,
+                grid: GridMap::new(GridBounds::default()),
+                intersectors: Vec::new(),
+            }
+        }
+
+        fn with(mut self, kind: IntersectorType, position: GridPosition, orientation: u8) -> Self {
+            let entity = Entity::from_raw(self.intersectors.len() as u32);
+            self.grid.set(GridLayer::Build, position, entity).unwrap();
+            self.intersectors
+                .push((kind, position, Orientation(orientation)));
+            self
+        }
+
+        fn trace(&self) -> Vec<BeamSegment> {
+            let footprint = Footprint::default();
+            trace_beam(&self.grid, self.shape, Entity::from_raw(0), |entity| {
+                let (kind, pivot, orientation) = *self.intersectors.get(entity.index() as usize)?;
+                Some(TracedIntersector {
+                    kind,
+                    pivot,
+                    orientation,
+                    footprint: &footprint,
+                })
+            })
+        }
+    }
+
+    fn cell(x: i32, y: i32) -> GridPosition {
+        GridPosition { x, y }
+    }
+
+    /// `steps` cells from the origin along `direction`.
+    fn along(shape: GridShape, direction: u8, steps: i32) -> GridPosition {
+        GridPosition::default().offset(shape.offset(GridDirection(direction)) * steps)
+    }
+
+    fn settings(shape: GridShape) -> GridSettings {
+        GridSettings { shape, ..default() }
+    }
+
+    #[test]
+    fn beams_stop_at_blockers() {
+        let segments = Setup::new(GridShape::Square)
+            .with(IntersectorType::Emitter, cell(0, 0), 0)
+            .with(IntersectorType::Blocker, cell(0, 4), 0)
+            .with(IntersectorType::Receiver, cell(0, 6), 0)
+            .trace();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].laser.end, cell(0, 4));
+        assert_eq!(segments[0].laser.to_intersector, Some(Entity::from_raw(1)));
+    }
+
+    #[test]
+    fn mirrors_turn_beams_a_quarter_turn_on_square_grids() {
+        let shape = GridShape::Square;
+        for (orientation, turned) in [(0, 0), (1, 2)] {
+            let segments = Setup::new(shape)
+                .with(IntersectorType::Emitter, cell(0, 0), 0)
+                .with(IntersectorType::Reflector, along(shape, 1, 3), orientation)
+                .trace();
+            assert_eq!(segments.len(), 2);
+            assert_eq!(segments[0].laser.direction, GridDirection(1));
+            assert_eq!(segments[1].laser.direction, GridDirection(turned));
+            assert_eq!(segments[1].laser.start, along(shape, 1, 3));
+            let cos = settings(shape)
+                .direction_to_world(segments[0].laser.direction)
+                .dot(settings(shape).direction_to_world(segments[1].laser.direction));
+            assert!(cos.abs() < 1e-5, "turned by acos({cos})");
+        }
+    }
+
+    #[test]
+    fn mirrors_turn_beams_a_sixth_turn_on_hex_grids() {
+        let shape = GridShape::Hexagonal;
+        for (orientation, turned) in [(0, 0), (1, 2)] {
+            let segments = Setup::new(shape)
+                .with(IntersectorType::Emitter, cell(0, 0), 0)
+                .with(IntersectorType::Reflector, along(shape, 1, 3), orientation)
+                .trace();
+            assert_eq!(segments.len(), 2);
+            assert_eq!(segments[0].laser.direction, GridDirection(1));
+            assert_eq!(segments[1].laser.direction, GridDirection(turned));
+            assert_eq!(segments[1].laser.start, along(shape, 1, 3));
+            let cos = settings(shape)
+                .direction_to_world(segments[0].laser.direction)
+                .dot(settings(shape).direction_to_world(segments[1].laser.direction));
+            assert!((cos - 0.5).abs() < 1e-5, "turned by acos({cos})");
+        }
+    }
+
+    #[test]
+    fn long_mirror_chains_end_after_max_bounces() {
+        // a staircase of mirrors that keeps turning the beam between up and right
+        let mut setup = Setup::new(GridShape::Square).with(IntersectorType::Emitter, cell(0, 0), 0);
+        for step in 0..MAX_BOUNCES as i32 {
+            setup = setup
+                .with(IntersectorType::Reflector, cell(step, step + 1), 0)
+                .with(IntersectorType::Reflector, cell(step + 1, step + 1), 0);
+        }
+        let segments = setup.trace();
+        assert_eq!(segments.len(), MAX_BOUNCES);
+        assert!(segments
+            .iter()
+            .all(|segment| segment.laser.to_intersector.is_some()));
+    }
+
+    #[test]
+    fn mirroring_twice_gives_the_original_orientation() {
+        for shape in [GridShape::Square, GridShape::Hexagonal] {
+            for kind in [
+                None,
+                Some(IntersectorType::Emitter),
+                Some(IntersectorType::Reflector),
+            ] {
+                for steps in 0..shape.direction_count() {
+                    let orientation = Orientation(steps);
+                    let mirrored = mirrored_orientation(kind, orientation, shape);
+                    assert_eq!(mirrored_orientation(kind, mirrored, shape), orientation);
+                }
+            }
+        }
+    }
+}
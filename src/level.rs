@@ -0,0 +1,705 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    building::Placeable,
+    camera::{Glide, MainCamera},
+    controls::{Action, Actions},
+    economy::{Resources, STARTING_STOCK},
+    editor::LevelEditor,
+    grid::{GridBounds, GridMap, GridSettings, GridShape},
+    history::{apply_build_commands, History},
+    hotbar::StatusMessage,
+    laser::Laser,
+    save::{PendingRestore, SavedBuilding},
+    worldgen::{obstacle_assets, spawn_color_well, spawn_obstacle, Obstacle},
+    AppState, Building, ColorWell, DeletionPending, GridPosition, LightColor, Orientation,
+};
+
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CurrentLevel>()
+            .init_resource::<Inventory>()
+            .init_resource::<Solved>();
+
+        app.add_systems(OnEnter(AppState::LevelSelect), spawn_level_select);
+        app.add_systems(OnExit(AppState::LevelSelect), despawn_level_select);
+        app.add_systems(Update, choose_level.run_if(in_state(AppState::LevelSelect)));
+        // the editor and its test play both start from the level as it was last left
+        for state in [AppState::InGame, AppState::Editor] {
+            app.add_systems(OnEnter(state), spawn_level.run_if(playing_level));
+            app.add_systems(OnExit(state), clear_level);
+        }
+        app.add_systems(
+            Update,
+            check_solution
+                .after(apply_build_commands)
+                .run_if(in_state(AppState::InGame).and_then(playing_level)),
+        );
+        app.add_systems(
+            Update,
+            back_to_level_select
+                .run_if(in_state(AppState::InGame).or_else(in_state(AppState::Editor))),
+        );
+    }
+}
+
+/// Folder the level select lists `*.level.ron` files from.
+const LEVELS_PATH: &str = "assets/levels";
+/// Height of the obstacles levels put down as terrain.
+pub const TERRAIN_HEIGHT: f32 = 0.6;
+
+/// A hand-made puzzle: a fixed map the player has to get light across with what they are given.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Level {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(default)]
+    pub shape: GridShape,
+    /// Cells across and up; the map runs from cell (0, 0) to (width - 1, height - 1).
+    pub width: i32,
+    pub height: i32,
+    /// Cells covered by rock.
+    #[serde(default)]
+    pub terrain: Vec<GridPosition>,
+    #[serde(default)]
+    pub wells: Vec<LevelWell>,
+    /// Buildings that come with the level and cannot be changed.
+    #[serde(default)]
+    pub fixed: Vec<LevelBuilding>,
+    /// Where the light has to go, and in which colour.
+    #[serde(default)]
+    pub receivers: Vec<LevelReceiver>,
+    /// How many of each building the player may place. Missing means anything goes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inventory: Option<BTreeMap<Placeable, u32>>,
+    #[serde(default)]
+    pub stock: BTreeMap<LightColor, u32>,
+    /// Buildings placed by a good solution, to score against.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub par: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LevelWell {
+    pub position: GridPosition,
+    pub color: LightColor,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LevelBuilding {
+    pub placeable: Placeable,
+    pub pivot: GridPosition,
+    #[serde(default)]
+    pub orientation: Orientation,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LevelReceiver {
+    #[serde(default = "default_receiver")]
+    pub placeable: Placeable,
+    pub pivot: GridPosition,
+    #[serde(default)]
+    pub orientation: Orientation,
+    pub color: LightColor,
+}
+
+fn default_receiver() -> Placeable {
+    Placeable("storage".to_string())
+}
+
+#[derive(Error, Debug)]
+pub enum LevelError {
+    #[error("could not access level file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse level: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("could not write level: {0}")]
+    Serialize(#[from] ron::Error),
+}
+
+impl Level {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LevelError> {
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), LevelError> {
+        if let Some(folder) = path.as_ref().parent() {
+            fs::create_dir_all(folder)?;
+        }
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn bounds(&self) -> GridBounds {
+        GridBounds::new(
+            GridPosition { x: 0, y: 0 },
+            GridPosition {
+                x: self.width - 1,
+                y: self.height - 1,
+            },
+        )
+    }
+
+    /// An empty map for the editor to start from.
+    pub fn blank(shape: GridShape) -> Self {
+        Self {
+            name: "Untitled".to_string(),
+            description: String::new(),
+            shape,
+            width: 9,
+            height: 9,
+            terrain: Vec::new(),
+            wells: Vec::new(),
+            fixed: Vec::new(),
+            receivers: Vec::new(),
+            inventory: None,
+            stock: STARTING_STOCK.into_iter().collect(),
+            par: None,
+        }
+    }
+
+    /// The level's buildings the way a save stores them, so they are put down the same way.
+    fn buildings(&self) -> Vec<SavedBuilding> {
+        let fixed = self.fixed.iter().map(|building| SavedBuilding {
+            placeable: building.placeable.clone(),
+            pivot: building.pivot,
+            orientation: building.orientation,
+            progress: None,
+            fixed: true,
+            requires: None,
+        });
+        let receivers = self.receivers.iter().map(|receiver| SavedBuilding {
+            placeable: receiver.placeable.clone(),
+            pivot: receiver.pivot,
+            orientation: receiver.orientation,
+            progress: None,
+            fixed: true,
+            requires: Some(receiver.color),
+        });
+        fixed.chain(receivers).collect()
+    }
+}
+
+/// Every level in `LEVELS_PATH` that loads, with its file, by file name. Broken ones are skipped
+/// with a warning.
+fn list_levels() -> Vec<(PathBuf, Level)> {
+    let Ok(entries) = fs::read_dir(LEVELS_PATH) else {
+        warn!("No levels found in {}", LEVELS_PATH);
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.to_string_lossy().ends_with(".level.ron"))
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .filter_map(|path| match Level::load(&path) {
+            Ok(level) => Some((path, level)),
+            Err(error) => {
+                warn!("Skipping {}: {}", path.display(), error);
+                None
+            }
+        })
+        .collect()
+}
+
+/// The level being played or edited. `None` is free play on a generated world.
+#[derive(Resource, Default)]
+pub struct CurrentLevel(pub Option<Level>);
+
+fn playing_level(current: Res<CurrentLevel>) -> bool {
+    current.0.is_some()
+}
+
+pub fn free_play(current: Res<CurrentLevel>) -> bool {
+    current.0.is_none()
+}
+
+/// Part of the level, so it cannot be demolished, turned or upgraded.
+#[derive(Component, Debug)]
+pub struct Fixed;
+
+/// Colour of light a level's receiver has to be sent. Beams of other colours do not count.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct RequiredColor(pub LightColor);
+
+/// Whether every receiver of the level being played gets light of its colour, as of the last
+/// check. Cleared whenever the level is left.
+#[derive(Resource, Default, Debug)]
+struct Solved(bool);
+
+/// How a solution with `placed` buildings compares to the level's par.
+fn par_verdict(placed: u32, par: Option<u32>) -> String {
+    match par {
+        Some(par) if placed < par => format!("{} under par", par - placed),
+        Some(par) if placed == par => "on par".to_string(),
+        Some(par) => format!("{} over par, par is {}", placed - par, par),
+        None => "no par set".to_string(),
+    }
+}
+
+/// Announces the level as solved once every receiver is lit in its colour, scored by the
+/// buildings the player put down against the level's par. Taking the solution apart and
+/// solving it again announces it again.
+fn check_solution(
+    current: Res<CurrentLevel>,
+    mut solved: ResMut<Solved>,
+    receivers: Query<(Entity, &RequiredColor), Without<DeletionPending>>,
+    lasers: Query<&Laser>,
+    placed: Query<(), (With<Building>, Without<Fixed>, Without<DeletionPending>)>,
+    mut status: EventWriter<StatusMessage>,
+) {
+    let Some(level) = &current.0 else {
+        return;
+    };
+    let lit = |receiver: Entity, color: LightColor| {
+        lasers
+            .iter()
+            .any(|laser| laser.to_intersector == Some(receiver) && laser.color == Some(color))
+    };
+    let now_solved = !receivers.is_empty()
+        && receivers
+            .iter()
+            .all(|(receiver, required)| lit(receiver, required.0));
+    if now_solved == solved.0 {
+        return;
+    }
+    solved.0 = now_solved;
+    if now_solved {
+        let placed = placed.iter().count() as u32;
+        status.send(StatusMessage::new(format!(
+            "{} solved with {} building{}, {}",
+            level.name,
+            placed,
+            if placed == 1 { "" } else { "s" },
+            par_verdict(placed, level.par)
+        )));
+    }
+}
+
+/// Buildings the player has left to place. Unlimited outside of levels.
+#[derive(Resource, Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Inventory(Option<BTreeMap<Placeable, u32>>);
+
+impl Inventory {
+    pub fn limited(counts: BTreeMap<Placeable, u32>) -> Self {
+        Self(Some(counts))
+    }
+
+    /// How many are left, `None` if there is no limit.
+    pub fn count(&self, placeable: &Placeable) -> Option<u32> {
+        self.0
+            .as_ref()
+            .map(|counts| counts.get(placeable).copied().unwrap_or_default())
+    }
+
+    /// Whether the building shows up at all, even if none are left.
+    pub fn offers(&self, placeable: &Placeable) -> bool {
+        self.0
+            .as_ref()
+            .is_none_or(|counts| counts.contains_key(placeable))
+    }
+
+    pub fn has(&self, placeable: &Placeable) -> bool {
+        self.count(placeable).is_none_or(|count| count > 0)
+    }
+
+    pub fn take(&mut self, placeable: &Placeable) {
+        if let Some(count) = self.0.as_mut().and_then(|counts| counts.get_mut(placeable)) {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    pub fn give(&mut self, placeable: &Placeable) {
+        if let Some(counts) = self.0.as_mut() {
+            *counts.entry(placeable.clone()).or_default() += 1;
+        }
+    }
+}
+
+/// The levels offered by the level select, with their files, in the order of its buttons.
+#[derive(Resource)]
+struct LevelList(Vec<(PathBuf, Level)>);
+
+#[derive(Component)]
+struct LevelSelectRoot;
+
+/// What a level select button does. Levels are indices into `LevelList`.
+#[derive(Component, Clone, Copy)]
+enum LevelButton {
+    Play(usize),
+    Edit(usize),
+    /// Opens the editor on an empty map.
+    New,
+    FreePlay,
+}
+
+const BUTTON_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.8);
+const BUTTON_HOVERED_COLOR: Color = Color::rgba(0.2, 0.2, 0.2, 0.9);
+
+fn spawn_level_select(mut commands: Commands) {
+    let levels = list_levels();
+    let text = |text: &str, font_size: f32, color: Color| {
+        TextBundle::from_section(
+            text,
+            TextStyle {
+                font_size,
+                color,
+                ..default()
+            },
+        )
+    };
+    let button =
+        |parent: &mut ChildBuilder, action: LevelButton, width: f32, title: &str, detail: &str| {
+            parent
+                .spawn((
+                    ButtonBundle {
+                        style: Style {
+                            width: Val::Px(width),
+                            flex_direction: FlexDirection::Column,
+                            justify_content: JustifyContent::Center,
+                            padding: UiRect::all(Val::Px(8.)),
+                            ..default()
+                        },
+                        background_color: BackgroundColor(BUTTON_COLOR),
+                        ..default()
+                    },
+                    action,
+                ))
+                .with_children(|button| {
+                    button.spawn(text(title, 20.0, Color::WHITE));
+                    if !detail.is_empty() {
+                        button.spawn(text(detail, 14.0, Color::GRAY));
+                    }
+                });
+        };
+    let row = NodeBundle {
+        style: Style {
+            column_gap: Val::Px(6.),
+            ..default()
+        },
+        ..default()
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.),
+                    height: Val::Percent(100.),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(6.),
+                    ..default()
+                },
+                background_color: BackgroundColor(Color::BLACK.with_a(0.85)),
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+            LevelSelectRoot,
+            Name::new("Level Select"),
+        ))
+        .with_children(|parent| {
+            parent.spawn(text("Choose a level", 28.0, Color::WHITE));
+            for (index, (_, level)) in levels.iter().enumerate() {
+                let detail = match level.par {
+                    Some(par) => format!("{} (par {})", level.description, par),
+                    None => level.description.clone(),
+                };
+                parent.spawn(row.clone()).with_children(|row| {
+                    button(
+                        row,
+                        LevelButton::Play(index),
+                        290.,
+                        &level.name,
+                        detail.trim(),
+                    );
+                    button(row, LevelButton::Edit(index), 64., "Edit", "");
+                });
+            }
+            parent.spawn(row.clone()).with_children(|row| {
+                button(
+                    row,
+                    LevelButton::FreePlay,
+                    290.,
+                    "Free play",
+                    "A generated world without limits",
+                );
+                button(row, LevelButton::New, 64., "New", "");
+            });
+        });
+    commands.insert_resource(LevelList(levels));
+}
+
+fn despawn_level_select(mut commands: Commands, roots: Query<Entity, With<LevelSelectRoot>>) {
+    for root in roots.iter() {
+        commands.entity(root).despawn_recursive();
+    }
+    commands.remove_resource::<LevelList>();
+}
+
+/// Clicking a level starts or edits it. Levels may use another grid shape than the command line
+/// asked for, so the shape is switched before anything is spawned; free play goes back to the
+/// command line's.
+fn choose_level(
+    mut commands: Commands,
+    levels: Res<LevelList>,
+    mut current: ResMut<CurrentLevel>,
+    mut settings: ResMut<GridSettings>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut buttons: Query<(&LevelButton, &Interaction, &mut BackgroundColor), Changed<Interaction>>,
+    mut free_play_shape: Local<Option<GridShape>>,
+) {
+    // nothing has changed the shape before the first level is chosen
+    let free_play_shape = *free_play_shape.get_or_insert(settings.shape);
+    for (button, interaction, mut background) in buttons.iter_mut() {
+        background.0 = match interaction {
+            Interaction::None => BUTTON_COLOR,
+            _ => BUTTON_HOVERED_COLOR,
+        };
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match *button {
+            LevelButton::Play(index) => {
+                let Some((_, level)) = levels.0.get(index) else {
+                    continue;
+                };
+                info!("Starting level {}", level.name);
+                current.0 = Some(level.clone());
+                next_state.set(AppState::InGame);
+            }
+            LevelButton::Edit(index) => {
+                let Some((path, level)) = levels.0.get(index) else {
+                    continue;
+                };
+                info!("Editing level {}", level.name);
+                current.0 = Some(level.clone());
+                commands.insert_resource(LevelEditor::new(path.clone()));
+                next_state.set(AppState::Editor);
+            }
+            LevelButton::New => {
+                let path = new_level_path();
+                info!("Editing a new level, to be saved as {}", path.display());
+                current.0 = Some(Level::blank(settings.shape));
+                commands.insert_resource(LevelEditor::new(path));
+                next_state.set(AppState::Editor);
+            }
+            LevelButton::FreePlay => {
+                current.0 = None;
+                next_state.set(AppState::InGame);
+            }
+        }
+        settings.shape = match &current.0 {
+            Some(level) => level.shape,
+            None => free_play_shape,
+        };
+    }
+}
+
+/// The first `custom_NN.level.ron` in `LEVELS_PATH` that is not taken yet.
+fn new_level_path() -> PathBuf {
+    (1..)
+        .map(|number| Path::new(LEVELS_PATH).join(format!("custom_{:02}.level.ron", number)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+/// `LevelSelect` leaves whatever is being played or edited. Unsaved changes to a level are lost.
+fn back_to_level_select(
+    mut commands: Commands,
+    actions: Res<Actions>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if !actions.just_pressed(Action::LevelSelect) {
+        return;
+    }
+    commands.remove_resource::<LevelEditor>();
+    next_state.set(AppState::LevelSelect);
+}
+
+/// Takes down everything a level or free play put on the map, so the editor and its test play
+/// can lay it out again, or another one can start. `spawn_level` starts over with an empty grid.
+fn clear_level(
+    mut commands: Commands,
+    mut history: ResMut<History>,
+    mut solved: ResMut<Solved>,
+    mut resources: ResMut<Resources>,
+    mut inventory: ResMut<Inventory>,
+    existing: Query<Entity, Or<(With<Building>, With<ColorWell>, With<Obstacle>, With<Laser>)>>,
+) {
+    for entity in existing.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    // the actions refer to buildings that are gone now
+    history.clear();
+    solved.0 = false;
+    // levels bring their own, free play starts afresh
+    *resources = Resources::new(STARTING_STOCK);
+    *inventory = Inventory::default();
+}
+
+/// Lays out the level: map size, terrain, wells, stock and inventory now, its buildings once the
+/// wells they stand on exist. In the editor nothing is fixed and every building may be placed.
+fn spawn_level(
+    mut commands: Commands,
+    current: Res<CurrentLevel>,
+    state: Res<State<AppState>>,
+    settings: Res<GridSettings>,
+    mut grid_map: ResMut<GridMap>,
+    mut resources: ResMut<Resources>,
+    mut inventory: ResMut<Inventory>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut camera: Query<&mut Glide, With<MainCamera>>,
+) {
+    let Some(level) = &current.0 else {
+        return;
+    };
+    *grid_map = GridMap::new(level.bounds());
+
+    for well in &level.wells {
+        spawn_color_well(
+            &mut commands,
+            &mut grid_map,
+            &settings,
+            well.position,
+            well.color,
+            &mut meshes,
+            &mut materials,
+        );
+    }
+    let (mesh, material) = obstacle_assets(&mut meshes, &mut materials);
+    for position in &level.terrain {
+        spawn_obstacle(
+            &mut commands,
+            &mut grid_map,
+            &settings,
+            *position,
+            TERRAIN_HEIGHT,
+            mesh.clone(),
+            material.clone(),
+        );
+    }
+    *resources = Resources::new(level.stock.clone());
+    let editing = *state.get() == AppState::Editor;
+    *inventory = match &level.inventory {
+        Some(counts) if !editing => Inventory::limited(counts.clone()),
+        _ => Inventory::default(),
+    };
+    let mut buildings = level.buildings();
+    if editing {
+        for building in &mut buildings {
+            building.fixed = false;
+        }
+    }
+    commands.insert_resource(PendingRestore(buildings));
+
+    let bounds = level.bounds();
+    let centre = (settings.grid_to_world(bounds.min) + settings.grid_to_world(bounds.max)) / 2.0;
+    for mut glide in &mut camera {
+        glide.to(Vec3::new(centre.x, 0.0, centre.y));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GridDirection;
+
+    #[test]
+    fn solutions_are_scored_against_par() {
+        assert_eq!(par_verdict(1, Some(3)), "2 under par");
+        assert_eq!(par_verdict(3, Some(3)), "on par");
+        assert_eq!(par_verdict(5, Some(3)), "2 over par, par is 3");
+        assert_eq!(par_verdict(5, None), "no par set");
+    }
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_event::<StatusMessage>()
+            .init_resource::<Solved>()
+            .insert_resource(CurrentLevel(Some(Level {
+                par: Some(1),
+                ..Level::blank(GridShape::Square)
+            })))
+            .add_systems(Update, check_solution);
+        app
+    }
+
+    fn beam(receiver: Entity, color: LightColor) -> Laser {
+        Laser {
+            source: None,
+            from_intersector: None,
+            to_intersector: Some(receiver),
+            index: 0,
+            direction: GridDirection::FORWARD,
+            start: GridPosition { x: 0, y: 0 },
+            end: GridPosition { x: 3, y: 0 },
+            color: Some(color),
+        }
+    }
+
+    fn messages(app: &mut App) -> Vec<String> {
+        app.world
+            .resource_mut::<Events<StatusMessage>>()
+            .drain()
+            .map(|message| message.0)
+            .collect()
+    }
+
+    #[test]
+    fn receivers_only_count_light_of_their_colour() {
+        let mut app = app();
+        let orange = app
+            .world
+            .spawn((Building, Fixed, RequiredColor(LightColor::Orange)))
+            .id();
+        let blue = app
+            .world
+            .spawn((Building, Fixed, RequiredColor(LightColor::Blue)))
+            .id();
+        app.world.spawn(Building);
+        app.world.spawn(beam(orange, LightColor::Orange));
+        let wrong = app.world.spawn(beam(blue, LightColor::Orange)).id();
+
+        app.update();
+        assert!(!app.world.resource::<Solved>().0);
+        assert!(messages(&mut app).is_empty());
+
+        app.world.despawn(wrong);
+        let right = app.world.spawn(beam(blue, LightColor::Blue)).id();
+        app.update();
+        assert!(app.world.resource::<Solved>().0);
+        assert_eq!(
+            messages(&mut app),
+            ["Untitled solved with 1 building, on par"]
+        );
+
+        // announced once, until the solution breaks and is found again
+        app.update();
+        assert!(messages(&mut app).is_empty());
+        app.world.despawn(right);
+        app.update();
+        assert!(!app.world.resource::<Solved>().0);
+        assert!(messages(&mut app).is_empty());
+        app.world.spawn(beam(blue, LightColor::Blue));
+        app.update();
+        assert_eq!(messages(&mut app).len(), 1);
+    }
+}
@@ -0,0 +1,1355 @@
+#![allow(clippy::type_complexity)]
+#![allow(clippy::too_many_arguments)]
+#![allow(clippy::result_unit_err)]
+
+use std::f32::consts::FRAC_PI_6;
+
+use bevy::utils::{HashMap, HashSet};
+use bevy::{
+    diagnostic::FrameTimeDiagnosticsPlugin,
+    pbr::PointLightShadowMap,
+    prelude::*,
+    window::{PresentMode, PrimaryWindow},
+};
+use bevy_inspector_egui::quick::WorldInspectorPlugin;
+use bevy_inspector_egui::InspectorOptions;
+use blueprint::{
+    blueprint_input, draw_paste_preview, paste_blueprint, BlueprintSettings, Clipboard,
+};
+use building::{
+    check_placement, spawn_model, BuildChecker, BuildRules, BuildingPlugin, BuildingRegistry,
+    Placeable,
+};
+use camera::{CameraPlugin, CameraSettings, MainCamera};
+use construction::{ConstructionPlugin, ConstructionSettings};
+use controls::{Action, Actions, ControlsPlugin};
+use economy::EconomyPlugin;
+use editor::EditorPlugin;
+use fps::FPSPlugin;
+use grid::{GridBounds, GridLayer, GridMap, GridRegion, GridSettings, GridShape, CHUNK_SIZE};
+use history::{
+    apply_build_commands, undo_redo, BuildAction, BuildCommand, History, PlacedBuilding,
+};
+use hotbar::{Hotbar, HotbarPlugin, StatusMessage};
+use laser::*;
+use level::{free_play, LevelPlugin};
+use replay::{in_replay, ReplayPlugin, ReplaySettings, Replayer};
+use save::{quick_load, quick_save, restore_buildings};
+use serde::{Deserialize, Serialize};
+use tween::TweenPlugin;
+use worldgen::{spawn_world, WorldGenSettings};
+
+mod blueprint;
+mod building;
+mod camera;
+mod construction;
+mod controls;
+mod economy;
+mod editor;
+mod fps;
+pub mod grid;
+mod history;
+mod hotbar;
+mod interpolate;
+mod laser;
+mod level;
+mod replay;
+mod save;
+#[cfg(test)]
+mod testing;
+mod tween;
+mod worldgen;
+
+/// Builds the game and runs it until the window closes.
+pub fn run() {
+    let mut app = App::new();
+
+    // plugins
+    app.add_plugins(DefaultPlugins.set(WindowPlugin {
+        primary_window: Some(Window {
+            title: "spectrum".into(),
+            present_mode: PresentMode::AutoNoVsync,
+            prevent_default_event_handling: false,
+            ..default()
+        }),
+        ..default()
+    }))
+    .add_plugins(WorldInspectorPlugin::new())
+    .add_plugins((FPSPlugin, FrameTimeDiagnosticsPlugin))
+    .add_plugins(ControlsPlugin)
+    .add_plugins(CameraPlugin)
+    .add_plugins(BuildingPlugin)
+    .add_plugins(ConstructionPlugin)
+    .add_plugins(EconomyPlugin)
+    .add_plugins(EditorPlugin)
+    .add_plugins(HotbarPlugin)
+    .add_plugins(LaserPlugin)
+    .add_plugins(LevelPlugin)
+    .add_plugins(ReplayPlugin)
+    .add_plugins(TweenPlugin);
+
+    // resources
+    app.insert_resource(ClearColor(Color::BLACK))
+        .insert_resource(PointLightShadowMap { size: 2048 })
+        .insert_resource(Game::default())
+        .insert_resource(AmbientLight {
+            brightness: 0.0,
+            ..default()
+        })
+        .insert_resource(Msaa::default())
+        .insert_resource(MouseWorldPosition(Vec2::ZERO))
+        .insert_resource(MouseGridPosition::default())
+        .init_resource::<DragStart>()
+        .init_resource::<History>()
+        .init_resource::<Clipboard>()
+        .insert_resource(GridSettings {
+            shape: if std::env::args().any(|arg| arg == "--hex") {
+                GridShape::Hexagonal
+            } else {
+                GridShape::Square
+            },
+            ..default()
+        })
+        .insert_resource(GridMap::default())
+        .insert_resource(CameraSettings {
+            perspective: std::env::args().any(|arg| arg == "--perspective"),
+            edge_scroll: std::env::args().any(|arg| arg == "--edge-scroll"),
+            ..default()
+        })
+        .insert_resource(ConstructionSettings {
+            enabled: std::env::args().any(|arg| arg == "--construction"),
+            builders: arg_value("--builders").unwrap_or(2),
+            rate: arg_value("--build-rate").unwrap_or(1.0),
+        })
+        .insert_resource(WorldGenSettings {
+            seed: arg_value("--seed").unwrap_or_else(rand::random),
+            bounds: arg_value("--map-size")
+                .map(|size| GridBounds::centred(size, size))
+                .unwrap_or_default(),
+            ..default()
+        })
+        .insert_resource(ReplaySettings {
+            record: arg_value("--record"),
+            replay: arg_value("--replay"),
+        })
+        .insert_resource({
+            let default = BlueprintSettings::default();
+            BlueprintSettings {
+                export: arg_value("--export").unwrap_or(default.export),
+                import: arg_value("--import").unwrap_or(default.import),
+            }
+        });
+    app.add_event::<BuildCommand>();
+    app.init_state::<AppState>();
+
+    // systems
+    app.add_systems(Startup, setup);
+    app.add_systems(Update, fit_floor);
+    // the grid shape is only settled once a level is chosen
+    app.add_systems(OnExit(AppState::LevelSelect), spawn_cursor_attachments);
+    app.add_systems(OnEnter(AppState::LevelSelect), despawn_cursor_attachments);
+    app.add_systems(OnEnter(AppState::InGame), spawn_world.run_if(free_play));
+    // the level editor points and previews with the same cursor
+    app.add_systems(
+        Update,
+        (
+            (cursor_system, track_drags).chain(),
+            move_cursor_attachment,
+            update_cursor_attachment,
+            turn_ghost,
+            rotate_placement,
+            tint_ghost_materials,
+            preview_placement,
+            debug_gizmos,
+        )
+            .run_if(in_state(AppState::InGame).or_else(in_state(AppState::Editor))),
+    );
+    // input only sends build commands, a replay sends them instead while it plays
+    app.add_systems(
+        Update,
+        (
+            (place_block, select_buildings, destroy_block_system).after(track_drags),
+            rotate_building,
+            upgrade_building,
+            undo_redo,
+            (blueprint_input, paste_blueprint).chain(),
+        )
+            .before(apply_build_commands)
+            .run_if(in_state(AppState::InGame).and_then(not(resource_exists::<Replayer>))),
+    );
+    app.add_systems(
+        Update,
+        (
+            apply_build_commands,
+            draw_paste_preview,
+            draw_selection_gizmos,
+        )
+            .run_if(in_state(AppState::InGame)),
+    );
+    app.add_systems(
+        Update,
+        (
+            (quick_save, quick_load).run_if(in_state(AppState::InGame).and_then(not(in_replay))),
+            restore_buildings,
+        )
+            .chain(),
+    );
+
+    // types
+    app.register_type::<GridPosition>();
+
+    app.run();
+}
+
+/// Parses the command line argument following `name`, e.g. `--seed 42`.
+fn arg_value<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let mut args = std::env::args().skip_while(|arg| arg != name);
+    args.nth(1)?.parse().ok()
+}
+
+// define the game state
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
+enum AppState {
+    #[default]
+    LevelSelect,
+    InGame,
+    /// Designing a level, see `editor`.
+    Editor,
+}
+
+#[derive(
+    Component,
+    Copy,
+    Clone,
+    Default,
+    Eq,
+    PartialEq,
+    Hash,
+    Reflect,
+    InspectorOptions,
+    Debug,
+    Serialize,
+    Deserialize,
+)]
+pub struct GridPosition {
+    x: i32,
+    y: i32,
+}
+
+impl GridPosition {
+    pub fn new(x: i32, y: i32) -> Self {
+        Self { x, y }
+    }
+
+    fn offset(self, offset: IVec2) -> Self {
+        Self {
+            x: self.x + offset.x,
+            y: self.y + offset.y,
+        }
+    }
+}
+
+/// One of the directions a beam can travel on the grid, counted in steps counter-clockwise
+/// from +x: quarter turns on square grids, sixth turns on hexagonal ones.
+#[derive(Component, Copy, Clone, Default, Eq, PartialEq, Hash, Reflect, Debug)]
+pub struct GridDirection(u8);
+
+impl GridDirection {
+    /// The direction an unrotated building faces.
+    const FORWARD: Self = Self(1);
+
+    fn from_steps(steps: i32, shape: GridShape) -> Self {
+        Self(steps.rem_euclid(shape.direction_count() as i32) as u8)
+    }
+
+    fn steps(self) -> u8 {
+        self.0
+    }
+
+    fn opposite(self, shape: GridShape) -> Self {
+        Self::from_steps(self.0 as i32 + shape.direction_count() as i32 / 2, shape)
+    }
+
+    fn rotated(self, orientation: Orientation, shape: GridShape) -> Self {
+        Self::from_steps(self.0 as i32 + orientation.0 as i32, shape)
+    }
+}
+
+/// How far a building is turned from its default facing, in direction steps counter-clockwise.
+#[derive(
+    Component, Copy, Clone, Default, Eq, PartialEq, Hash, Reflect, Debug, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Orientation(u8);
+
+impl Orientation {
+    fn steps(self) -> u8 {
+        self.0
+    }
+
+    fn inverse(self, shape: GridShape) -> Self {
+        Self(GridDirection::from_steps(-(self.0 as i32), shape).0)
+    }
+
+    fn rotate(self, offset: IVec2, shape: GridShape) -> IVec2 {
+        (0..self.0).fold(offset, |offset, _| shape.rotate_step(offset))
+    }
+
+    /// Turned `steps` further counter-clockwise.
+    fn turned(self, steps: i32, shape: GridShape) -> Self {
+        Self(GridDirection::from_steps(self.0 as i32 + steps, shape).0)
+    }
+
+    fn to_quat(self, shape: GridShape) -> Quat {
+        // a counter-clockwise turn on the grid is a clockwise turn around world +y
+        Quat::from_rotation_y(-(self.0 as f32) * shape.step_angle())
+    }
+}
+
+/// Cell and face of a footprint a beam enters through, both in the building's own frame.
+#[derive(Copy, Clone, Eq, PartialEq, Reflect, Debug)]
+pub struct FootprintEntry {
+    cell: IVec2,
+    face: GridDirection,
+}
+
+/// Cells a building covers, as offsets from its pivot cell at its default orientation.
+#[derive(Component, Clone, Eq, PartialEq, Debug, Deserialize)]
+#[serde(from = "FootprintDefinition")]
+pub struct Footprint {
+    cells: Vec<IVec2>,
+}
+
+/// How footprints are written in building definitions.
+#[derive(Deserialize)]
+enum FootprintDefinition {
+    Single,
+    /// `width` × `height` cells with the pivot at `pivot`, counted from the bottom-left corner.
+    Rect {
+        width: i32,
+        height: i32,
+        pivot: IVec2,
+    },
+    /// Arbitrary offsets from the pivot, which is always part of the footprint.
+    Cells(Vec<IVec2>),
+}
+
+impl From<FootprintDefinition> for Footprint {
+    fn from(definition: FootprintDefinition) -> Self {
+        match definition {
+            FootprintDefinition::Single => Footprint::default(),
+            FootprintDefinition::Rect {
+                width,
+                height,
+                pivot,
+            } => Footprint {
+                cells: (0..height)
+                    .flat_map(|y| (0..width).map(move |x| IVec2::new(x, y) - pivot))
+                    .collect(),
+            },
+            FootprintDefinition::Cells(mut cells) => {
+                cells.push(IVec2::ZERO);
+                // a cell listed twice would be claimed twice, which the grid refuses halfway
+                cells.sort_by_key(|cell| (cell.y, cell.x));
+                cells.dedup();
+                Footprint { cells }
+            }
+        }
+    }
+}
+
+impl Default for Footprint {
+    fn default() -> Self {
+        Self {
+            cells: vec![IVec2::ZERO],
+        }
+    }
+}
+
+impl Footprint {
+    fn cells(
+        &self,
+        pivot: GridPosition,
+        orientation: Orientation,
+        shape: GridShape,
+    ) -> impl Iterator<Item = GridPosition> + '_ {
+        self.cells
+            .iter()
+            .map(move |cell| pivot.offset(orientation.rotate(*cell, shape)))
+    }
+
+    /// Where a beam travelling in `direction` enters the footprint when it reaches `cell`.
+    fn entry(
+        &self,
+        pivot: GridPosition,
+        orientation: Orientation,
+        cell: GridPosition,
+        direction: GridDirection,
+        shape: GridShape,
+    ) -> Option<FootprintEntry> {
+        let inverse = orientation.inverse(shape);
+        let local = inverse.rotate(IVec2::new(cell.x - pivot.x, cell.y - pivot.y), shape);
+        if !self.cells.contains(&local) {
+            return None;
+        }
+
+        Some(FootprintEntry {
+            cell: local,
+            face: direction.opposite(shape).rotated(inverse, shape),
+        })
+    }
+}
+
+#[derive(Resource, Default)]
+struct Game {
+    current_placeable: Option<Placeable>,
+    /// Orientation the next building is placed with.
+    orientation: Orientation,
+}
+
+#[derive(Component)]
+struct DeletionPending;
+
+#[derive(Component)]
+struct Active;
+
+#[derive(Component)]
+struct Building;
+
+#[derive(Component)]
+struct CursorAttachment;
+
+#[derive(Component)]
+struct Floor;
+
+/// Buildings picked with a box selection.
+#[derive(Component)]
+struct Selected;
+
+const SELECTION_COLOR: Color = Color::rgba(0.4, 0.7, 1.0, 0.9);
+
+/// Cells the `Place` and `Demolish` actions started on, kept until the frame after release.
+#[derive(Resource, Default)]
+struct DragStart {
+    place: Option<GridPosition>,
+    demolish: Option<GridPosition>,
+}
+
+/// Root of the preview of the selected building, a child of the cursor attachment.
+#[derive(Component)]
+struct Ghost;
+
+const GHOST_VALID: Color = Color::rgba(0.2, 1.0, 0.4, 0.35);
+const GHOST_INVALID: Color = Color::rgba(1.0, 0.15, 0.1, 0.35);
+
+/// Meshes and materials the cursor attachment is built from.
+#[derive(Resource)]
+struct CursorAssets {
+    slab_mesh: Handle<Mesh>,
+    slab_material: Handle<StandardMaterial>,
+    /// One floor tile per footprint cell of the ghost.
+    tile_mesh: Handle<Mesh>,
+    /// Shared by every part of the ghost, tinted by `preview_placement`.
+    ghost_material: Handle<StandardMaterial>,
+}
+
+#[derive(Resource, Default)]
+struct MouseWorldPosition(Vec2);
+
+#[derive(Resource, Default)]
+struct MouseGridPosition(GridPosition);
+
+/// Colours of light wells emit; the economy counts each one separately.
+#[derive(
+    Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord, Reflect, Debug, Serialize, Deserialize,
+)]
+pub enum LightColor {
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Blue,
+    Violet,
+}
+
+impl LightColor {
+    /// How the colour is drawn on wells, beams and in the UI.
+    fn color(self) -> Color {
+        match self {
+            LightColor::Red => Color::rgb(1.0, 0.1, 0.1),
+            LightColor::Orange => Color::ORANGE_RED,
+            LightColor::Yellow => Color::rgb(1.0, 0.85, 0.1),
+            LightColor::Green => Color::rgb(0.2, 1.0, 0.3),
+            LightColor::Blue => Color::rgb(0.2, 0.4, 1.0),
+            LightColor::Violet => Color::rgb(0.6, 0.2, 1.0),
+        }
+    }
+}
+
+#[derive(Component)]
+struct ColorWell {
+    color: LightColor,
+}
+
+impl Default for ColorWell {
+    fn default() -> Self {
+        Self {
+            color: LightColor::Orange,
+        }
+    }
+}
+
+fn debug_gizmos(
+    mut gizmos: Gizmos,
+    grid: Res<GridMap>,
+    settings: Res<GridSettings>,
+    time: Res<Time>,
+    mut last_change_tick: Local<u32>,
+    mut changed_chunks: Local<HashMap<IVec2, f32>>,
+) {
+    let at_floor = |point: Vec2, height: f32| Vec3::new(point.x, height, point.y);
+
+    // one outline per chunk, a linestrip per cell is too much for the bigger maps
+    let bounds = grid.bounds();
+    let chunk_outline = |chunk: IVec2| {
+        let min = GridPosition {
+            x: (chunk.x * CHUNK_SIZE).max(bounds.min.x),
+            y: (chunk.y * CHUNK_SIZE).max(bounds.min.y),
+        };
+        let max = GridPosition {
+            x: (chunk.x * CHUNK_SIZE + CHUNK_SIZE - 1).min(bounds.max.x),
+            y: (chunk.y * CHUNK_SIZE + CHUNK_SIZE - 1).min(bounds.max.y),
+        };
+        [
+            min,
+            GridPosition { x: max.x, y: min.y },
+            max,
+            GridPosition { x: min.x, y: max.y },
+            min,
+        ]
+        .map(|corner| at_floor(settings.grid_to_world(corner), 0.01))
+    };
+    for x in bounds.min.x.div_euclid(CHUNK_SIZE)..=bounds.max.x.div_euclid(CHUNK_SIZE) {
+        for y in bounds.min.y.div_euclid(CHUNK_SIZE)..=bounds.max.y.div_euclid(CHUNK_SIZE) {
+            gizmos.linestrip(
+                chunk_outline(IVec2::new(x, y)),
+                Color::rgba(1.0, 1.0, 1.0, 0.1),
+            );
+        }
+    }
+
+    // outline building footprints
+    for (position, entity) in grid.iter_region(GridLayer::Build, bounds) {
+        let corners: Vec<Vec2> = settings.cell_corners(position).collect();
+        for (direction, _, neighbour) in grid.neighbours(GridLayer::Build, position, settings.shape)
+        {
+            if neighbour == Some(entity) {
+                continue;
+            }
+            // the edge facing `direction` runs between the corners either side of it
+            let index = direction.steps() as usize;
+            gizmos.line(
+                at_floor(corners[(index + corners.len() - 1) % corners.len()], 0.02),
+                at_floor(corners[index], 0.02),
+                Color::rgba(1.0, 1.0, 1.0, 0.6),
+            );
+        }
+    }
+
+    // fade out the chunks written to recently
+    let now = time.elapsed_seconds();
+    for chunk in grid.chunks_changed_since(GridLayer::Build, *last_change_tick) {
+        changed_chunks.insert(chunk, now);
+    }
+    *last_change_tick = grid.change_tick();
+    changed_chunks.retain(|_, changed_at| now - *changed_at < 1.0);
+    for (chunk, changed_at) in changed_chunks.iter() {
+        gizmos.linestrip(
+            chunk_outline(*chunk),
+            Color::YELLOW.with_a(1.0 - (now - changed_at)),
+        );
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let plane_mesh = meshes.add(Plane3d::default().mesh().size(1.0, 1.0));
+
+    // Directional light
+    commands.spawn((
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                shadows_enabled: true,
+                illuminance: 300.0,
+                ..default()
+            },
+            transform: Transform::from_xyz(10.0, 10.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ..Default::default()
+        },
+        Name::new("Directional Light"),
+    ));
+
+    // Floor
+    let floor_mat = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.03, 0.03, 0.03),
+        reflectance: 0.0,
+        perceptual_roughness: 1.0,
+        ..default()
+    });
+
+    commands.spawn((
+        PbrBundle {
+            mesh: plane_mesh.clone(),
+            material: floor_mat.clone(),
+            transform: Transform::from_xyz(0.0, 0.0, 0.0),
+            ..default()
+        },
+        Name::new("Floor"),
+        Floor,
+    ));
+}
+
+/// Stretches the floor under the whole map whenever the map or its shape changes.
+fn fit_floor(
+    grid: Res<GridMap>,
+    settings: Res<GridSettings>,
+    mut floors: Query<&mut Transform, With<Floor>>,
+) {
+    if !grid.is_changed() && !settings.is_changed() {
+        return;
+    }
+
+    let bounds = grid.bounds();
+    let corners = [
+        bounds.min,
+        GridPosition {
+            x: bounds.max.x,
+            y: bounds.min.y,
+        },
+        bounds.max,
+        GridPosition {
+            x: bounds.min.x,
+            y: bounds.max.y,
+        },
+    ]
+    .map(|corner| settings.grid_to_world(corner));
+    let min = corners.into_iter().reduce(Vec2::min).unwrap() - settings.cell_size;
+    let max = corners.into_iter().reduce(Vec2::max).unwrap() + settings.cell_size;
+    let (centre, size) = ((min + max) / 2.0, max - min);
+    for mut transform in &mut floors {
+        transform.translation = Vec3::new(centre.x, 0.0, centre.y);
+        transform.scale = Vec3::new(size.x, 1.0, size.y);
+    }
+}
+
+fn cursor_system(
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    mut mouse_world_position: ResMut<MouseWorldPosition>,
+    mut mouse_grid_position: ResMut<MouseGridPosition>,
+    settings: Res<GridSettings>,
+) {
+    let (camera, camera_transform) = q_camera.single();
+    let window = q_window.single();
+
+    if let Some(world_position) = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor))
+        .map(|ray| {
+            // get the intersection of the ray with the xz plane on y=0
+            // t is the distance from the ray origin to the intersection point
+            let t = -ray.origin.y / ray.direction.y;
+            Vec3::new(
+                ray.origin.x + t * ray.direction.x,
+                0.0,
+                ray.origin.z + t * ray.direction.z,
+            )
+        })
+    {
+        mouse_world_position.0 = Vec2::new(world_position.x, world_position.z);
+        mouse_grid_position.0 =
+            settings.world_to_grid(Vec2::new(world_position.x, world_position.z));
+    }
+}
+
+fn spawn_cursor_attachments(
+    mut commands: Commands,
+    settings: Res<GridSettings>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let tile_size = settings.cell_size * 0.9;
+    let tile_mesh = match settings.shape {
+        GridShape::Square => Mesh::from(Cuboid::new(tile_size, 0.02, tile_size)),
+        // the cylinder's first corner points along +x, cells are pointy-top
+        GridShape::Hexagonal => Mesh::from(
+            Cylinder::new(tile_size / 3f32.sqrt(), 0.02)
+                .mesh()
+                .resolution(6),
+        )
+        .rotated_by(Quat::from_rotation_y(FRAC_PI_6)),
+    };
+
+    commands.insert_resource(CursorAssets {
+        slab_mesh: meshes.add(Cuboid::new(0.9, 0.1, 0.9)),
+        slab_material: materials.add(StandardMaterial {
+            base_color: Color::WHITE,
+            reflectance: 0.5,
+            diffuse_transmission: 0.5,
+            specular_transmission: 0.5,
+            perceptual_roughness: 0.5,
+            thickness: 0.2,
+            ..default()
+        }),
+        tile_mesh: meshes.add(tile_mesh),
+        ghost_material: materials.add(StandardMaterial {
+            base_color: GHOST_VALID,
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        }),
+    });
+
+    commands.spawn((
+        SpatialBundle::default(),
+        Name::new("Cursor"),
+        CursorAttachment,
+        Active,
+    ));
+}
+
+/// Takes the cursor down on the way back to the level select, the next level may have another
+/// grid shape.
+fn despawn_cursor_attachments(
+    mut commands: Commands,
+    attachments: Query<Entity, With<CursorAttachment>>,
+) {
+    for entity in attachments.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn move_cursor_attachment(
+    time: Res<Time>,
+    mut cursor_attachement: Query<&mut Transform, With<CursorAttachment>>,
+    mouse_grid_pos: Res<MouseGridPosition>,
+    settings: Res<GridSettings>,
+) {
+    let target = settings.grid_to_world(mouse_grid_pos.0);
+    for mut transform in cursor_attachement.iter_mut() {
+        transform.translation = transform.translation.lerp(
+            Vec3::new(target.x, transform.translation.y, target.y),
+            time.delta_seconds() * 15.,
+        );
+    }
+}
+
+/// Swaps the cursor's children for a ghost of the selected building, or for the plain slab
+/// when nothing is selected.
+fn update_cursor_attachment(
+    game: Res<Game>,
+    registry: Res<BuildingRegistry>,
+    settings: Res<GridSettings>,
+    cursor_assets: Res<CursorAssets>,
+    mut commands: Commands,
+    active_cursor_attachement: Query<Entity, (With<CursorAttachment>, With<Active>)>,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut shown_placeable: Local<Option<Placeable>>,
+) {
+    // turning the placement only turns the ghost, see `turn_ghost`; a new cursor comes with new
+    // assets and starts out empty
+    if *shown_placeable == game.current_placeable
+        && !registry.is_changed()
+        && !cursor_assets.is_changed()
+    {
+        return;
+    }
+    *shown_placeable = game.current_placeable.clone();
+    let Ok(active_entity) = active_cursor_attachement.get_single() else {
+        return;
+    };
+    commands.entity(active_entity).despawn_descendants();
+
+    let Some(definition) = game
+        .current_placeable
+        .as_ref()
+        .and_then(|placeable| registry.get(placeable))
+    else {
+        commands.entity(active_entity).with_children(|parent| {
+            parent.spawn((
+                PbrBundle {
+                    mesh: cursor_assets.slab_mesh.clone(),
+                    material: cursor_assets.slab_material.clone(),
+                    ..default()
+                },
+                Name::new("Cursor Block"),
+            ));
+        });
+        return;
+    };
+
+    commands.entity(active_entity).with_children(|parent| {
+        parent
+            .spawn((
+                SpatialBundle::from_transform(
+                    Transform::from_xyz(0.0, definition.height, 0.0)
+                        .with_rotation(game.orientation.to_quat(settings.shape)),
+                ),
+                Ghost,
+                Name::new(format!("{} Ghost", definition.name)),
+            ))
+            .with_children(|ghost| {
+                spawn_model(
+                    ghost,
+                    definition,
+                    settings.shape,
+                    &asset_server,
+                    &mut meshes,
+                    &mut materials,
+                );
+                // footprint tiles on the floor, in the ghost's unrotated frame
+                for cell in &definition.footprint.cells {
+                    let offset = settings.grid_to_world(GridPosition {
+                        x: cell.x,
+                        y: cell.y,
+                    }) - settings.origin;
+                    ghost.spawn(PbrBundle {
+                        mesh: cursor_assets.tile_mesh.clone(),
+                        material: cursor_assets.ghost_material.clone(),
+                        transform: Transform::from_xyz(
+                            offset.x,
+                            0.01 - definition.height,
+                            offset.y,
+                        ),
+                        ..default()
+                    });
+                }
+            });
+    });
+}
+
+fn turn_ghost(
+    time: Res<Time>,
+    game: Res<Game>,
+    settings: Res<GridSettings>,
+    mut ghosts: Query<&mut Transform, With<Ghost>>,
+) {
+    let target = game.orientation.to_quat(settings.shape);
+    for mut transform in ghosts.iter_mut() {
+        transform.rotation = transform
+            .rotation
+            .slerp(target, (time.delta_seconds() * 15.).min(1.0));
+    }
+}
+
+/// `RotateLeft` and `RotateRight` turn the next building (or blueprint) counter-clockwise and
+/// clockwise.
+fn rotate_placement(mut game: ResMut<Game>, settings: Res<GridSettings>, actions: Res<Actions>) {
+    let mut steps = 0;
+    if actions.just_pressed(Action::RotateLeft) {
+        steps += 1;
+    }
+    if actions.just_pressed(Action::RotateRight) {
+        steps -= 1;
+    }
+
+    if steps != 0 {
+        game.orientation = game.orientation.turned(steps, settings.shape);
+    }
+}
+
+/// Scene models spawn their meshes a few frames late, so swap in the ghost material whenever a
+/// material shows up below a `Ghost`.
+fn tint_ghost_materials(
+    cursor_assets: Res<CursorAssets>,
+    ghosts: Query<(), With<Ghost>>,
+    parents: Query<&Parent>,
+    mut new_materials: Query<
+        (Entity, &mut Handle<StandardMaterial>),
+        Added<Handle<StandardMaterial>>,
+    >,
+) {
+    for (entity, mut material) in new_materials.iter_mut() {
+        if parents
+            .iter_ancestors(entity)
+            .any(|ancestor| ghosts.contains(ancestor))
+        {
+            *material = cursor_assets.ghost_material.clone();
+        }
+    }
+}
+
+/// Tints the ghost by whether `place_block` would accept it at the hovered cell and draws the
+/// beams placing it would add or reroute.
+fn preview_placement(
+    mut gizmos: Gizmos,
+    game: Res<Game>,
+    registry: Res<BuildingRegistry>,
+    grid_map: Res<GridMap>,
+    settings: Res<GridSettings>,
+    mouse_grid_pos: Res<MouseGridPosition>,
+    cursor_assets: Res<CursorAssets>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut shown_valid: Local<Option<bool>>,
+    color_wells: Query<(), With<ColorWell>>,
+    active_cursor_attachement: Query<Entity, (With<CursorAttachment>, With<Active>)>,
+    q_intersector: Query<
+        (
+            Entity,
+            &IntersectorType,
+            &GridPosition,
+            &Orientation,
+            &Footprint,
+        ),
+        Without<DeletionPending>,
+    >,
+    q_laser: Query<&Laser>,
+) {
+    let Some(definition) = game
+        .current_placeable
+        .as_ref()
+        .and_then(|placeable| registry.get(placeable))
+    else {
+        return;
+    };
+    let Ok(ghost) = active_cursor_attachement.get_single() else {
+        return;
+    };
+
+    let pivot = mouse_grid_pos.0;
+    let valid = check_placement(
+        definition,
+        &grid_map,
+        settings.shape,
+        pivot,
+        game.orientation,
+        |ground| color_wells.contains(ground),
+    )
+    .is_ok();
+    // only touch the material when the verdict flips, every write re-uploads it
+    if *shown_valid != Some(valid) {
+        if let Some(material) = materials.get_mut(&cursor_assets.ghost_material) {
+            material.base_color = if valid { GHOST_VALID } else { GHOST_INVALID };
+            *shown_valid = Some(valid);
+        }
+    }
+
+    let Some(kind) = definition.intersector else {
+        return;
+    };
+    if !valid {
+        return;
+    }
+
+    // trace every beam again on a copy of the grid with the ghost placed in it, standing in
+    // for the building with the cursor entity
+    let mut grid = grid_map.clone();
+    let cells = definition
+        .footprint
+        .cells(pivot, game.orientation, settings.shape);
+    if grid.set_footprint(GridLayer::Build, cells, ghost).is_err() {
+        return;
+    }
+    let ghost_intersector = TracedIntersector {
+        kind,
+        pivot,
+        orientation: game.orientation,
+        footprint: &definition.footprint,
+    };
+    let lookup = |entity| {
+        if entity == ghost {
+            return Some(ghost_intersector);
+        }
+        q_intersector
+            .get(entity)
+            .ok()
+            .map(
+                |(_, kind, pivot, orientation, footprint)| TracedIntersector {
+                    kind: *kind,
+                    pivot: *pivot,
+                    orientation: *orientation,
+                    footprint,
+                },
+            )
+    };
+
+    let existing: HashSet<(GridPosition, GridPosition, GridDirection)> = q_laser
+        .iter()
+        .map(|laser| (laser.start, laser.end, laser.direction))
+        .collect();
+    let emitters = q_intersector
+        .iter()
+        .filter(|(_, kind, ..)| **kind == IntersectorType::Emitter)
+        .map(|(entity, ..)| entity)
+        .chain((kind == IntersectorType::Emitter).then_some(ghost));
+    for emitter in emitters {
+        for segment in trace_beam(&grid, settings.shape, emitter, lookup) {
+            let laser = segment.laser;
+            if existing.contains(&(laser.start, laser.end, laser.direction)) {
+                continue;
+            }
+            let start = settings.grid_to_world(laser.start);
+            let end = settings.grid_to_world(laser.end);
+            gizmos.line(
+                Vec3::new(start.x, 0.5, start.y),
+                Vec3::new(end.x, 0.5, end.y),
+                GHOST_VALID.with_a(0.8),
+            );
+        }
+    }
+}
+
+/// Places the selected building when the left button is released, on every cell of the line
+/// dragged out since it was pressed where the building fits. The whole line is checked first,
+/// and the cells left out are reported.
+fn place_block(
+    checker: BuildChecker,
+    mut build_commands: EventWriter<BuildCommand>,
+    mut status: EventWriter<StatusMessage>,
+    settings: Res<GridSettings>,
+    registry: Res<BuildingRegistry>,
+    game: Res<Game>,
+    mouse_grid_pos: Res<MouseGridPosition>,
+    actions: Res<Actions>,
+    drag: Res<DragStart>,
+) {
+    let Some(start) = drag.place.filter(|_| actions.just_released(Action::Place)) else {
+        return;
+    };
+    let Some(definition) = game
+        .current_placeable
+        .as_ref()
+        .and_then(|placeable| registry.get(placeable))
+    else {
+        return;
+    };
+
+    let line: Vec<PlacedBuilding> = settings
+        .line(start, mouse_grid_pos.0)
+        .map(|pivot| PlacedBuilding {
+            placeable: definition.id.clone(),
+            pivot,
+            orientation: game.orientation,
+        })
+        .collect();
+    let conflicts = checker.conflicts(&line);
+    if let Some((_, error)) = conflicts.first() {
+        let message = if conflicts.len() == line.len() {
+            format!("Can't place {} here: {}", definition.name, error)
+        } else {
+            format!(
+                "Skipped {} of {} {}: {}",
+                conflicts.len(),
+                line.len(),
+                definition.name,
+                error
+            )
+        };
+        status.send(StatusMessage(message));
+    }
+    let fitting: Vec<PlacedBuilding> = line
+        .iter()
+        .filter(|building| {
+            !conflicts
+                .iter()
+                .any(|(conflict, _)| std::ptr::eq(*conflict, *building))
+        })
+        .cloned()
+        .collect();
+    if fitting.is_empty() {
+        return;
+    }
+    build_commands.send(BuildCommand::Do(BuildAction::Place(fitting)));
+}
+
+/// Demolishes every building touched by a `Demolish` drag box once it is released, or the
+/// selection on `DeleteSelected`.
+fn destroy_block_system(
+    checker: BuildChecker,
+    mut build_commands: EventWriter<BuildCommand>,
+    settings: Res<GridSettings>,
+    actions: Res<Actions>,
+    mouse_grid_pos: Res<MouseGridPosition>,
+    drag: Res<DragStart>,
+    selected: Query<Entity, With<Selected>>,
+) {
+    let mut targets = Vec::new();
+    if let Some(start) = drag
+        .demolish
+        .filter(|_| actions.just_released(Action::Demolish))
+    {
+        targets.extend(buildings_in(
+            checker.grid(),
+            &settings.region(start, mouse_grid_pos.0),
+        ));
+    }
+    if actions.just_pressed(Action::DeleteSelected) {
+        targets.extend(selected.iter());
+    }
+    targets.sort();
+    targets.dedup();
+
+    let buildings: Vec<PlacedBuilding> = targets
+        .into_iter()
+        .filter_map(|entity| checker.describe(entity))
+        .collect();
+    if buildings.is_empty() {
+        return;
+    }
+    build_commands.send(BuildCommand::Do(BuildAction::Remove(buildings)));
+}
+
+/// `RotateBuilding` turns the building under the cursor one step counter-clockwise.
+fn rotate_building(
+    checker: BuildChecker,
+    mut build_commands: EventWriter<BuildCommand>,
+    settings: Res<GridSettings>,
+    actions: Res<Actions>,
+    mouse_grid_pos: Res<MouseGridPosition>,
+) {
+    if !actions.just_pressed(Action::RotateBuilding) {
+        return;
+    }
+    let Some(building) = checker.building_at(mouse_grid_pos.0) else {
+        return;
+    };
+
+    let action = BuildAction::Rotate {
+        pivot: building.pivot,
+        from: building.orientation,
+        to: building.orientation.turned(1, settings.shape),
+    };
+    build_commands.send(BuildCommand::Do(action));
+}
+
+/// `Upgrade` turns the building under the cursor into its next tier.
+fn upgrade_building(
+    checker: BuildChecker,
+    mut build_commands: EventWriter<BuildCommand>,
+    mut status: EventWriter<StatusMessage>,
+    registry: Res<BuildingRegistry>,
+    actions: Res<Actions>,
+    mouse_grid_pos: Res<MouseGridPosition>,
+) {
+    if !actions.just_pressed(Action::Upgrade) {
+        return;
+    }
+    let Some(building) = checker.building_at(mouse_grid_pos.0) else {
+        return;
+    };
+    let Some(to) = registry
+        .get(&building.placeable)
+        .and_then(|definition| definition.upgrade.clone())
+    else {
+        status.send(StatusMessage(format!(
+            "{:?} has no upgrade",
+            building.placeable
+        )));
+        return;
+    };
+    if let Err(error) = checker.check_upgrade(&building, &to) {
+        status.send(StatusMessage(format!(
+            "Can't upgrade {:?} to {:?}: {}",
+            building.placeable, to, error
+        )));
+        return;
+    }
+
+    let action = BuildAction::Upgrade {
+        pivot: building.pivot,
+        from: building.placeable,
+        to,
+    };
+    build_commands.send(BuildCommand::Do(action));
+}
+
+/// Every building with at least one footprint cell inside `region`.
+fn buildings_in(grid_map: &GridMap, region: &GridRegion) -> Vec<Entity> {
+    let mut entities: Vec<Entity> = grid_map
+        .iter_region(GridLayer::Build, region.bounds)
+        .filter(|(position, _)| region.contains(*position))
+        .map(|(_, entity)| entity)
+        .collect();
+    entities.sort();
+    entities.dedup();
+    entities
+}
+
+fn track_drags(
+    actions: Res<Actions>,
+    mouse_grid_pos: Res<MouseGridPosition>,
+    hotbar: Res<Hotbar>,
+    mut drag: ResMut<DragStart>,
+) {
+    let drag = &mut *drag;
+    for (action, start) in [
+        (Action::Place, &mut drag.place),
+        (Action::Demolish, &mut drag.demolish),
+    ] {
+        // presses on the hotbar never start a drag in the world
+        if actions.just_pressed(action) && !hotbar.hovered() {
+            *start = Some(mouse_grid_pos.0);
+        } else if !actions.pressed(action) && !actions.just_released(action) {
+            *start = None;
+        }
+    }
+}
+
+/// Left-dragging with nothing to place selects every building the box touches.
+fn select_buildings(
+    mut commands: Commands,
+    game: Res<Game>,
+    clipboard: Res<Clipboard>,
+    grid_map: Res<GridMap>,
+    settings: Res<GridSettings>,
+    actions: Res<Actions>,
+    mouse_grid_pos: Res<MouseGridPosition>,
+    drag: Res<DragStart>,
+    selected: Query<Entity, With<Selected>>,
+    buildings: Query<(), (With<Building>, Without<DeletionPending>)>,
+) {
+    let release = drag.place.filter(|_| {
+        actions.just_released(Action::Place)
+            && game.current_placeable.is_none()
+            && !clipboard.pasting()
+    });
+    if release.is_none() && !actions.just_pressed(Action::Cancel) {
+        return;
+    }
+
+    for entity in selected.iter() {
+        commands.entity(entity).remove::<Selected>();
+    }
+    if let Some(start) = release {
+        for entity in buildings_in(&grid_map, &settings.region(start, mouse_grid_pos.0)) {
+            if buildings.contains(entity) {
+                commands.entity(entity).insert(Selected);
+            }
+        }
+    }
+}
+
+/// Outlines the selection, and the cells a drag in progress would act on: the line of
+/// buildings being placed, or the box being selected or demolished with the buildings in it.
+fn draw_selection_gizmos(
+    mut gizmos: Gizmos,
+    game: Res<Game>,
+    registry: Res<BuildingRegistry>,
+    grid_map: Res<GridMap>,
+    settings: Res<GridSettings>,
+    mouse_grid_pos: Res<MouseGridPosition>,
+    actions: Res<Actions>,
+    drag: Res<DragStart>,
+    color_wells: Query<(), With<ColorWell>>,
+    active_cursor_attachement: Query<Entity, (With<CursorAttachment>, With<Active>)>,
+    q_buildings: Query<(&GridPosition, &Orientation, &Footprint), Without<DeletionPending>>,
+    selected: Query<Entity, With<Selected>>,
+) {
+    let outline_footprint = |gizmos: &mut Gizmos, entity: Entity, color: Color| {
+        if let Ok((pivot, orientation, footprint)) = q_buildings.get(entity) {
+            let cells: Vec<GridPosition> = footprint
+                .cells(*pivot, *orientation, settings.shape)
+                .collect();
+            outline_cells(
+                gizmos,
+                &settings,
+                cells.iter().copied(),
+                |cell| cells.contains(&cell),
+                color,
+            );
+        }
+    };
+
+    for entity in selected.iter() {
+        outline_footprint(&mut gizmos, entity, SELECTION_COLOR);
+    }
+
+    let definition = game
+        .current_placeable
+        .as_ref()
+        .and_then(|placeable| registry.get(placeable));
+    let (start, color) = match (drag.place, drag.demolish, definition) {
+        (Some(start), _, Some(definition)) if actions.pressed(Action::Place) => {
+            let Ok(placeholder) = active_cursor_attachement.get_single() else {
+                return;
+            };
+            // place the line on a copy of the grid, so overlapping buildings show up as invalid
+            let mut grid = grid_map.clone();
+            for pivot in settings.line(start, mouse_grid_pos.0) {
+                let cells: Vec<GridPosition> = definition
+                    .footprint
+                    .cells(pivot, game.orientation, settings.shape)
+                    .collect();
+                let valid = check_placement(
+                    definition,
+                    &grid,
+                    settings.shape,
+                    pivot,
+                    game.orientation,
+                    |ground| color_wells.contains(ground),
+                )
+                .is_ok()
+                    && grid
+                        .set_footprint(GridLayer::Build, cells.iter().copied(), placeholder)
+                        .is_ok();
+                outline_cells(
+                    &mut gizmos,
+                    &settings,
+                    cells.iter().copied(),
+                    |cell| cells.contains(&cell),
+                    if valid { GHOST_VALID } else { GHOST_INVALID }.with_a(0.9),
+                );
+            }
+            return;
+        }
+        (Some(start), _, None) if actions.pressed(Action::Place) => (start, SELECTION_COLOR),
+        (_, Some(start), _) if actions.pressed(Action::Demolish) => {
+            (start, GHOST_INVALID.with_a(0.9))
+        }
+        _ => return,
+    };
+
+    let region = settings.region(start, mouse_grid_pos.0);
+    outline_cells(
+        &mut gizmos,
+        &settings,
+        region.cells(),
+        |cell| region.contains(cell),
+        color.with_a(0.4),
+    );
+    for entity in buildings_in(&grid_map, &region) {
+        outline_footprint(&mut gizmos, entity, color);
+    }
+}
+
+/// Draws the edges of `cells` that face a cell `inside` rejects, i.e. the outline of the area.
+fn outline_cells(
+    gizmos: &mut Gizmos,
+    settings: &GridSettings,
+    cells: impl IntoIterator<Item = GridPosition>,
+    inside: impl Fn(GridPosition) -> bool,
+    color: Color,
+) {
+    for position in cells {
+        let corners: Vec<Vec2> = settings.cell_corners(position).collect();
+        for direction in settings.shape.directions() {
+            if inside(position.offset(settings.shape.offset(direction))) {
+                continue;
+            }
+            // the edge facing `direction` runs between the corners either side of it
+            let index = direction.steps() as usize;
+            let from = corners[(index + corners.len() - 1) % corners.len()];
+            let to = corners[index];
+            gizmos.line(
+                Vec3::new(from.x, 0.03, from.y),
+                Vec3::new(to.x, 0.03, to.y),
+                color,
+            );
+        }
+    }
+}
@@ -0,0 +1,578 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::{app::AppExit, prelude::*, time::TimeUpdateStrategy};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    construction::ConstructionSettings,
+    controls::{Action, Actions},
+    grid::{GridBounds, GridSettings, GridShape},
+    history::{apply_build_commands, BuildCommand},
+    hotbar::StatusMessage,
+    laser::Laser,
+    level::{CurrentLevel, Level},
+    worldgen::WorldGenSettings,
+    AppState, Building, DeletionPending,
+};
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplaySettings>();
+
+        app.add_systems(Startup, start_replay);
+        app.add_systems(
+            Update,
+            begin_replay
+                .run_if(in_state(AppState::LevelSelect).and_then(resource_exists::<Replayer>)),
+        );
+        app.add_systems(OnEnter(AppState::InGame), start_recording);
+        app.add_systems(OnExit(AppState::InGame), stop_replay);
+        app.add_systems(
+            Update,
+            (
+                (record_frame, feed_replay).before(apply_build_commands),
+                record_commands.after(apply_build_commands),
+                refuse_quick_saves.run_if(in_replay),
+            )
+                .run_if(in_state(AppState::InGame)),
+        );
+        app.add_systems(Last, finish_recording);
+    }
+}
+
+/// Bumped whenever the replay format changes. Replays only play back on the version they were
+/// recorded with, since the game they reproduce has changed along with it.
+pub const REPLAY_VERSION: u32 = 1;
+
+/// Files to record the game to and play a recording back from, both off by default. Only build
+/// commands are recorded, so quick saves and loads are off while either is going on.
+#[derive(Resource, Default, Debug)]
+pub struct ReplaySettings {
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+}
+
+/// Everything needed to play a game again exactly as it went: how it started, how long every
+/// frame took and what was built when.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Replay {
+    pub version: u32,
+    pub shape: GridShape,
+    pub seed: u64,
+    /// Extent of the free play map, older replays were all played on the default one.
+    #[serde(default)]
+    pub bounds: GridBounds,
+    pub construction: bool,
+    pub builders: usize,
+    /// `ConstructionSettings::rate`, which older replays all ran at the default of.
+    #[serde(default = "default_build_rate")]
+    pub build_rate: f32,
+    /// The level played, with its contents, so editing the file later does not change the
+    /// replay. `None` for free play.
+    pub level: Option<Level>,
+    /// Length of every frame in game, in nanoseconds. The economy and construction advance by
+    /// these, so they are replayed to the nanosecond.
+    pub frames: Vec<u32>,
+    pub commands: Vec<RecordedCommand>,
+}
+
+fn default_build_rate() -> f32 {
+    ConstructionSettings::default().rate
+}
+
+/// A build command, and the frame it was carried out in.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecordedCommand {
+    pub tick: usize,
+    pub command: BuildCommand,
+}
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("could not access replay file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse replay: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("could not write replay: {0}")]
+    Serialize(#[from] ron::Error),
+    #[error("replay is version {version}, only version {REPLAY_VERSION} can be played")]
+    Version { version: u32 },
+}
+
+impl Replay {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        let replay: Self = ron::from_str(&fs::read_to_string(path)?)?;
+        if replay.version != REPLAY_VERSION {
+            return Err(ReplayError::Version {
+                version: replay.version,
+            });
+        }
+        Ok(replay)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        if let Some(folder) = path.as_ref().parent() {
+            fs::create_dir_all(folder)?;
+        }
+        fs::write(path, ron::ser::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// The game being recorded, written out whenever something is built and on exit.
+#[derive(Resource)]
+pub struct Recorder {
+    path: PathBuf,
+    replay: Replay,
+}
+
+impl Recorder {
+    fn save(&self) {
+        if let Err(error) = self.replay.write(&self.path) {
+            warn!(
+                "Could not write replay to {}: {}",
+                self.path.display(),
+                error
+            );
+        }
+    }
+}
+
+/// A replay being played back. Present only while it runs; the player's input builds nothing
+/// meanwhile. The app's first frame always takes no time, so the game starts on the second one,
+/// which takes as long as the first recorded frame.
+#[derive(Resource)]
+pub struct Replayer {
+    replay: Replay,
+    /// Frame about to be played.
+    tick: usize,
+    /// Index of the first command not sent yet.
+    next_command: usize,
+}
+
+/// Sets the game up the way the replay started, to skip the level select on the next frame.
+fn start_replay(
+    mut commands: Commands,
+    settings: Res<ReplaySettings>,
+    mut grid_settings: ResMut<GridSettings>,
+    mut worldgen: ResMut<WorldGenSettings>,
+    mut construction: ResMut<ConstructionSettings>,
+    mut current: ResMut<CurrentLevel>,
+) {
+    let Some(path) = &settings.replay else {
+        return;
+    };
+    let replay = match Replay::read(path) {
+        Ok(replay) => replay,
+        Err(error) => {
+            warn!("Could not play {}: {}", path.display(), error);
+            return;
+        }
+    };
+    info!(
+        "Replaying {} ({} frames)",
+        path.display(),
+        replay.frames.len()
+    );
+
+    grid_settings.shape = replay.shape;
+    worldgen.seed = replay.seed;
+    worldgen.bounds = replay.bounds;
+    construction.enabled = replay.construction;
+    construction.builders = replay.builders;
+    construction.rate = replay.build_rate;
+    current.0 = replay.level.clone();
+    if let Some(frame) = replay.frames.first() {
+        commands.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_nanos(
+            *frame as u64,
+        )));
+    }
+    commands.insert_resource(Replayer {
+        replay,
+        tick: 0,
+        next_command: 0,
+    });
+}
+
+/// Starts the replayed game once the app's first frame is over.
+fn begin_replay(mut next_state: ResMut<NextState<AppState>>) {
+    next_state.set(AppState::InGame);
+}
+
+/// Leaving the game halfway through a replay hands it back to the player.
+fn stop_replay(mut commands: Commands, replayer: Option<Res<Replayer>>) {
+    let Some(replayer) = replayer else {
+        return;
+    };
+    info!("Replay stopped after {} frames", replayer.tick);
+    commands.insert_resource(TimeUpdateStrategy::Automatic);
+    commands.remove_resource::<Replayer>();
+}
+
+/// Whether a game is being recorded or played back.
+pub fn in_replay(recorder: Option<Res<Recorder>>, replayer: Option<Res<Replayer>>) -> bool {
+    recorder.is_some() || replayer.is_some()
+}
+
+/// Tells the player why `QuickSave` and `QuickLoad` do nothing: a replay only holds the build
+/// commands, so a loaded game would send it astray.
+fn refuse_quick_saves(actions: Res<Actions>, mut status: EventWriter<StatusMessage>) {
+    if actions.just_pressed(Action::QuickSave) || actions.just_pressed(Action::QuickLoad) {
+        status.send(StatusMessage::new(
+            "Quick save and load are off while a replay is recorded or played",
+        ));
+    }
+}
+
+/// Starts recording when the game begins, unless it is a replay being played. Test plays from
+/// the editor start the level over, so each one records over the last.
+fn start_recording(
+    mut commands: Commands,
+    settings: Res<ReplaySettings>,
+    grid_settings: Res<GridSettings>,
+    worldgen: Res<WorldGenSettings>,
+    construction: Res<ConstructionSettings>,
+    current: Res<CurrentLevel>,
+    replayer: Option<Res<Replayer>>,
+) {
+    let Some(path) = settings.record.as_ref().filter(|_| replayer.is_none()) else {
+        return;
+    };
+    info!("Recording the game to {}", path.display());
+    commands.insert_resource(Recorder {
+        path: path.clone(),
+        replay: Replay {
+            version: REPLAY_VERSION,
+            shape: grid_settings.shape,
+            seed: worldgen.seed,
+            bounds: worldgen.bounds,
+            construction: construction.enabled,
+            builders: construction.builders,
+            build_rate: construction.rate,
+            level: current.0.clone(),
+            frames: Vec::new(),
+            commands: Vec::new(),
+        },
+    });
+}
+
+fn record_frame(time: Res<Time>, recorder: Option<ResMut<Recorder>>) {
+    if let Some(mut recorder) = recorder {
+        recorder.replay.frames.push(time.delta().as_nanos() as u32);
+    }
+}
+
+/// Adds this frame's build commands to the recording and writes it out.
+fn record_commands(
+    mut build_commands: EventReader<BuildCommand>,
+    recorder: Option<ResMut<Recorder>>,
+) {
+    let Some(mut recorder) = recorder else {
+        build_commands.clear();
+        return;
+    };
+    if build_commands.is_empty() {
+        return;
+    }
+    let tick = recorder.replay.frames.len().saturating_sub(1);
+    for command in build_commands.read() {
+        recorder.replay.commands.push(RecordedCommand {
+            tick,
+            command: command.clone(),
+        });
+    }
+    recorder.save();
+}
+
+fn finish_recording(exit: EventReader<AppExit>, recorder: Option<Res<Recorder>>) {
+    if let Some(recorder) = recorder.filter(|_| !exit.is_empty()) {
+        recorder.save();
+    }
+}
+
+/// Sends the commands recorded for this frame into the same stream the player's input goes to,
+/// and sets how long the next frame takes. Once every frame is played, time runs normally again
+/// and the player takes over.
+fn feed_replay(
+    mut commands: Commands,
+    replayer: Option<ResMut<Replayer>>,
+    mut build_commands: EventWriter<BuildCommand>,
+    buildings: Query<(), (With<Building>, Without<DeletionPending>)>,
+    lasers: Query<(), With<Laser>>,
+) {
+    let Some(mut replayer) = replayer else {
+        return;
+    };
+    let replayer = &mut *replayer;
+    let tick = replayer.tick;
+    if tick >= replayer.replay.frames.len() {
+        // time jumps back to the wall clock, the next frame is held to `Time<Virtual>`'s max delta
+        info!(
+            "Replay finished after {} frames: {} buildings, {} beam segments",
+            tick,
+            buildings.iter().count(),
+            lasers.iter().count()
+        );
+        commands.insert_resource(TimeUpdateStrategy::Automatic);
+        commands.remove_resource::<Replayer>();
+        return;
+    }
+
+    let pending = &replayer.replay.commands[replayer.next_command..];
+    let due = pending
+        .iter()
+        .take_while(|recorded| recorded.tick <= tick)
+        .count();
+    build_commands.send_batch(
+        pending[..due]
+            .iter()
+            .map(|recorded| recorded.command.clone()),
+    );
+    replayer.next_command += due;
+
+    replayer.tick += 1;
+    if let Some(frame) = replayer.replay.frames.get(replayer.tick) {
+        commands.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_nanos(
+            *frame as u64,
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        building::Placeable,
+        controls::Actions,
+        grid::{GridLayer, GridMap},
+        history::{BuildAction, PlacedBuilding},
+        laser::LaserPlugin,
+        testing::build_app,
+        ColorWell, GridPosition, LightColor, Orientation,
+    };
+
+    /// Frame lengths the game itself saw.
+    #[derive(Resource, Default)]
+    struct Seen(Vec<Duration>);
+
+    fn see_frame(time: Res<Time>, mut seen: ResMut<Seen>) {
+        seen.0.push(time.delta());
+    }
+
+    #[test]
+    fn every_recorded_frame_is_played_at_its_length() {
+        let frames = [5_000_000, 7_000_000, 11_000_000];
+        let path = std::env::temp_dir().join(format!("replay_timing_{}.ron", std::process::id()));
+        Replay {
+            version: REPLAY_VERSION,
+            shape: GridShape::Square,
+            seed: 1,
+            bounds: GridBounds::default(),
+            construction: true,
+            builders: 1,
+            build_rate: default_build_rate(),
+            level: None,
+            frames: frames.to_vec(),
+            commands: Vec::new(),
+        }
+        .write(&path)
+        .unwrap();
+
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, ReplayPlugin))
+            .init_state::<AppState>()
+            .insert_resource(ReplaySettings {
+                record: None,
+                replay: Some(path.clone()),
+            })
+            .init_resource::<GridSettings>()
+            .init_resource::<WorldGenSettings>()
+            .init_resource::<ConstructionSettings>()
+            .init_resource::<CurrentLevel>()
+            .init_resource::<Actions>()
+            .init_resource::<Seen>()
+            .add_event::<BuildCommand>()
+            .add_event::<StatusMessage>()
+            .add_systems(Update, see_frame.run_if(in_state(AppState::InGame)));
+        for _ in 0..5 {
+            app.update();
+        }
+        fs::remove_file(&path).unwrap();
+
+        let seen = &app.world.resource::<Seen>().0;
+        let played: Vec<u64> = seen[..frames.len()]
+            .iter()
+            .map(|frame| frame.as_nanos() as u64)
+            .collect();
+        assert_eq!(played, frames.map(|frame| frame as u64));
+        assert!(app.world.get_resource::<Replayer>().is_none());
+    }
+
+    /// A headless game with an orange well at the origin, recording to or replaying from the
+    /// files in `settings`.
+    fn game(shape: GridShape, settings: ReplaySettings) -> App {
+        let mut app = build_app(shape);
+        app.add_plugins((ReplayPlugin, LaserPlugin))
+            .init_state::<AppState>()
+            .insert_resource(settings)
+            .init_resource::<WorldGenSettings>()
+            .init_resource::<CurrentLevel>()
+            .init_resource::<Actions>();
+        let well = app
+            .world
+            .spawn(ColorWell {
+                color: LightColor::Orange,
+            })
+            .id();
+        app.world
+            .resource_mut::<GridMap>()
+            .set(GridLayer::Ground, GridPosition { x: 0, y: 0 }, well)
+            .unwrap();
+        app
+    }
+
+    fn placed(placeable: &str, x: i32, y: i32, orientation: u8) -> PlacedBuilding {
+        PlacedBuilding {
+            placeable: Placeable(placeable.to_string()),
+            pivot: GridPosition { x, y },
+            orientation: Orientation(orientation),
+        }
+    }
+
+    type Cell = (i32, i32);
+
+    /// What the game looks like, without the entity ids that differ from run to run: the
+    /// building covering each cell, every building, and every beam segment.
+    #[derive(PartialEq, Debug)]
+    struct Snapshot {
+        cells: Vec<(Cell, Placeable)>,
+        buildings: Vec<(Cell, Placeable, Orientation)>,
+        beams: Vec<(Cell, usize, Cell, Cell)>,
+    }
+
+    fn snapshot(app: &mut App) -> Snapshot {
+        let cell = |position: &GridPosition| (position.x, position.y);
+        let grid = app.world.resource::<GridMap>();
+        let placeable = |entity: &Entity| app.world.get::<Placeable>(*entity).unwrap().clone();
+        let mut cells: Vec<(Cell, Placeable)> = grid
+            .iter_region(GridLayer::Build, grid.bounds())
+            .map(|(position, entity)| (cell(&position), placeable(&entity)))
+            .collect();
+        cells.sort_by_key(|(cell, _)| *cell);
+
+        let mut buildings: Vec<(Cell, Placeable, Orientation)> = app
+            .world
+            .query_filtered::<(&Placeable, &GridPosition, &Orientation), (
+                With<Building>,
+                Without<DeletionPending>,
+            )>()
+            .iter(&app.world)
+            .map(|(placeable, pivot, orientation)| (cell(pivot), placeable.clone(), *orientation))
+            .collect();
+        buildings.sort_by_key(|(pivot, ..)| *pivot);
+
+        let mut beams: Vec<(Cell, usize, Cell, Cell)> = app
+            .world
+            .query::<&Laser>()
+            .iter(&app.world)
+            .map(|laser| {
+                let source = laser
+                    .source
+                    .and_then(|source| app.world.get::<GridPosition>(source))
+                    .unwrap();
+                (
+                    cell(source),
+                    laser.index,
+                    cell(&laser.start),
+                    cell(&laser.end),
+                )
+            })
+            .collect();
+        beams.sort();
+        Snapshot {
+            cells,
+            buildings,
+            beams,
+        }
+    }
+
+    fn replays_match_the_recorded_game(shape: GridShape) {
+        let path =
+            std::env::temp_dir().join(format!("replay_{:?}_{}.ron", shape, std::process::id()));
+        let mut recorded = game(
+            shape,
+            ReplaySettings {
+                record: Some(path.clone()),
+                replay: None,
+            },
+        );
+        recorded
+            .world
+            .resource_mut::<NextState<AppState>>()
+            .set(AppState::InGame);
+        recorded.update();
+
+        let commands = [
+            BuildCommand::Do(BuildAction::Place(vec![
+                placed("collector", 0, 0, 0),
+                placed("mirror", 0, 3, 0),
+                placed("storage", 4, 3, 0),
+            ])),
+            BuildCommand::Do(BuildAction::Place(vec![
+                placed("wall", -3, 6, 1),
+                placed("mirror", 2, -2, 0),
+            ])),
+            BuildCommand::Do(BuildAction::Rotate {
+                pivot: GridPosition { x: 0, y: 3 },
+                from: Orientation(0),
+                to: Orientation(1),
+            }),
+            BuildCommand::Do(BuildAction::Remove(vec![placed("mirror", 2, -2, 0)])),
+            BuildCommand::Undo,
+            BuildCommand::Do(BuildAction::Remove(vec![placed("wall", -3, 6, 1)])),
+            BuildCommand::Undo,
+            BuildCommand::Undo,
+        ];
+        for command in commands {
+            recorded.world.send_event(command);
+            recorded.update();
+            recorded.update();
+        }
+        let expected = snapshot(&mut recorded);
+        assert_eq!(expected.buildings.len(), 5);
+        assert!(expected.beams.len() > 1, "{:?}", expected.beams);
+
+        let replay = Replay::read(&path).unwrap();
+        assert_eq!(replay.commands.len(), 8);
+        let mut replayed = game(
+            shape,
+            ReplaySettings {
+                record: None,
+                replay: Some(path.clone()),
+            },
+        );
+        for _ in 0..replay.frames.len() + 5 {
+            replayed.update();
+        }
+        fs::remove_file(&path).unwrap();
+        assert!(replayed.world.get_resource::<Replayer>().is_none());
+        assert_eq!(snapshot(&mut replayed), expected);
+    }
+
+    #[test]
+    fn replays_match_the_recorded_game_on_square_grids() {
+        replays_match_the_recorded_game(GridShape::Square);
+    }
+
+    #[test]
+    fn replays_match_the_recorded_game_on_hex_grids() {
+        replays_match_the_recorded_game(GridShape::Hexagonal);
+    }
+}
@@ -0,0 +1,829 @@
+use std::{fs, path::Path};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    building::{Builder, Placeable},
+    camera::{Glide, MainCamera, Orbit},
+    construction::ConstructionSite,
+    controls::{Action, Actions},
+    economy::Resources,
+    grid::{GridBounds, GridMap, GridSettings, GridShape},
+    history::{History, PlacedBuilding},
+    level::{Fixed, Inventory, RequiredColor},
+    worldgen::{obstacle_assets, spawn_color_well, spawn_obstacle, Obstacle},
+    Building, ColorWell, DeletionPending, GridPosition, LightColor, Orientation,
+};
+
+/// Where `QuickSave` writes the game and `QuickLoad` reads it from.
+const QUICKSAVE_PATH: &str = "saves/quicksave.ron";
+
+/// Version of the save format written by this build. Bump it whenever `SaveGame` changes in a
+/// way old files would not parse as, and add a migration from the previous version.
+pub const SAVE_VERSION: u32 = 3;
+
+/// Everything needed to rebuild a game. Beams are not stored: they follow from the buildings
+/// and are traced again after loading.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SaveGame {
+    /// Always `SAVE_VERSION` once parsed; older files are migrated on the way in.
+    pub version: u32,
+    pub shape: GridShape,
+    pub bounds: GridBounds,
+    pub wells: Vec<SavedWell>,
+    pub obstacles: Vec<SavedObstacle>,
+    pub buildings: Vec<SavedBuilding>,
+    pub resources: Resources,
+    pub inventory: Inventory,
+    pub camera: SavedCamera,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SavedWell {
+    pub position: GridPosition,
+    pub color: LightColor,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SavedObstacle {
+    pub position: GridPosition,
+    pub height: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SavedBuilding {
+    pub placeable: Placeable,
+    pub pivot: GridPosition,
+    #[serde(default)]
+    pub orientation: Orientation,
+    /// Seconds of work done, if it is still a construction site.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<f32>,
+    /// Part of the level being played.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fixed: bool,
+    /// Colour a level's receiver has to be sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requires: Option<LightColor>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SavedCamera {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub yaw: f32,
+    /// Orthographic scale; perspective cameras zoom by moving, which `translation` covers.
+    pub scale: f32,
+}
+
+#[derive(Error, Debug)]
+pub enum SaveError {
+    #[error("could not access save file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse save: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("could not write save: {0}")]
+    Serialize(#[from] ron::Error),
+    #[error("save has format version {version}, this build reads up to {SAVE_VERSION}")]
+    Newer { version: u32 },
+    #[error("save has unknown format version {version}")]
+    Unknown { version: u32 },
+    #[error("save is for a {saved:?} grid, but the game runs on a {current:?} one")]
+    Shape {
+        saved: GridShape,
+        current: GridShape,
+    },
+}
+
+impl SaveGame {
+    pub fn to_ron(&self) -> Result<String, SaveError> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    /// Reads a save of any version up to `SAVE_VERSION`. Older ones are parsed as the format they
+    /// were written in and migrated one version at a time, each step only knowing the next.
+    pub fn parse(text: &str) -> Result<Self, SaveError> {
+        let SaveHeader { version } = ron::from_str(text)?;
+        match version {
+            1 => Ok(Self::from(v2::SaveGame::from(
+                ron::from_str::<v1::SaveGame>(text)?,
+            ))),
+            2 => Ok(Self::from(ron::from_str::<v2::SaveGame>(text)?)),
+            SAVE_VERSION => Ok(ron::from_str(text)?),
+            version if version > SAVE_VERSION => Err(SaveError::Newer { version }),
+            version => Err(SaveError::Unknown { version }),
+        }
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+        if let Some(folder) = path.as_ref().parent() {
+            fs::create_dir_all(folder)?;
+        }
+        fs::write(path, self.to_ron()?)?;
+        Ok(())
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, SaveError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+/// The part of a save that is read first, to tell how to read the rest.
+#[derive(Deserialize)]
+struct SaveHeader {
+    /// Saves from before the header existed are version 1.
+    #[serde(default = "first_version")]
+    version: u32,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+/// The first format, frozen as it was written: no header, wells stored as `(position, color)`
+/// pairs. These types must stay as they are when the current ones change, or old files stop
+/// parsing; the game's own value types, like positions and colours, are shared by every format.
+mod v1 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct SaveGame {
+        pub shape: GridShape,
+        pub wells: Vec<(GridPosition, LightColor)>,
+        pub obstacles: Vec<SavedObstacle>,
+        pub buildings: Vec<SavedBuilding>,
+        pub resources: Resources,
+        pub camera: SavedCamera,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SavedObstacle {
+        pub position: GridPosition,
+        pub height: f32,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SavedBuilding {
+        pub placeable: Placeable,
+        pub pivot: GridPosition,
+        #[serde(default)]
+        pub orientation: Orientation,
+        #[serde(default)]
+        pub progress: Option<f32>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SavedCamera {
+        pub translation: Vec3,
+        pub rotation: Quat,
+        pub yaw: f32,
+        pub scale: f32,
+    }
+}
+
+/// Version 1 to 2: wells become named structs.
+impl From<v1::SaveGame> for v2::SaveGame {
+    fn from(save: v1::SaveGame) -> Self {
+        Self {
+            shape: save.shape,
+            wells: save
+                .wells
+                .into_iter()
+                .map(|(position, color)| v2::SavedWell { position, color })
+                .collect(),
+            obstacles: save
+                .obstacles
+                .into_iter()
+                .map(|obstacle| v2::SavedObstacle {
+                    position: obstacle.position,
+                    height: obstacle.height,
+                })
+                .collect(),
+            buildings: save
+                .buildings
+                .into_iter()
+                .map(|building| v2::SavedBuilding {
+                    placeable: building.placeable,
+                    pivot: building.pivot,
+                    orientation: building.orientation,
+                    progress: building.progress,
+                })
+                .collect(),
+            resources: save.resources,
+            camera: v2::SavedCamera {
+                translation: save.camera.translation,
+                rotation: save.camera.rotation,
+                yaw: save.camera.yaw,
+                scale: save.camera.scale,
+            },
+        }
+    }
+}
+
+/// The second format, frozen: versioned, with named wells.
+mod v2 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct SaveGame {
+        pub shape: GridShape,
+        pub wells: Vec<SavedWell>,
+        pub obstacles: Vec<SavedObstacle>,
+        pub buildings: Vec<SavedBuilding>,
+        pub resources: Resources,
+        pub camera: SavedCamera,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SavedWell {
+        pub position: GridPosition,
+        pub color: LightColor,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SavedObstacle {
+        pub position: GridPosition,
+        pub height: f32,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SavedBuilding {
+        pub placeable: Placeable,
+        pub pivot: GridPosition,
+        #[serde(default)]
+        pub orientation: Orientation,
+        #[serde(default)]
+        pub progress: Option<f32>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SavedCamera {
+        pub translation: Vec3,
+        pub rotation: Quat,
+        pub yaw: f32,
+        pub scale: f32,
+    }
+}
+
+/// Version 2 to 3: the map's bounds, the level inventory and the level's markers on buildings
+/// are stored. Older games were free play on a map of the default size.
+impl From<v2::SaveGame> for SaveGame {
+    fn from(save: v2::SaveGame) -> Self {
+        Self {
+            version: SAVE_VERSION,
+            shape: save.shape,
+            bounds: GridBounds::default(),
+            wells: save
+                .wells
+                .into_iter()
+                .map(|well| SavedWell {
+                    position: well.position,
+                    color: well.color,
+                })
+                .collect(),
+            obstacles: save
+                .obstacles
+                .into_iter()
+                .map(|obstacle| SavedObstacle {
+                    position: obstacle.position,
+                    height: obstacle.height,
+                })
+                .collect(),
+            buildings: save
+                .buildings
+                .into_iter()
+                .map(|building| SavedBuilding {
+                    placeable: building.placeable,
+                    pivot: building.pivot,
+                    orientation: building.orientation,
+                    progress: building.progress,
+                    fixed: false,
+                    requires: None,
+                })
+                .collect(),
+            resources: save.resources,
+            inventory: Inventory::default(),
+            camera: SavedCamera {
+                translation: save.camera.translation,
+                rotation: save.camera.rotation,
+                yaw: save.camera.yaw,
+                scale: save.camera.scale,
+            },
+        }
+    }
+}
+
+/// Buildings of a loaded game, waiting for the world they stand on to be spawned.
+#[derive(Resource)]
+pub struct PendingRestore(pub Vec<SavedBuilding>);
+
+/// The parts of the world a save is taken from. Buildings being demolished are left out.
+#[derive(SystemParam)]
+pub struct Saver<'w, 's> {
+    settings: Res<'w, GridSettings>,
+    grid_map: Res<'w, GridMap>,
+    resources: Res<'w, Resources>,
+    inventory: Res<'w, Inventory>,
+    wells: Query<'w, 's, (&'static GridPosition, &'static ColorWell)>,
+    obstacles: Query<'w, 's, (&'static GridPosition, &'static Transform), With<Obstacle>>,
+    buildings: Query<
+        'w,
+        's,
+        (
+            &'static Placeable,
+            &'static GridPosition,
+            &'static Orientation,
+            Option<&'static ConstructionSite>,
+            Option<&'static Fixed>,
+            Option<&'static RequiredColor>,
+        ),
+        (With<Building>, Without<DeletionPending>),
+    >,
+    camera:
+        Query<'w, 's, (&'static Transform, &'static Projection, &'static Orbit), With<MainCamera>>,
+}
+
+impl Saver<'_, '_> {
+    pub fn save(&self) -> SaveGame {
+        let (camera_transform, projection, orbit) = self.camera.single();
+
+        let mut save = SaveGame {
+            version: SAVE_VERSION,
+            shape: self.settings.shape,
+            bounds: self.grid_map.bounds(),
+            wells: self
+                .wells
+                .iter()
+                .map(|(position, well)| SavedWell {
+                    position: *position,
+                    color: well.color,
+                })
+                .collect(),
+            obstacles: self
+                .obstacles
+                .iter()
+                .map(|(position, transform)| SavedObstacle {
+                    position: *position,
+                    height: transform.scale.y,
+                })
+                .collect(),
+            buildings: self
+                .buildings
+                .iter()
+                .map(
+                    |(placeable, pivot, orientation, site, fixed, requires)| SavedBuilding {
+                        placeable: placeable.clone(),
+                        pivot: *pivot,
+                        orientation: *orientation,
+                        progress: site.map(|site| site.progress),
+                        fixed: fixed.is_some(),
+                        requires: requires.map(|requires| requires.0),
+                    },
+                )
+                .collect(),
+            resources: self.resources.clone(),
+            inventory: self.inventory.clone(),
+            camera: SavedCamera {
+                translation: camera_transform.translation,
+                rotation: camera_transform.rotation,
+                yaw: orbit.target_yaw,
+                scale: match projection {
+                    Projection::Orthographic(projection) => projection.scale,
+                    Projection::Perspective(_) => 1.0,
+                },
+            },
+        };
+        // query order is arbitrary, sort so saving twice gives the same file
+        let key = |position: &GridPosition| (position.y, position.x);
+        save.wells.sort_by_key(|well| key(&well.position));
+        save.obstacles
+            .sort_by_key(|obstacle| key(&obstacle.position));
+        save.buildings.sort_by_key(|building| key(&building.pivot));
+        save
+    }
+}
+
+/// Everything a save replaces. The terrain is spawned straight away, the buildings once it
+/// exists, by `restore_buildings`.
+#[derive(SystemParam)]
+pub struct Loader<'w, 's> {
+    commands: Commands<'w, 's>,
+    settings: Res<'w, GridSettings>,
+    grid_map: ResMut<'w, GridMap>,
+    resources: ResMut<'w, Resources>,
+    inventory: ResMut<'w, Inventory>,
+    history: ResMut<'w, History>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+    existing: Query<'w, 's, Entity, Or<(With<Building>, With<ColorWell>, With<Obstacle>)>>,
+    camera: Query<
+        'w,
+        's,
+        (
+            &'static mut Transform,
+            &'static mut Projection,
+            &'static mut Orbit,
+            &'static mut Glide,
+        ),
+        With<MainCamera>,
+    >,
+}
+
+impl Loader<'_, '_> {
+    /// Replaces the world with the saved one, unless it was saved on a different grid.
+    pub fn load(&mut self, save: SaveGame) -> Result<(), SaveError> {
+        if save.shape != self.settings.shape {
+            return Err(SaveError::Shape {
+                saved: save.shape,
+                current: self.settings.shape,
+            });
+        }
+
+        for entity in self.existing.iter() {
+            self.commands.entity(entity).despawn_recursive();
+        }
+        *self.grid_map = GridMap::new(save.bounds);
+        // the actions refer to buildings that are gone now
+        self.history.clear();
+
+        for well in save.wells {
+            spawn_color_well(
+                &mut self.commands,
+                &mut self.grid_map,
+                &self.settings,
+                well.position,
+                well.color,
+                &mut self.meshes,
+                &mut self.materials,
+            );
+        }
+        let (mesh, material) = obstacle_assets(&mut self.meshes, &mut self.materials);
+        for obstacle in save.obstacles {
+            spawn_obstacle(
+                &mut self.commands,
+                &mut self.grid_map,
+                &self.settings,
+                obstacle.position,
+                obstacle.height,
+                mesh.clone(),
+                material.clone(),
+            );
+        }
+        *self.resources = save.resources;
+        *self.inventory = save.inventory;
+
+        for (mut transform, mut projection, mut orbit, mut glide) in &mut self.camera {
+            transform.translation = save.camera.translation;
+            transform.rotation = save.camera.rotation;
+            orbit.yaw = save.camera.yaw;
+            orbit.target_yaw = save.camera.yaw;
+            *glide = Glide::default();
+            if let Projection::Orthographic(projection) = &mut *projection {
+                projection.scale = save.camera.scale;
+            }
+        }
+
+        self.commands
+            .insert_resource(PendingRestore(save.buildings));
+        Ok(())
+    }
+}
+
+/// `QuickSave` writes the game to `QUICKSAVE_PATH`.
+pub fn quick_save(actions: Res<Actions>, saver: Saver) {
+    if !actions.just_pressed(Action::QuickSave) {
+        return;
+    }
+    match saver.save().write(QUICKSAVE_PATH) {
+        Ok(()) => info!("Saved the game to {}", QUICKSAVE_PATH),
+        Err(error) => warn!("Could not save to {}: {}", QUICKSAVE_PATH, error),
+    }
+}
+
+/// `QuickLoad` replaces the world with the one in `QUICKSAVE_PATH`.
+pub fn quick_load(actions: Res<Actions>, mut loader: Loader) {
+    if !actions.just_pressed(Action::QuickLoad) {
+        return;
+    }
+    match SaveGame::read(QUICKSAVE_PATH).and_then(|save| loader.load(save)) {
+        Ok(()) => info!("Loaded the game from {}", QUICKSAVE_PATH),
+        Err(error) => warn!("Could not load {}: {}", QUICKSAVE_PATH, error),
+    }
+}
+
+/// Places the buildings of a game or level that was just loaded, now that the wells they stand
+/// on exist.
+pub fn restore_buildings(
+    mut commands: Commands,
+    pending: Option<Res<PendingRestore>>,
+    mut builder: Builder,
+) {
+    let Some(pending) = pending else {
+        return;
+    };
+    commands.remove_resource::<PendingRestore>();
+
+    let buildings: Vec<(PlacedBuilding, Option<f32>)> = pending
+        .0
+        .iter()
+        .map(|building| {
+            (
+                PlacedBuilding {
+                    placeable: building.placeable.clone(),
+                    pivot: building.pivot,
+                    orientation: building.orientation,
+                },
+                building.progress,
+            )
+        })
+        .collect();
+    let entities = builder.restore(&buildings);
+    for (building, entity) in pending.0.iter().zip(&entities) {
+        let Some(entity) = entity else {
+            continue;
+        };
+        let mut entity = commands.entity(*entity);
+        if building.fixed {
+            entity.insert(Fixed);
+        }
+        if let Some(color) = building.requires {
+            entity.insert(RequiredColor(color));
+        }
+    }
+    let restored = entities.iter().flatten().count();
+    if restored < buildings.len() {
+        warn!("Restored {} of {} buildings", restored, buildings.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_3;
+
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::{
+        history::{apply_build_commands, BuildAction, BuildCommand},
+        testing::build_app,
+    };
+
+    /// A headless game with a camera, restoring loaded buildings like the real one.
+    fn app(shape: GridShape) -> App {
+        let mut app = build_app(shape);
+        app.add_systems(Update, restore_buildings.after(apply_build_commands));
+        app.world.spawn((
+            Transform::default(),
+            Projection::Orthographic(OrthographicProjection::default()),
+            Orbit::default(),
+            Glide::default(),
+            MainCamera,
+        ));
+        app
+    }
+
+    fn save(app: &mut App) -> SaveGame {
+        app.world.run_system_once(|saver: Saver| saver.save())
+    }
+
+    fn load(app: &mut App, save: SaveGame) -> Result<(), SaveError> {
+        let result = app
+            .world
+            .run_system_once_with(save, |In(save), mut loader: Loader| loader.load(save));
+        // spawn the terrain, then the buildings on it
+        app.update();
+        app.update();
+        result
+    }
+
+    fn building(id: &str, x: i32, y: i32, orientation: u8) -> PlacedBuilding {
+        PlacedBuilding {
+            placeable: Placeable(id.to_string()),
+            pivot: GridPosition { x, y },
+            orientation: Orientation(orientation),
+        }
+    }
+
+    /// A world with terrain, finished buildings, a construction site, a level's fixed receiver
+    /// and a moved camera.
+    fn played_world(shape: GridShape) -> App {
+        let mut app = app(shape);
+        let terrain = SaveGame {
+            version: SAVE_VERSION,
+            shape,
+            bounds: GridBounds::centred(40, 30),
+            wells: vec![SavedWell {
+                position: GridPosition { x: 3, y: 0 },
+                color: LightColor::Orange,
+            }],
+            obstacles: vec![SavedObstacle {
+                position: GridPosition { x: -2, y: -2 },
+                height: 1.5,
+            }],
+            buildings: vec![SavedBuilding {
+                placeable: Placeable("storage".to_string()),
+                pivot: GridPosition { x: 5, y: 5 },
+                orientation: Orientation(0),
+                progress: None,
+                fixed: true,
+                requires: Some(LightColor::Orange),
+            }],
+            resources: Resources::new([(LightColor::Orange, 500)]),
+            inventory: Inventory::default(),
+            camera: SavedCamera {
+                translation: Vec3::ZERO,
+                rotation: Quat::IDENTITY,
+                yaw: 0.0,
+                scale: 1.0,
+            },
+        };
+        load(&mut app, terrain).unwrap();
+
+        app.world
+            .send_event(BuildCommand::Do(BuildAction::Place(vec![
+                building("collector", 3, 0, 0),
+                building("mirror", 0, 1, 1),
+                building("wall", -1, 4, 2),
+                building("storage", -3, 2, 0),
+            ])));
+        app.update();
+
+        let mut camera = app
+            .world
+            .query_filtered::<(&mut Transform, &mut Projection, &mut Orbit), With<MainCamera>>();
+        let (mut transform, mut projection, mut orbit) = camera.single_mut(&mut app.world);
+        *transform = Transform::from_xyz(4.0, 12.0, -7.5).looking_at(Vec3::ZERO, Vec3::Y);
+        orbit.target_yaw = FRAC_PI_3;
+        if let Projection::Orthographic(projection) = &mut *projection {
+            projection.scale = 0.75;
+        }
+        app
+    }
+
+    fn round_trip(shape: GridShape) {
+        let mut played = played_world(shape);
+        let saved = save(&mut played);
+        assert_eq!(saved.wells.len(), 1);
+        assert_eq!(saved.obstacles.len(), 1);
+        assert_eq!(saved.buildings.len(), 5);
+        assert!(saved
+            .buildings
+            .iter()
+            .any(|building| building.fixed && building.requires == Some(LightColor::Orange)));
+
+        let text = saved.to_ron().unwrap();
+        let parsed = SaveGame::parse(&text).unwrap();
+        assert_eq!(parsed.to_ron().unwrap(), text);
+
+        // start from a different world, so nothing survives by accident
+        let mut loaded = app(shape);
+        loaded.insert_resource(Resources::new([(LightColor::Orange, 3)]));
+        load(&mut loaded, parsed).unwrap();
+        let reloaded = save(&mut loaded);
+
+        let pieces = |save: &SaveGame| {
+            save.buildings
+                .iter()
+                .map(|building| {
+                    (
+                        building.placeable.clone(),
+                        building.pivot,
+                        building.orientation,
+                        building.progress,
+                        building.fixed,
+                        building.requires,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pieces(&reloaded), pieces(&saved));
+        assert_eq!(
+            reloaded.resources.get(LightColor::Orange),
+            saved.resources.get(LightColor::Orange)
+        );
+        assert_eq!(reloaded.bounds, saved.bounds);
+        assert_eq!(reloaded.camera.translation, saved.camera.translation);
+        assert_eq!(reloaded.camera.rotation, saved.camera.rotation);
+        assert_eq!(reloaded.camera.yaw, FRAC_PI_3);
+        assert_eq!(reloaded.camera.scale, 0.75);
+        assert_eq!(reloaded.to_ron().unwrap(), text);
+    }
+
+    const SAVE_V1: &str = include_str!("../tests/fixtures/save_v1.ron");
+    const SAVE_V2: &str = include_str!("../tests/fixtures/save_v2.ron");
+
+    /// Whatever version a save was read from, writing it again gives a current one that reads
+    /// back the same.
+    fn assert_current(save: &SaveGame) {
+        assert_eq!(save.version, SAVE_VERSION);
+        let text = save.to_ron().unwrap();
+        assert_eq!(SaveGame::parse(&text).unwrap().to_ron().unwrap(), text);
+    }
+
+    #[test]
+    fn version_1_save_is_migrated() {
+        let save = SaveGame::parse(SAVE_V1).unwrap();
+        assert_current(&save);
+        assert_eq!(save.shape, GridShape::Hexagonal);
+        assert_eq!(save.bounds, GridBounds::default());
+
+        let wells: Vec<_> = save
+            .wells
+            .iter()
+            .map(|well| (well.position, well.color))
+            .collect();
+        assert_eq!(
+            wells,
+            [
+                (GridPosition { x: 3, y: 0 }, LightColor::Orange),
+                (GridPosition { x: -4, y: 2 }, LightColor::Blue),
+            ]
+        );
+        assert_eq!(save.obstacles[0].height, 1.5);
+
+        let buildings: Vec<_> = save
+            .buildings
+            .iter()
+            .map(|building| {
+                (
+                    building.placeable.0.as_str(),
+                    building.orientation,
+                    building.progress,
+                    building.fixed,
+                    building.requires,
+                )
+            })
+            .collect();
+        assert_eq!(
+            buildings,
+            [
+                ("collector", Orientation(0), None, false, None),
+                ("mirror", Orientation(2), None, false, None),
+                ("storage", Orientation(0), Some(2.5), false, None),
+            ]
+        );
+
+        assert_eq!(save.resources.get(LightColor::Orange), 42);
+        assert_eq!(save.resources.get(LightColor::Blue), 3);
+        assert_eq!(save.inventory.count(&Placeable("mirror".to_string())), None);
+        assert_eq!(save.camera.translation, Vec3::new(4.0, 12.0, -7.5));
+        assert_eq!(save.camera.scale, 0.75);
+    }
+
+    #[test]
+    fn version_2_save_is_migrated() {
+        let save = SaveGame::parse(SAVE_V2).unwrap();
+        assert_current(&save);
+        assert_eq!(save.shape, GridShape::Square);
+        assert_eq!(save.bounds, GridBounds::default());
+        assert_eq!(save.wells[0].color, LightColor::Orange);
+        assert_eq!(save.obstacles.len(), 2);
+        assert_eq!(save.buildings[1].pivot, GridPosition { x: -1, y: 4 });
+        assert_eq!(save.buildings[1].orientation, Orientation(3));
+        assert_eq!(save.buildings[1].progress, Some(1.0));
+        assert!(save.buildings.iter().all(|building| !building.fixed));
+        assert_eq!(save.resources.get(LightColor::Orange), 17);
+        assert_eq!(save.inventory.count(&Placeable("wall".to_string())), None);
+        assert_eq!(save.camera.scale, 1.25);
+    }
+
+    #[test]
+    fn unreadable_versions_are_refused() {
+        let newer = SAVE_V2.replace("version: 2", "version: 99");
+        assert!(matches!(
+            SaveGame::parse(&newer),
+            Err(SaveError::Newer { version: 99 })
+        ));
+        let unknown = SAVE_V2.replace("version: 2", "version: 0");
+        assert!(matches!(
+            SaveGame::parse(&unknown),
+            Err(SaveError::Unknown { version: 0 })
+        ));
+    }
+
+    #[test]
+    fn square_world_survives_saving_and_loading() {
+        round_trip(GridShape::Square);
+    }
+
+    #[test]
+    fn hexagonal_world_survives_saving_and_loading() {
+        round_trip(GridShape::Hexagonal);
+    }
+
+    #[test]
+    fn save_from_another_grid_is_refused() {
+        let mut square = played_world(GridShape::Square);
+        let saved = save(&mut square);
+        let mut hexagonal = app(GridShape::Hexagonal);
+        assert!(matches!(
+            load(&mut hexagonal, saved),
+            Err(SaveError::Shape { .. })
+        ));
+    }
+}
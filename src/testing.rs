@@ -0,0 +1,39 @@
+//! Headless apps for tests that run the game's systems without a window or renderer.
+
+use bevy::prelude::*;
+
+use crate::{
+    building::BuildingRegistry,
+    construction::{ConstructionQueue, ConstructionSettings},
+    economy::Resources,
+    grid::{GridBounds, GridMap, GridSettings, GridShape},
+    history::{apply_build_commands, BuildCommand, History},
+    hotbar::StatusMessage,
+    laser::LaserUpdateEvent,
+    level::Inventory,
+    LightColor,
+};
+
+/// An app holding everything `Builder` needs: the building definitions from `assets/buildings`,
+/// a map of the default size, an unlimited inventory and more orange light than any test
+/// spends. Build commands sent to it are applied on every update.
+pub fn build_app(shape: GridShape) -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+        .init_asset::<Mesh>()
+        .init_asset::<StandardMaterial>()
+        .init_asset::<Scene>()
+        .insert_resource(GridSettings { shape, ..default() })
+        .insert_resource(GridMap::new(GridBounds::default()))
+        .insert_resource(BuildingRegistry::from_assets())
+        .insert_resource(Resources::new([(LightColor::Orange, 1_000_000)]))
+        .init_resource::<Inventory>()
+        .init_resource::<ConstructionSettings>()
+        .init_resource::<ConstructionQueue>()
+        .init_resource::<History>()
+        .add_event::<BuildCommand>()
+        .add_event::<LaserUpdateEvent>()
+        .add_event::<StatusMessage>()
+        .add_systems(Update, apply_build_commands);
+    app
+}
@@ -0,0 +1,488 @@
+use bevy::{ecs::system::EntityCommands, prelude::*, utils::HashMap};
+
+use crate::interpolate::{Easing, Interpolate};
+
+pub struct TweenPlugin;
+
+impl Plugin for TweenPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Easings>();
+
+        app.add_systems(Update, advance_animations);
+    }
+}
+
+/// Easing used by tweens that do not name one.
+pub const DEFAULT_EASING: &str = "ease_out";
+
+/// Easing curves by name, so tweens can share them and designers can tune them in one place.
+/// Unknown names fall back to linear.
+#[derive(Resource, Clone, Debug)]
+pub struct Easings(HashMap<String, Easing>);
+
+impl Default for Easings {
+    fn default() -> Self {
+        Self(HashMap::from_iter(
+            [
+                ("linear", Easing::Linear),
+                (
+                    "ease_in",
+                    Easing::CubicBezier(Vec2::new(0.42, 0.0), Vec2::new(1.0, 1.0)),
+                ),
+                (
+                    "ease_out",
+                    Easing::CubicBezier(Vec2::new(0.0, 0.0), Vec2::new(0.58, 1.0)),
+                ),
+                (
+                    "ease_in_out",
+                    Easing::CubicBezier(Vec2::new(0.42, 0.0), Vec2::new(0.58, 1.0)),
+                ),
+                // quick start that settles gently, for buildings popping into place
+                (
+                    "snappy",
+                    Easing::CubicBezier(Vec2::new(0.2, 0.75), Vec2::new(0.68, 1.0)),
+                ),
+            ]
+            .map(|(name, easing)| (name.to_string(), easing)),
+        ))
+    }
+}
+
+impl Easings {
+    pub fn get(&self, name: &str) -> Easing {
+        self.0.get(name).copied().unwrap_or(Easing::Linear)
+    }
+}
+
+/// Moves a `Transform` towards the given targets over `duration` seconds. The start is recorded
+/// on the first update, so the motion only depends on the time passed, not on the frame rate.
+/// Parts without a target are left alone for other systems to drive.
+#[derive(Clone, Debug)]
+pub struct Tween {
+    pub translation: Option<Vec3>,
+    pub rotation: Option<Quat>,
+    pub scale: Option<Vec3>,
+    pub duration: f32,
+    pub easing: String,
+    elapsed: f32,
+    start: Option<Transform>,
+}
+
+impl Tween {
+    pub fn new(duration: f32) -> Self {
+        Self {
+            translation: None,
+            rotation: None,
+            scale: None,
+            duration,
+            easing: DEFAULT_EASING.to_string(),
+            elapsed: 0.0,
+            start: None,
+        }
+    }
+
+    pub fn translation(mut self, translation: Vec3) -> Self {
+        self.translation = Some(translation);
+        self
+    }
+
+    pub fn rotation(mut self, rotation: Quat) -> Self {
+        self.rotation = Some(rotation);
+        self
+    }
+
+    pub fn scale(mut self, scale: Vec3) -> Self {
+        self.scale = Some(scale);
+        self
+    }
+
+    pub fn easing(mut self, easing: impl Into<String>) -> Self {
+        self.easing = easing.into();
+        self
+    }
+
+    pub fn finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+
+    /// Moves the tween on by `delta` seconds and applies it to `transform`. Returns the time
+    /// left over after it finished.
+    pub fn advance(&mut self, transform: &mut Transform, delta: f32, easing: Easing) -> f32 {
+        let start = *self.start.get_or_insert(*transform);
+        self.elapsed += delta;
+        // land exactly on the target, whatever the curve's precision
+        let t = if self.finished() {
+            1.0
+        } else {
+            easing.ease(self.elapsed / self.duration)
+        };
+
+        if let Some(target) = self.translation {
+            transform.translation = start.translation.interpolate(target, t);
+        }
+        if let Some(target) = self.rotation {
+            transform.rotation = start.rotation.interpolate(target, t);
+        }
+        if let Some(target) = self.scale {
+            transform.scale = start.scale.interpolate(target, t);
+        }
+        (self.elapsed - self.duration).max(0.0)
+    }
+
+    /// Makes the tween start over from wherever the transform is next time.
+    fn reset(&mut self) {
+        self.elapsed = 0.0;
+        self.start = None;
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Step {
+    Tween(Tween),
+    Delay { duration: f32, elapsed: f32 },
+}
+
+/// What happens once an animation has played to the end.
+pub enum AnimationAction {
+    /// Despawns the entity and its children.
+    Despawn,
+    /// Changes the entity, e.g. inserts or removes components.
+    Modify(Box<dyn FnOnce(&mut EntityCommands) + Send + Sync>),
+    /// Sends an event built from the entity.
+    Send(Box<dyn FnOnce(Entity, &mut Commands) + Send + Sync>),
+}
+
+impl AnimationAction {
+    pub fn modify(change: impl FnOnce(&mut EntityCommands) + Send + Sync + 'static) -> Self {
+        AnimationAction::Modify(Box::new(change))
+    }
+
+    pub fn send<E: Event>(event: impl FnOnce(Entity) -> E + Send + Sync + 'static) -> Self {
+        AnimationAction::Send(Box::new(move |entity, commands| {
+            let event = event(entity);
+            commands.add(move |world: &mut World| {
+                world.send_event(event);
+            });
+        }))
+    }
+
+    fn run(self, entity: Entity, commands: &mut Commands) {
+        match self {
+            AnimationAction::Despawn => commands.entity(entity).despawn_recursive(),
+            AnimationAction::Modify(change) => change(&mut commands.entity(entity)),
+            AnimationAction::Send(send) => send(entity, commands),
+        }
+    }
+}
+
+/// Tweens and delays played one after the other, optionally on repeat. Its completion actions
+/// run when the last step ends; an animation that loops or gets replaced never completes.
+#[derive(Component, Default)]
+pub struct Animation {
+    steps: Vec<Step>,
+    current: usize,
+    looping: bool,
+    on_complete: Vec<AnimationAction>,
+}
+
+impl From<Tween> for Animation {
+    fn from(tween: Tween) -> Self {
+        Self::default().then(tween)
+    }
+}
+
+impl Animation {
+    pub fn then(mut self, tween: Tween) -> Self {
+        self.steps.push(Step::Tween(tween));
+        self
+    }
+
+    /// Holds still for `seconds` before the next step.
+    pub fn wait(mut self, seconds: f32) -> Self {
+        self.steps.push(Step::Delay {
+            duration: seconds,
+            elapsed: 0.0,
+        });
+        self
+    }
+
+    /// Starts over from the first step after the last one, forever.
+    pub fn looping(mut self) -> Self {
+        self.looping = true;
+        self
+    }
+
+    pub fn on_complete(mut self, action: AnimationAction) -> Self {
+        self.on_complete.push(action);
+        self
+    }
+
+    /// Takes the completion actions, e.g. to hand them on to an animation replacing this one.
+    pub fn take_actions(&mut self) -> Vec<AnimationAction> {
+        std::mem::take(&mut self.on_complete)
+    }
+
+    /// Plays `delta` seconds, carrying the time left over by a finished step into the next one.
+    /// Returns whether the animation has ended.
+    fn advance(&mut self, transform: &mut Transform, mut delta: f32, easings: &Easings) -> bool {
+        loop {
+            let Some(step) = self.steps.get_mut(self.current) else {
+                // an empty loop would never get anywhere
+                if !self.looping || self.steps.is_empty() || delta <= 0.0 {
+                    return !self.looping;
+                }
+                self.restart();
+                continue;
+            };
+            let finished = match step {
+                Step::Tween(tween) => {
+                    delta = tween.advance(transform, delta, easings.get(&tween.easing));
+                    tween.finished()
+                }
+                Step::Delay { duration, elapsed } => {
+                    *elapsed += delta;
+                    delta = (*elapsed - *duration).max(0.0);
+                    *elapsed >= *duration
+                }
+            };
+            if !finished {
+                return false;
+            }
+            self.current += 1;
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current = 0;
+        for step in &mut self.steps {
+            match step {
+                Step::Tween(tween) => tween.reset(),
+                Step::Delay { elapsed, .. } => *elapsed = 0.0,
+            }
+        }
+    }
+}
+
+fn advance_animations(
+    mut commands: Commands,
+    time: Res<Time>,
+    easings: Res<Easings>,
+    mut query: Query<(Entity, &mut Animation, &mut Transform)>,
+) {
+    for (entity, mut animation, mut transform) in query.iter_mut() {
+        if animation.advance(&mut transform, time.delta_seconds(), &easings) {
+            commands.entity(entity).remove::<Animation>();
+            for action in animation.take_actions() {
+                action.run(entity, &mut commands);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+
+    /// Every update after the first takes this long. The first one always takes no time.
+    const FRAME: f32 = 0.25;
+
+    #[derive(Component)]
+    struct Done;
+
+    #[derive(Event)]
+    struct Finished(Entity);
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, TweenPlugin))
+            .add_event::<Finished>()
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+                FRAME,
+            )));
+        // the first update starts the clock
+        app.update();
+        app
+    }
+
+    fn spawn(app: &mut App, animation: Animation) -> Entity {
+        app.world
+            .spawn((TransformBundle::default(), animation))
+            .id()
+    }
+
+    fn x(app: &App, entity: Entity) -> f32 {
+        app.world.get::<Transform>(entity).unwrap().translation.x
+    }
+
+    fn to_x(x: f32, duration: f32) -> Tween {
+        Tween::new(duration)
+            .translation(Vec3::new(x, 0.0, 0.0))
+            .easing("linear")
+    }
+
+    #[test]
+    fn tween_runs_from_start_to_target() {
+        let mut app = app();
+        let entity = spawn(&mut app, Animation::from(to_x(4.0, 1.0)));
+        app.world.get_mut::<Transform>(entity).unwrap().scale = Vec3::splat(2.0);
+
+        app.update();
+        assert_eq!(x(&app, entity), 1.0);
+        app.update();
+        assert_eq!(x(&app, entity), 2.0);
+        app.update();
+        app.update();
+        assert_eq!(x(&app, entity), 4.0);
+        assert!(app.world.get::<Animation>(entity).is_none());
+        // parts without a target are left alone
+        assert_eq!(
+            app.world.get::<Transform>(entity).unwrap().scale,
+            Vec3::splat(2.0)
+        );
+    }
+
+    #[test]
+    fn tween_starts_where_the_transform_is() {
+        let mut app = app();
+        let entity = app
+            .world
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(-2.0, 0.0, 0.0)),
+                Animation::from(to_x(2.0, 1.0)),
+            ))
+            .id();
+        app.update();
+        assert_eq!(x(&app, entity), -1.0);
+    }
+
+    #[test]
+    fn easing_shapes_the_motion_but_not_the_ends() {
+        let mut app = app();
+        let entity = spawn(&mut app, Animation::from(to_x(1.0, 1.0).easing("ease_in")));
+        app.update();
+        let early = x(&app, entity);
+        assert!(early > 0.0 && early < FRAME, "{}", early);
+        for _ in 0..3 {
+            app.update();
+        }
+        assert_eq!(x(&app, entity), 1.0);
+    }
+
+    #[test]
+    fn delays_hold_still_and_pass_on_leftover_time() {
+        let mut app = app();
+        let entity = spawn(
+            &mut app,
+            Animation::default()
+                .wait(0.5)
+                .then(to_x(2.0, 0.5))
+                .then(to_x(0.0, 0.25)),
+        );
+        app.update();
+        app.update();
+        assert_eq!(x(&app, entity), 0.0);
+        app.update();
+        assert_eq!(x(&app, entity), 1.0);
+        app.update();
+        assert_eq!(x(&app, entity), 2.0);
+        app.update();
+        assert_eq!(x(&app, entity), 0.0);
+        assert!(app.world.get::<Animation>(entity).is_none());
+
+        // a frame longer than a step carries on into the next
+        let entity = spawn(
+            &mut app,
+            Animation::default().wait(0.125).then(to_x(1.0, 0.5)),
+        );
+        app.update();
+        assert_eq!(x(&app, entity), 0.25);
+    }
+
+    #[test]
+    fn looping_starts_over_and_never_completes() {
+        let mut app = app();
+        let entity = spawn(
+            &mut app,
+            Animation::default()
+                .then(to_x(1.0, 0.5))
+                .then(to_x(0.0, 0.5))
+                .looping()
+                .on_complete(AnimationAction::modify(|entity| {
+                    entity.insert(Done);
+                })),
+        );
+        let mut positions = Vec::new();
+        for _ in 0..10 {
+            app.update();
+            positions.push(x(&app, entity));
+        }
+        assert_eq!(
+            positions,
+            [0.5, 1.0, 0.5, 0.0, 0.5, 1.0, 0.5, 0.0, 0.5, 1.0]
+        );
+        assert!(app.world.get::<Animation>(entity).is_some());
+        assert!(app.world.get::<Done>(entity).is_none());
+    }
+
+    #[test]
+    fn completion_actions_run_once_the_last_step_ends() {
+        let mut app = app();
+        let modified = spawn(
+            &mut app,
+            Animation::from(to_x(1.0, 0.5))
+                .on_complete(AnimationAction::modify(|entity| {
+                    entity.insert(Done);
+                }))
+                .on_complete(AnimationAction::send(Finished)),
+        );
+        let despawned = spawn(
+            &mut app,
+            Animation::default()
+                .wait(0.25)
+                .on_complete(AnimationAction::Despawn),
+        );
+
+        app.update();
+        assert!(app.world.get::<Done>(modified).is_none());
+        assert!(app.world.get_entity(despawned).is_none());
+
+        app.update();
+        assert!(app.world.get::<Done>(modified).is_some());
+        let finished: Vec<Entity> = app
+            .world
+            .resource_mut::<Events<Finished>>()
+            .drain()
+            .map(|Finished(entity)| entity)
+            .collect();
+        assert_eq!(finished, vec![modified]);
+    }
+
+    #[test]
+    fn replaced_animation_hands_on_its_actions() {
+        let mut app = app();
+        let entity = spawn(
+            &mut app,
+            Animation::from(to_x(1.0, 1.0)).on_complete(AnimationAction::modify(|entity| {
+                entity.insert(Done);
+            })),
+        );
+        app.update();
+        let mut replacement = Animation::from(to_x(-1.0, 0.25));
+        for action in app
+            .world
+            .get_mut::<Animation>(entity)
+            .unwrap()
+            .take_actions()
+        {
+            replacement = replacement.on_complete(action);
+        }
+        app.world.entity_mut(entity).insert(replacement);
+        app.update();
+        assert_eq!(x(&app, entity), -1.0);
+        assert!(app.world.get::<Done>(entity).is_some());
+    }
+}
@@ -0,0 +1,366 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    grid::{GridBounds, GridLayer, GridMap, GridSettings},
+    laser::IntersectorType,
+    ColorWell, Footprint, GridPosition, LightColor, Orientation,
+};
+
+/// Well colours from most to least common. Rarer colours show up further from spawn.
+const RARITY: [LightColor; 6] = [
+    LightColor::Orange,
+    LightColor::Red,
+    LightColor::Yellow,
+    LightColor::Green,
+    LightColor::Blue,
+    LightColor::Violet,
+];
+
+/// Parameters of the world generator. The same settings always produce the same world.
+#[derive(Resource, Clone, Debug)]
+pub struct WorldGenSettings {
+    pub seed: u64,
+    /// Extent of the map. Nothing is generated outside it, nor built there later.
+    pub bounds: GridBounds,
+    /// Distance in cells from spawn that wells and obstacles are scattered within.
+    pub radius: i32,
+    pub well_count: usize,
+    /// Wells are at least this many cells apart.
+    pub min_well_distance: f32,
+    pub obstacle_clusters: usize,
+    pub max_cluster_size: usize,
+    /// Cells around spawn kept free of obstacles.
+    pub spawn_clearing: f32,
+}
+
+impl Default for WorldGenSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            bounds: GridBounds::default(),
+            radius: 40,
+            well_count: 24,
+            min_well_distance: 6.0,
+            obstacle_clusters: 30,
+            max_cluster_size: 10,
+            spawn_clearing: 4.0,
+        }
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct GeneratedWorld {
+    pub wells: Vec<(GridPosition, LightColor)>,
+    /// Obstacle cells and the height of the rock on each.
+    pub obstacles: Vec<(GridPosition, f32)>,
+}
+
+/// Stream of random numbers the obstacle heights are drawn from, apart from the layout's.
+const HEIGHT_STREAM: u64 = 1;
+
+/// Seed for a stream of random numbers of its own, so drawing more or fewer from one stream
+/// never shifts what another one draws.
+fn sub_seed(seed: u64, stream: u64) -> u64 {
+    // SplitMix64's finaliser, which spreads neighbouring inputs far apart
+    let mut z = seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Scatters wells and obstacle clusters around cell (0, 0). There is always an orange well at
+/// spawn, so the first collector has somewhere to go.
+pub fn generate(settings: &WorldGenSettings, grid: &GridSettings) -> GeneratedWorld {
+    let bounds = settings.bounds;
+    let mut rng = StdRng::seed_from_u64(settings.seed);
+    let spawn = GridPosition::default();
+    let distance = |a: GridPosition, b: GridPosition| {
+        grid.grid_to_world(a).distance(grid.grid_to_world(b)) / grid.cell_size
+    };
+    let random_cell = |rng: &mut StdRng| {
+        let cell = GridPosition {
+            x: rng.gen_range(-settings.radius..=settings.radius),
+            y: rng.gen_range(-settings.radius..=settings.radius),
+        };
+        (bounds.contains(cell) && distance(spawn, cell) <= settings.radius as f32).then_some(cell)
+    };
+
+    let mut world = GeneratedWorld {
+        wells: vec![(spawn, RARITY[0])],
+        ..default()
+    };
+    // rejection sampling, giving up once the area is too crowded to fit more wells
+    for _ in 0..settings.well_count * 50 {
+        if world.wells.len() >= settings.well_count {
+            break;
+        }
+        let Some(cell) = random_cell(&mut rng) else {
+            continue;
+        };
+        if world
+            .wells
+            .iter()
+            .any(|(well, _)| distance(*well, cell) < settings.min_well_distance)
+        {
+            continue;
+        }
+        // the further out, the rarer the colours that can show up
+        let reach = distance(spawn, cell) / settings.radius as f32 * RARITY.len() as f32;
+        let rarity = rng.gen::<f32>() * reach;
+        let color = RARITY[(rarity as usize).min(RARITY.len() - 1)];
+        world.wells.push((cell, color));
+    }
+
+    let shape = grid.shape;
+    let mut obstacles: Vec<GridPosition> = Vec::new();
+    let free = |obstacles: &[GridPosition], cell: GridPosition| {
+        bounds.contains(cell)
+            && distance(spawn, cell) > settings.spawn_clearing
+            && !obstacles.contains(&cell)
+            // leave room around wells to build on them and around them
+            && world.wells.iter().all(|(well, _)| distance(*well, cell) >= 2.0)
+    };
+    for _ in 0..settings.obstacle_clusters {
+        let Some(seed_cell) = random_cell(&mut rng).filter(|cell| free(&obstacles, *cell)) else {
+            continue;
+        };
+        let size = rng.gen_range(1..=settings.max_cluster_size);
+        let first = obstacles.len();
+        obstacles.push(seed_cell);
+        // grow the cluster by stepping off a random cell of it
+        for _ in 0..size * 4 {
+            if obstacles.len() - first >= size {
+                break;
+            }
+            let from = obstacles[rng.gen_range(first..obstacles.len())];
+            let direction = shape
+                .directions()
+                .nth(rng.gen_range(0..shape.direction_count() as usize))
+                .unwrap();
+            let cell = from.offset(shape.offset(direction));
+            if free(&obstacles, cell) {
+                obstacles.push(cell);
+            }
+        }
+    }
+
+    let mut heights = StdRng::seed_from_u64(sub_seed(settings.seed, HEIGHT_STREAM));
+    world.obstacles = obstacles
+        .into_iter()
+        .map(|cell| (cell, heights.gen_range(0.3..0.9)))
+        .collect();
+    world
+}
+
+/// Rock that blocks beams and building. Not a `Building`, so it cannot be demolished.
+#[derive(Component)]
+pub struct Obstacle;
+
+/// Generates the world from the `WorldGenSettings` resource and spawns it.
+pub fn spawn_world(
+    mut commands: Commands,
+    mut grid_map: ResMut<GridMap>,
+    settings: Res<GridSettings>,
+    worldgen: Res<WorldGenSettings>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    info!("Generating world from seed {}", worldgen.seed);
+    *grid_map = GridMap::new(worldgen.bounds);
+    let world = generate(&worldgen, &settings);
+
+    for (position, color) in world.wells {
+        spawn_color_well(
+            &mut commands,
+            &mut grid_map,
+            &settings,
+            position,
+            color,
+            &mut meshes,
+            &mut materials,
+        );
+    }
+
+    let (mesh, material) = obstacle_assets(&mut meshes, &mut materials);
+    for (position, height) in world.obstacles {
+        spawn_obstacle(
+            &mut commands,
+            &mut grid_map,
+            &settings,
+            position,
+            height,
+            mesh.clone(),
+            material.clone(),
+        );
+    }
+}
+
+/// Mesh and material every obstacle shares. Heights come from scaling the mesh.
+pub fn obstacle_assets(
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) -> (Handle<Mesh>, Handle<StandardMaterial>) {
+    (
+        meshes.add(Cuboid::new(0.9, 1.0, 0.9)),
+        materials.add(StandardMaterial {
+            base_color: Color::rgb(0.12, 0.11, 0.1),
+            perceptual_roughness: 0.95,
+            ..default()
+        }),
+    )
+}
+
+pub fn spawn_obstacle(
+    commands: &mut Commands,
+    grid_map: &mut GridMap,
+    settings: &GridSettings,
+    position: GridPosition,
+    height: f32,
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+) -> Entity {
+    let world_position = settings.grid_to_world(position);
+    let entity = commands
+        .spawn((
+            PbrBundle {
+                mesh,
+                material,
+                transform: Transform::from_xyz(world_position.x, height / 2.0, world_position.y)
+                    .with_scale(Vec3::new(1.0, height, 1.0)),
+                ..default()
+            },
+            position,
+            Orientation::default(),
+            Footprint::default(),
+            IntersectorType::Blocker,
+            Obstacle,
+            Name::new("Obstacle"),
+        ))
+        .id();
+    grid_map.set(GridLayer::Build, position, entity).unwrap();
+    entity
+}
+
+pub fn spawn_color_well(
+    commands: &mut Commands,
+    grid_map: &mut GridMap,
+    settings: &GridSettings,
+    position: GridPosition,
+    color: LightColor,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) -> Entity {
+    let world = settings.grid_to_world(position);
+    let render_color = color.color();
+    let entity = commands
+        .spawn((
+            PbrBundle {
+                mesh: meshes.add(Cuboid::new(1.0, 1.0, 1.0)),
+                material: materials.add(StandardMaterial {
+                    base_color: render_color,
+                    reflectance: 0.5,
+                    emissive: render_color * 10.0,
+                    ..default()
+                }),
+                transform: Transform::from_xyz(world.x, -0.49, world.y),
+                ..default()
+            },
+            position,
+            ColorWell { color },
+            Name::new("Color Well"),
+        ))
+        .id();
+    grid_map.set(GridLayer::Ground, position, entity).unwrap();
+    entity
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::GridShape;
+
+    fn settings(seed: u64) -> WorldGenSettings {
+        WorldGenSettings { seed, ..default() }
+    }
+
+    fn grid_settings() -> [GridSettings; 2] {
+        [GridShape::Square, GridShape::Hexagonal].map(|shape| GridSettings { shape, ..default() })
+    }
+
+    #[test]
+    fn same_seed_same_world() {
+        for grid in grid_settings() {
+            for seed in [0, 1, 42, u64::MAX] {
+                let world = generate(&settings(seed), &grid);
+                assert_eq!(world, generate(&settings(seed), &grid));
+                assert!(world.wells.len() > 1 && !world.obstacles.is_empty());
+            }
+            assert_ne!(generate(&settings(1), &grid), generate(&settings(2), &grid));
+        }
+    }
+
+    #[test]
+    fn wells_keep_their_distance() {
+        for grid in grid_settings() {
+            for seed in 0..20 {
+                let settings = settings(seed);
+                let world = generate(&settings, &grid);
+                for (index, (a, _)) in world.wells.iter().enumerate() {
+                    for (b, _) in &world.wells[index + 1..] {
+                        let distance = grid.grid_to_world(*a).distance(grid.grid_to_world(*b));
+                        assert!(
+                            distance >= settings.min_well_distance,
+                            "wells {:?} and {:?} are {} apart",
+                            a,
+                            b,
+                            distance
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn everything_stays_inside_the_bounds_and_off_the_wells() {
+        let settings = WorldGenSettings {
+            bounds: GridBounds::centred(30, 30),
+            ..settings(7)
+        };
+        for grid in grid_settings() {
+            let world = generate(&settings, &grid);
+            let mut cells: Vec<GridPosition> = world
+                .wells
+                .iter()
+                .map(|(cell, _)| *cell)
+                .chain(world.obstacles.iter().map(|(cell, _)| *cell))
+                .collect();
+            assert!(cells.iter().all(|cell| settings.bounds.contains(*cell)));
+            let count = cells.len();
+            cells.sort_by_key(|cell| (cell.x, cell.y));
+            cells.dedup();
+            assert_eq!(cells.len(), count);
+        }
+    }
+
+    #[test]
+    fn heights_do_not_depend_on_the_layout() {
+        let grid = GridSettings::default();
+        let few = generate(
+            &WorldGenSettings {
+                obstacle_clusters: 3,
+                ..settings(5)
+            },
+            &grid,
+        );
+        let many = generate(&settings(5), &grid);
+        let heights = |world: &GeneratedWorld| -> Vec<f32> {
+            world.obstacles.iter().map(|(_, height)| *height).collect()
+        };
+        let (few, many) = (heights(&few), heights(&many));
+        assert!(many.len() > few.len());
+        assert_eq!(few, many[..few.len()]);
+        assert!(many.iter().all(|height| (0.3..0.9).contains(height)));
+    }
+}